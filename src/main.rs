@@ -1,10 +1,21 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use openmw_config::OpenMWConfiguration;
+use rayon::prelude::*;
 use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
     fs::{self, hard_link, metadata},
-    io::{self, Result, Write},
-    path::PathBuf,
+    io::{self, IsTerminal, Result, Write},
+    path::{Path, PathBuf},
+};
+use vfstool_lib::{
+    DisplayTree, IgnoreRules, PathDisplay, ScanOptions, SerializeLayout, SerializeType, VfsFile,
+    archives::{self, PackCompression, PackFormat},
+    directory_node::DirectoryNode,
+    glob_to_regex, long_path, normalize_path, normalize_str,
+    vfs::{ArchiveStats, ShadowedFile, TreeStyle, VFS},
 };
-use vfstool_lib::{SerializeType, normalize_path, vfs::VFS};
 
 #[cfg(unix)]
 use std::os::unix::fs::symlink as soft_link;
@@ -15,6 +26,13 @@ use std::os::windows::fs::symlink_file as soft_link;
 pub enum VFSToolExitCode {
     FindFailed = 1,
     FileNotInLooseDirectories = 2,
+    ExtractCollision = 3,
+    DoctorFoundProblems = 4,
+    CollapseIntoSource = 5,
+    ManifestFilesMissing = 6,
+    TargetExists = 7,
+    UnreadableArchive = 8,
+    BadIgnoreFile = 9,
     BadRegex = 254,
     FailedToLoadOpenMWConfig = 255,
 }
@@ -24,6 +42,13 @@ impl From<VFSToolExitCode> for i32 {
         match value {
             VFSToolExitCode::FindFailed => 1,
             VFSToolExitCode::FileNotInLooseDirectories => 2,
+            VFSToolExitCode::ExtractCollision => 3,
+            VFSToolExitCode::DoctorFoundProblems => 4,
+            VFSToolExitCode::CollapseIntoSource => 5,
+            VFSToolExitCode::ManifestFilesMissing => 6,
+            VFSToolExitCode::TargetExists => 7,
+            VFSToolExitCode::UnreadableArchive => 8,
+            VFSToolExitCode::BadIgnoreFile => 9,
             VFSToolExitCode::BadRegex => 254,
             VFSToolExitCode::FailedToLoadOpenMWConfig => 255,
         }
@@ -31,29 +56,146 @@ impl From<VFSToolExitCode> for i32 {
 }
 
 mod print {
+    use std::{
+        io::IsTerminal,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
     pub const RED: &str = "\x1b[31m";
     pub const GREEN: &str = "\x1b[32m";
+    pub const YELLOW: &str = "\x1b[33m";
     pub const BLUE: &str = "\x1b[34m";
     pub const RESET: &str = "\x1b[0m";
 
-    pub const fn err_prefix() -> &'static str {
-        concat!("\x1b[31m", "[ ERROR ]", "\x1b[0m", ": ")
+    static ENABLED: AtomicBool = AtomicBool::new(true);
+    static QUIET: AtomicBool = AtomicBool::new(false);
+
+    /// Decides whether messages are colored. In `Auto` mode, color is only used when both stdout
+    /// and stderr are terminals, since helpers don't know which stream they're printed to.
+    pub fn init(choice: super::ColorChoice) {
+        let enabled = match choice {
+            super::ColorChoice::Always => true,
+            super::ColorChoice::Never => false,
+            super::ColorChoice::Auto => {
+                std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+            }
+        };
+
+        ENABLED.store(enabled, Ordering::Relaxed);
+    }
+
+    fn enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    /// Hides the per-file lines printed through `progress!`
+    pub fn set_quiet(quiet: bool) {
+        QUIET.store(quiet, Ordering::Relaxed);
+    }
+
+    pub fn quiet() -> bool {
+        QUIET.load(Ordering::Relaxed)
+    }
+
+    fn paint<S: std::fmt::Display>(color: &str, input: S) -> String {
+        if enabled() {
+            format!("{color}{input}{RESET}")
+        } else {
+            input.to_string()
+        }
+    }
+
+    pub fn err_prefix() -> &'static str {
+        if enabled() {
+            concat!("\x1b[31m", "[ ERROR ]", "\x1b[0m", ": ")
+        } else {
+            "[ ERROR ]: "
+        }
+    }
+
+    pub fn success_prefix() -> &'static str {
+        if enabled() {
+            concat!("\x1b[32m", "[ SUCCESS ]", "\x1b[0m", ": ")
+        } else {
+            "[ SUCCESS ]: "
+        }
     }
 
-    pub const fn success_prefix() -> &'static str {
-        concat!("\x1b[32m", "[ SUCCESS ]", "\x1b[0m", ": ")
+    pub fn warn_prefix() -> &'static str {
+        if enabled() {
+            concat!("\x1b[33m", "[ WARNING ]", "\x1b[0m", ": ")
+        } else {
+            "[ WARNING ]: "
+        }
     }
 
     pub fn red<S: std::fmt::Display>(input: S) -> String {
-        format!("{RED}{input}{RESET}")
+        paint(RED, input)
     }
 
     pub fn blue<S: std::fmt::Display>(input: S) -> String {
-        format!("{BLUE}{input}{RESET}")
+        paint(BLUE, input)
     }
 
     pub fn green<S: std::fmt::Display>(input: S) -> String {
-        format!("{GREEN}{input}{RESET}")
+        paint(GREEN, input)
+    }
+
+    pub fn yellow<S: std::fmt::Display>(input: S) -> String {
+        paint(YELLOW, input)
+    }
+}
+
+/// Prints a line about a single file, unless `--quiet` is used
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !print::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Tally of what happened to each file `collapse` or `extract` handled, printed once they finish
+struct Summary {
+    started: std::time::Instant,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl Summary {
+    fn start() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            succeeded: 0,
+            failed: 0,
+            skipped: 0,
+        }
+    }
+
+    fn record(&mut self, succeeded: bool) {
+        if succeeded {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    fn print(&self) {
+        let failed = format!("{} failed", self.failed);
+
+        println!(
+            "Processed {} files in {:.2?}: {}, {}, {}",
+            self.succeeded + self.failed + self.skipped,
+            self.started.elapsed(),
+            print::green(format!("{} succeeded", self.succeeded)),
+            if self.failed > 0 {
+                print::red(failed)
+            } else {
+                failed
+            },
+            print::yellow(format!("{} skipped", self.skipped)),
+        );
     }
 }
 
@@ -78,10 +220,106 @@ struct Cli {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
-    /// Whether or not to use relative paths in output
+    /// Inspect a single BSA or BA2 archive instead of a whole installation. The VFS holds only
+    /// this archive's contents, so no openmw.cfg is needed, and options for loading one, like
+    /// `--no-archives`, are ignored. `doctor` and `remaining` work on data directories, so they
+    /// can't be used with it
+    #[arg(long, value_name = "FILE", conflicts_with = "config")]
+    archive: Option<PathBuf>,
+
+    /// Whether or not to use relative paths in output. Same as `--paths relative`
     #[arg(short = 'r', long)]
     use_relative: bool,
 
+    /// How to show paths in output: `relative` VFS paths with archived files grouped under their
+    /// archive, `absolute` paths on disk, or `both`, which shows each file at its VFS path along
+    /// with the resolved source it's read from
+    #[arg(
+        long,
+        value_enum,
+        default_value = "absolute",
+        conflicts_with = "use_relative"
+    )]
+    paths: PathMode,
+
+    /// Draw `--format tree` output with plain ASCII instead of box-drawing characters
+    #[arg(long, global = true)]
+    ascii: bool,
+
+    /// Custom prefix drawn before each directory in `--format tree` output
+    #[arg(long, global = true)]
+    dir_glyph: Option<String>,
+
+    /// Custom prefix drawn before each file in `--format tree` output
+    #[arg(long, global = true)]
+    file_glyph: Option<String>,
+
+    /// Write JSON and TOML output without pretty-printing, for piping into other programs.
+    /// Without it, both are pretty-printed
+    #[arg(long, global = true)]
+    compact: bool,
+
+    /// Write each serialized file as `{name, priority}`, where priority is the zero-based
+    /// load-order index of the archive or data directory it came from. Archives come first.
+    #[arg(long, global = true)]
+    with_priority: bool,
+
+    /// Run everything on a single thread, so messages logged while scanning come out in the same
+    /// order on every run. Listings and collapse are always written in sorted path order
+    #[arg(long, global = true)]
+    single_threaded: bool,
+
+    /// Leave out files which can't be stat'd or opened while building the VFS, logging each one
+    #[arg(long, global = true)]
+    skip_unreadable: bool,
+
+    /// Resolve symlinks and `..` in data directories and files while building the VFS, so one
+    /// directory reached through different paths counts as a single source
+    #[arg(long, global = true)]
+    resolve_symlinked_sources: bool,
+
+    /// Build the VFS from loose files only, without loading any archives
+    #[arg(long, global = true)]
+    no_archives: bool,
+
+    /// Also load the archive named after each content file, eg `MyMod.bsa` for `MyMod.esp`.
+    /// These come after every `fallback-archive=`, so they win over them
+    #[arg(long, global = true, conflicts_with = "no_archives")]
+    auto_archives: bool,
+
+    /// Leave the `.bsa` and `.ba2` files which were loaded as archives out of listings, so only
+    /// their contents are shown. `collapse` and `extract` still write the archive files
+    #[arg(long, global = true)]
+    hide_archive_files: bool,
+
+    /// Only look for loose files up to N directories deep in each data directory, where 1 only
+    /// takes files directly inside of it
+    #[arg(long, global = true, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Only take the files directly inside of each data directory. Same as `--max-depth 1`
+    #[arg(long, global = true, conflicts_with = "max_depth")]
+    no_recurse: bool,
+
+    /// Leave loose files matching the gitignore-style patterns in this file out of the VFS.
+    /// Defaults to `.vfsignore` next to openmw.cfg, when there is one
+    #[arg(long, global = true, value_name = "FILE")]
+    ignore_file: Option<PathBuf>,
+
+    /// Keep the placeholder entries of `archiveinvalidationinvalidated!.bsa` from replacing
+    /// files in the VFS. Pass `--ignore-invalidation=false` to load them like any other archive
+    #[arg(long, global = true, default_value_t = true, action = clap::ArgAction::Set)]
+    ignore_invalidation: bool,
+
+    /// When to color messages. `auto` colors them only when writing to a terminal
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    color: ColorChoice,
+
+    /// Leave out the line `collapse` and `extract` print for each file, keeping errors and the
+    /// final summary
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -93,7 +331,25 @@ enum Commands {
     /// filesystem inside of it. Skyrim support ;)
     Collapse {
         /// Target folder to collapse the VFS into
-        collapse_into: PathBuf,
+        #[arg(
+            required_unless_present = "into_archive",
+            conflicts_with = "into_archive"
+        )]
+        collapse_into: Option<PathBuf>,
+
+        /// Instead of a folder, pack every file in the VFS into a single new archive at this path
+        #[arg(long)]
+        into_archive: Option<PathBuf>,
+
+        /// Archive layout to write when using `--into-archive`
+        #[arg(long, value_enum, default_value = "tes3", requires = "into_archive")]
+        archive_format: ArchiveFormat,
+
+        /// How to compress files packed with `--into-archive`. By default, each file keeps the
+        /// compression it had in its source archive, and loose files are stored uncompressed.
+        /// TES3 archives can't be compressed.
+        #[arg(long, value_enum, requires = "into_archive")]
+        compression: Option<Compression>,
 
         /// If this is used, any case where hard linking failed or won't work (files in BSA
         /// archives), falls back to normal copying operations
@@ -107,14 +363,102 @@ enum Commands {
         /// Use symbolic instead of hardlinks, to allow cross-device links
         #[arg(short, long)]
         symbolic: bool,
+
+        /// Skip any file with this extension, eg `--exclude-extension esp`. May be repeated.
+        /// Files without an extension are only skipped by an empty one, ie `""`.
+        ///
+        /// `bsa` and `ba2` are always excluded when `--extract-archives` is used, since their
+        /// contents are extracted instead.
+        #[arg(long = "exclude-extension", value_name = "EXT")]
+        exclude_extensions: Vec<String>,
+
+        /// Only collapse files inside of this VFS directory, eg `--only meshes`. May be repeated
+        /// to collapse several. `--prune` then leaves files outside of them alone too.
+        #[arg(long, value_name = "PREFIX")]
+        only: Vec<PathBuf>,
+
+        /// Collapse even if the target folder is, or is inside of, one of the data directories.
+        /// This normally makes the VFS link files into itself, so only use it if you're sure.
+        #[arg(long)]
+        force: bool,
+
+        /// After collapsing, remove every file in the target folder which is no longer part of
+        /// the VFS, eg ones left behind by mods removed since the last collapse. Asks before
+        /// deleting anything, unless `--yes` is used. Refused if the target folder holds a data
+        /// directory or archive, even with `--force`.
+        #[arg(long, conflicts_with = "into_archive")]
+        prune: bool,
+
+        /// Only list the files `--prune` would remove, without removing them
+        #[arg(long, requires = "prune")]
+        dry_run: bool,
+
+        /// Prune without asking for confirmation first
+        #[arg(short, long, requires = "prune")]
+        yes: bool,
+
+        /// What to do with files which already exist in the target folder
+        #[arg(
+            long = "overwrite",
+            visible_alias = "overwrite-policy",
+            value_enum,
+            default_value = "overwrite"
+        )]
+        overwrite: OverwritePolicy,
+
+        /// How many more times to try linking or copying a file after a transient failure, eg a
+        /// sharing violation from antivirus or a network drive. Each retry waits twice as long as
+        /// the last, starting at 50ms
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        retries: u32,
+
+        /// Whether to name each written file by its lowercase VFS path, or as its source spells
+        /// it. Only the names of the links change, not the files they point to
+        #[arg(long, value_enum, default_value = "lowercase")]
+        name_case: NameCase,
     },
     /// Extract a given file from the VFS into a given directory
     Extract {
         /// Full relative path to a VFS file, eg meshes/xbase_anim.nif
+        ///
+        /// Globs such as `textures/mountains/*.dds` or `meshes/**/{a,b}.nif` extract every
+        /// matching file instead.
         source_file: PathBuf,
 
-        /// Directory to extract the file to
-        target_dir: PathBuf,
+        /// Directory to extract the file to. Not needed with `--stdout`
+        #[arg(required_unless_present = "stdout")]
+        target_dir: Option<PathBuf>,
+
+        /// Recreate each file's VFS directories inside of the target directory, instead of
+        /// placing every file directly inside of it
+        #[arg(short, long)]
+        preserve_tree: bool,
+
+        /// Write the file's contents to stdout instead of a directory, eg to pipe into `xxd`.
+        ///
+        /// Several glob matches are written back to back in sorted order, like `cat`.
+        #[arg(long, conflicts_with_all = ["target_dir", "preserve_tree"])]
+        stdout: bool,
+
+        /// What to do with files which already exist in the target directory
+        #[arg(
+            long = "overwrite",
+            visible_alias = "overwrite-policy",
+            value_enum,
+            default_value = "overwrite",
+            conflicts_with = "stdout"
+        )]
+        overwrite: OverwritePolicy,
+
+        /// Whether to name each extracted file by its lowercase VFS path, or as its source
+        /// spells it
+        #[arg(
+            long,
+            value_enum,
+            default_value = "original",
+            conflicts_with = "stdout"
+        )]
+        name_case: NameCase,
     },
     /// Given some VFS path, like `meshes/xbase_anim.nif`, return its absolute path (if found)
     FindFile {
@@ -124,6 +468,9 @@ enum Commands {
         /// vfstool find-file meshes/xbase_anim.nif
         ///
         /// C:\Games\Morrowind\Data Files\Meshes\XBase_Anim.nif
+        ///
+        /// Pass `-` to read VFS paths from stdin instead, one per line, and resolve them all
+        /// against a single VFS.
         path: PathBuf,
 
         /// If true, only searches for files which are NOT present inside of archives/BSA files.
@@ -137,32 +484,161 @@ enum Commands {
     /// Given some query term, locate all matches in the vfs.
     Find {
         /// VFS Path to query. Supports regular expressions!
-        path: PathBuf,
-
-        /// Output format when serializing as text.
-        #[arg(short, long, value_enum, default_value = "yaml")]
-        format: OutputFormat,
+        ///
+        /// Ending the query with a separator, eg `music/ms/`, instead lists every file inside of
+        /// that VFS directory.
+        #[arg(required_unless_present = "patterns_from")]
+        path: Option<PathBuf>,
+
+        /// Search for every regex listed in this file, one per line, instead of a single query.
+        /// Blank lines and lines starting with `#` are skipped. Files matching any of them are
+        /// included.
+        #[arg(long, value_name = "FILE", conflicts_with = "path")]
+        patterns_from: Option<PathBuf>,
+
+        /// With `--patterns-from`, write a section per pattern holding the files it matched,
+        /// instead of one combined result
+        #[arg(long, requires = "patterns_from", conflicts_with = "split")]
+        tag_matches: bool,
+
+        /// How to organize the results. `source` gives each data directory and archive its own
+        /// top-level entry, holding the matching files it provides.
+        #[arg(long, value_enum, default_value = "directory")]
+        group_by: GroupBy,
+
+        /// Only keep files stored in the archive with this file name, eg `Textures.bsa`.
+        /// Compared case-insensitively against the name only, not the archive's full path.
+        #[arg(long, value_name = "NAME")]
+        from_archive: Option<String>,
+
+        /// Where the query has to match. `prefix` and `exact` anchor it to the start, or both
+        /// ends, of each file's VFS path, eg `meshes/` rather than its path on disk.
+        /// `anywhere` matches it anywhere in the file's full path.
+        #[arg(long, value_enum, default_value = "anywhere")]
+        anchor: Anchor,
+
+        /// Output format when serializing as text. Defaults to the format matching the
+        /// extension of `--output`, eg `.json`, or yaml otherwise.
+        #[arg(short, long, value_enum)]
+        format: Option<OutputFormat>,
 
         /// Path to save the resulting search tree to.
         ///
         /// If omitted, the result is printed directly to stdout.
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Treat `--output` as a directory, and write each top-level directory of the result
+        /// to its own file inside of it, eg `meshes.yaml`
+        #[arg(long, requires = "output")]
+        split: bool,
+
+        /// Only show this many directories below the root, summarizing anything deeper as a
+        /// file count, eg `"...": "1423 files"`
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+
+        /// Re-root the result at this directory, eg `textures/`, removing it from every path.
+        /// Files outside of it are left out. It's matched from the root of the result, so with
+        /// `--paths absolute` this is a path on disk.
+        #[arg(long, value_name = "PREFIX")]
+        strip_prefix: Option<PathBuf>,
     },
     /// Given an absolute path, return a filtered version of the VFS containing either things
     /// replacing it, or files from this directory which are not being replaced
     Remaining {
+        /// Data directory to filter against. Case and trailing separators are ignored, and a
+        /// path containing data directories, or inside of one, picks those directories
         filter_path: PathBuf,
 
         /// If used, show only files replacing contents of this path, instead of ones still in it
         #[arg(short, long)]
         replacements_only: bool,
 
+        /// If used, show only files from this path which win in the full load order, ie what
+        /// this directory actually contributes to the VFS
+        #[arg(short, long, conflicts_with = "replacements_only")]
+        winning_only: bool,
+
+        /// Output format when serializing as text. Defaults to the format matching the
+        /// extension of `--output`, eg `.json`, or yaml otherwise.
+        #[arg(short, long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// Path to save the resulting search tree to.
+        ///
+        /// If omitted, the result is printed directly to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Treat `--output` as a directory, and write each top-level directory of the result
+        /// to its own file inside of it, eg `meshes.yaml`
+        #[arg(long, requires = "output")]
+        split: bool,
+
+        /// Only show this many directories below the root, summarizing anything deeper as a
+        /// file count, eg `"...": "1423 files"`
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+
+        /// Re-root the result at this directory, eg `textures/`, removing it from every path.
+        /// Files outside of it are left out. It's matched from the root of the result, so with
+        /// `--paths absolute` this is a path on disk.
+        #[arg(long, value_name = "PREFIX")]
+        strip_prefix: Option<PathBuf>,
+    },
+    /// Check each data directory in openmw.cfg for signs of misconfiguration, such as pointing
+    /// at a mod's folder when its content actually lives one level down.
+    ///
+    /// Exits with code 4 if any directory looks wrong.
+    Doctor,
+    /// List every file which is completely hidden by a higher-priority copy, along with the
+    /// source which wins
+    Shadowed {
         /// Output format when serializing as text.
         #[arg(short, long, value_enum, default_value = "yaml")]
         format: OutputFormat,
 
-        /// Path to save the resulting search tree to.
+        /// Path to save the resulting list to.
+        ///
+        /// If omitted, the result is printed directly to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check that every path listed in a manifest exists in the VFS, reporting which are present,
+    /// missing, or hiding other copies. Exits non-zero if any are missing
+    Check {
+        /// File listing one VFS path per line. Blank lines and lines starting with `#` are skipped
+        #[arg(short, long)]
+        manifest: PathBuf,
+    },
+    /// List the largest files in the VFS, biggest first, along with where each one comes from
+    Size {
+        /// How many files to list
+        #[arg(short, long, default_value_t = 20)]
+        top: usize,
+    },
+    /// List every loaded archive in load order, with its format, entry count, size on disk, and
+    /// how many of its entries aren't shadowed by a later source
+    ListArchives {
+        /// Serialize the list in this format instead of printing a table
+        #[arg(short, long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// Path to save the list to.
+        ///
+        /// If omitted, the result is printed directly to stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List every VFS path whose copies in different sources are spelled with differing case,
+    /// eg `Meshes/XBase.nif` and `meshes/xbase.nif`, which clash on case-sensitive filesystems
+    CaseCheck {
+        /// Serialize the list in this format instead of printing a line per path
+        #[arg(short, long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// Path to save the list to.
         ///
         /// If omitted, the result is printed directly to stdout.
         #[arg(short, long)]
@@ -170,12 +646,143 @@ enum Commands {
     },
 }
 
+/// Settings for `--paths`, mirroring `PathDisplay`
+#[derive(Debug, ValueEnum, Clone, Copy)]
+enum PathMode {
+    Relative,
+    Absolute,
+    Both,
+}
+
+impl From<PathMode> for PathDisplay {
+    fn from(mode: PathMode) -> Self {
+        match mode {
+            PathMode::Relative => PathDisplay::Relative,
+            PathMode::Absolute => PathDisplay::Absolute,
+            PathMode::Both => PathDisplay::Both,
+        }
+    }
+}
+
+/// Settings for `--color`
+#[derive(Debug, ValueEnum, Clone, Copy)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
 /// Supported output formats
-#[derive(Debug, ValueEnum, Clone)]
+#[derive(Debug, ValueEnum, Clone, Copy)]
 enum OutputFormat {
     Json,
     Yaml,
     Toml,
+    /// One JSON object per line, written in parallel
+    Jsonl,
+    /// Plain text tree, drawn with the glyphs chosen by `--ascii`/`--dir-glyph`/`--file-glyph`
+    Tree,
+}
+
+/// Supported archive layouts for packing
+#[derive(Debug, ValueEnum, Clone)]
+enum ArchiveFormat {
+    Tes3,
+    Tes4,
+    Sse,
+    Fo4,
+}
+
+/// Compression used for files packed into TES4 and FO4 archives
+#[derive(Debug, ValueEnum, Clone, Copy)]
+enum Compression {
+    None,
+    Fast,
+    Best,
+}
+
+/// How `extract` and `collapse` treat files which already exist where they write
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+enum OverwritePolicy {
+    /// Leave the existing file in place
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Write next to the existing file, with a numeric suffix, eg `a_1.nif`
+    Rename,
+    /// Stop before touching the existing file
+    Error,
+}
+
+/// How `extract` and `collapse` spell the names of the files they write
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+enum NameCase {
+    /// Use the normalized, lowercase VFS path
+    Lowercase,
+    /// Use the path as the file's source spells it, where it's known
+    Original,
+}
+
+impl NameCase {
+    /// The path, relative to the target directory, a file found at VFS path `key` is written
+    /// to. TES4 archives don't keep their original spelling, so their entries are always
+    /// written lowercase.
+    fn apply(self, key: &Path, file: &VfsFile) -> PathBuf {
+        let normalized = normalize_path(key);
+
+        match self {
+            NameCase::Lowercase => normalized,
+            // Only trust a spelling of the same path, so a file inserted without a data
+            // directory can't redirect the write somewhere else
+            NameCase::Original => file
+                .spelled_path()
+                .filter(|spelled| normalize_path(spelled) == normalized)
+                .unwrap_or(normalized),
+        }
+    }
+}
+
+/// Top-level layout of `find` results
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+enum GroupBy {
+    /// One tree following the VFS's directories
+    Directory,
+    /// A tree per data directory and archive
+    Source,
+}
+
+/// Where a `find` pattern has to match
+#[derive(Debug, PartialEq, ValueEnum, Clone, Copy)]
+enum Anchor {
+    /// From the start of the VFS path
+    Prefix,
+    /// The whole VFS path
+    Exact,
+    /// Anywhere in the file's path, including the path to its source on disk
+    Anywhere,
+}
+
+impl Anchor {
+    /// Wraps `pattern` in the anchors this mode asks for. The pattern is grouped first, so an
+    /// alternation like `a|b` is anchored as a whole.
+    fn apply(self, pattern: &str) -> String {
+        match self {
+            Anchor::Prefix => format!("^(?:{pattern})"),
+            Anchor::Exact => format!("^(?:{pattern})$"),
+            Anchor::Anywhere => pattern.to_string(),
+        }
+    }
+
+    /// The text of `file` which patterns are matched against. Anchored patterns are matched
+    /// against the VFS path, since a file's source path starts with its data directory.
+    fn subject(self, file: &VfsFile) -> String {
+        let path = match self {
+            Anchor::Prefix | Anchor::Exact => file.relative_path(),
+            Anchor::Anywhere => normalize_path(file.path()),
+        };
+
+        path.to_string_lossy().to_string()
+    }
 }
 
 /// Type of search to do when finding a file
@@ -192,7 +799,7 @@ enum FindType {
 }
 
 fn validate_config_dir(dir: &PathBuf) -> io::Result<PathBuf> {
-    let dir_metadata = metadata(&dir);
+    let dir_metadata = metadata(dir);
     let default_location = openmw_config::default_config_path();
 
     let config_arg_fail = match dir_metadata.is_ok() && dir_metadata.unwrap().is_dir() {
@@ -202,7 +809,7 @@ fn validate_config_dir(dir: &PathBuf) -> io::Result<PathBuf> {
             &default_location.display()
         )),
         true => {
-            match fs::read_dir(&dir)?
+            match fs::read_dir(dir)?
                 .filter_map(|entry| entry.ok())
                 .find(|entry| entry.file_name().eq_ignore_ascii_case("openmw.cfg"))
                 .map(|entry| entry.path())
@@ -227,369 +834,2077 @@ fn validate_config_dir(dir: &PathBuf) -> io::Result<PathBuf> {
     ))
 }
 
-fn filter_data_paths(to_keep: &PathBuf, paths: &mut Vec<PathBuf>) {
-    let normalized_input = normalize_path(&to_keep);
-    paths.retain(|path| normalize_path(&path).eq(&normalized_input))
+/// Keeps the data directories `to_keep` refers to: the directory itself, any directory inside of
+/// it, or the directory it's inside of. Paths are compared normalized and component by
+/// component, so case and trailing separators don't matter.
+fn filter_data_paths(to_keep: &Path, paths: &mut Vec<PathBuf>) {
+    let normalized_input = normalize_path(to_keep);
+
+    paths.retain(|path| {
+        let path = normalize_path(path);
+        path.starts_with(&normalized_input) || normalized_input.starts_with(&path)
+    })
+}
+
+/// Output settings shared by every command which renders a tree
+struct Rendering<'a> {
+    style: TreeStyle,
+    layout: SerializeLayout,
+    /// When set, serialized files carry the load-order priority of their source in this VFS
+    priorities: Option<&'a VFS>,
 }
 
-fn output_to_serialize_type(format: OutputFormat) -> SerializeType {
+/// Serializer backing an output format, if it isn't drawn as text
+fn serialize_type(format: OutputFormat) -> Option<SerializeType> {
     match format {
-        OutputFormat::Json => SerializeType::Json,
-        OutputFormat::Yaml => SerializeType::Yaml,
-        OutputFormat::Toml => SerializeType::Toml,
+        OutputFormat::Json => Some(SerializeType::Json),
+        OutputFormat::Yaml => Some(SerializeType::Yaml),
+        OutputFormat::Toml => Some(SerializeType::Toml),
+        OutputFormat::Jsonl => Some(SerializeType::JsonLines),
+        OutputFormat::Tree => None,
     }
 }
 
-fn construct_vfs(config_path: PathBuf) -> VFS {
-    let config = match openmw_config::OpenMWConfiguration::new(Some(config_path)) {
-        Err(config_err) => {
-            eprintln!("Failed to load configuration file: {config_err}");
-            std::process::exit(VFSToolExitCode::FailedToLoadOpenMWConfig.into());
-        }
-        Ok(config) => config,
-    };
-
-    let data_paths = config.data_directories();
-
-    let archives = config
-        .fallback_archives_iter()
-        .map(|archive| archive.value().as_str())
-        .collect();
-
-    VFS::from_directories(data_paths, Some(archives))
+/// Warns about each file in `tree` which the text form leaves out for having no name
+fn warn_nameless(tree: &DisplayTree) {
+    for file in tree.values().flat_map(DirectoryNode::nameless_files) {
+        eprintln!(
+            "{}Skipping a file with no name in {}",
+            print::warn_prefix(),
+            print::blue(file.canonical_path())
+        );
+    }
 }
 
-fn write_serialized_vfs(
-    path: Option<PathBuf>,
-    format: OutputFormat,
+/// Writes a tree into `writer` in the requested output format
+fn write_tree<W: Write>(
     files: &vfstool_lib::DisplayTree,
+    format: OutputFormat,
+    render: &Rendering,
+    mut writer: W,
 ) -> io::Result<()> {
-    let serialized = VFS::serialize_from_tree(files, output_to_serialize_type(format))?;
-
-    match path {
-        None => println!("{serialized}"),
-        Some(path) => {
-            let parent = path
-                .parent()
-                .expect("Failed to extract parent directory from output param!");
-            fs::create_dir_all(parent)?;
-            let mut file = fs::File::create(&path)?;
-            write!(file, "{serialized}")?;
+    match (serialize_type(format), render.priorities) {
+        (None, _) => {
+            warn_nameless(files);
+            writer.write_all(VFS::display_tree(files, &render.style).as_bytes())
+        }
+        (Some(write_type), None) => {
+            VFS::serialize_to_writer(files, write_type, render.layout, writer)
+        }
+        (Some(write_type), Some(vfs)) => {
+            vfs.serialize_with_priority_to_writer(files, write_type, render.layout, writer)
         }
     }
-
-    Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Cli::parse();
-    let config_dir = args.config.unwrap_or(openmw_config::default_config_path());
+/// Renders a shadowed-file report in the requested output format. As text, each path is followed
+/// by its winning source and then every source it hides.
+fn render_shadowed(
+    report: &BTreeMap<PathBuf, ShadowedFile>,
+    format: OutputFormat,
+    render: &Rendering,
+) -> io::Result<String> {
+    let style = &render.style;
+
+    match serialize_type(format) {
+        Some(write_type) => VFS::serialize_shadowed_with(report, write_type, render.layout),
+        None => {
+            let mut output = String::new();
+
+            for (path, entry) in report {
+                output.push_str(&format!(
+                    "{}\n{}{} (winner)\n",
+                    path.display(),
+                    style.dir_prefix,
+                    entry.winner.display()
+                ));
+
+                for source in &entry.shadowed {
+                    output.push_str(&format!("{}{}\n", style.file_prefix, source.display()));
+                }
+            }
 
-    let resolved_config_dir = validate_config_dir(&config_dir)?;
+            Ok(output)
+        }
+    }
+}
 
-    let vfs: VFS = construct_vfs(resolved_config_dir.clone());
+/// Top-level folders which OpenMW and the games it can load read assets from
+const KNOWN_VFS_FOLDERS: &[&str] = &[
+    "animations",
+    "bookart",
+    "fonts",
+    "icons",
+    "interface",
+    "l10n",
+    "meshes",
+    "music",
+    "scripts",
+    "shaders",
+    "sound",
+    "splash",
+    "strings",
+    "textures",
+    "video",
+];
+
+/// Heuristic verdict on a single `data=` entry
+enum DataDirDiagnosis {
+    Missing,
+    Healthy,
+    /// No content at the top level, but these subdirectories look like data directories
+    Nested(Vec<PathBuf>),
+    Unrecognized,
+}
 
-    match args.command {
-        Commands::Collapse {
-            collapse_into,
-            allow_copying,
-            extract_archives,
-            symbolic,
-        } => {
-            if metadata(&collapse_into).is_err() {
-                fs::create_dir_all(&collapse_into)?;
-            };
+/// Whether `dir` directly holds a known asset folder, a content file, or an archive
+fn has_vfs_content(dir: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
 
-            vfs.iter().for_each(|(relative_path, file)| {
-                let merged_path = collapse_into.join(relative_path);
-                let merged_dir = merged_path.parent().unwrap();
+    entries.filter_map(|entry| entry.ok()).any(|entry| {
+        let path = entry.path();
+
+        if path.is_dir() {
+            let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+            KNOWN_VFS_FOLDERS.contains(&name.as_str())
+        } else {
+            let file = VfsFile::from(&path);
+            file.is_plugin()
+                || file.path().extension().is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case("bsa") || extension.eq_ignore_ascii_case("ba2")
+                })
+        }
+    })
+}
 
-                if metadata(&merged_dir).is_err() {
-                    fs::create_dir_all(&merged_dir).unwrap();
-                };
+fn diagnose_data_dir(dir: &Path) -> DataDirDiagnosis {
+    if !dir.is_dir() {
+        return DataDirDiagnosis::Missing;
+    }
 
-                if file.is_loose() {
-                    assert!(file.path().exists());
+    if has_vfs_content(dir) {
+        return DataDirDiagnosis::Healthy;
+    }
 
-                    if metadata(&merged_path).is_ok() {
-                        fs::remove_file(&merged_path).unwrap();
-                    }
+    let mut nested: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir() && has_vfs_content(path))
+        .collect();
 
-                    // Since we extract files *out of* BSA archives
-                    // Don't bother including them in the collapsed directory
-                    if let Some(extension) = file.path().extension() {
-                        let extension = extension.to_ascii_lowercase();
-                        let file_name = file.file_name().unwrap_or_default().to_ascii_lowercase();
+    if nested.is_empty() {
+        DataDirDiagnosis::Unrecognized
+    } else {
+        nested.sort();
+        DataDirDiagnosis::Nested(nested)
+    }
+}
 
-                        if (extension == "bsa" || extension == "ba2") && extract_archives && file_name != "archiveinvalidationinvalidated!.bsa" {
-                            println!("Skipping archive {}", file.file_name().unwrap().to_string_lossy());
-                            return;
-                        }
-                    }
+/// Reports likely-misconfigured data directories and the broken symlinks inside of them, before
+/// any VFS is built from them
+fn doctor(config_path: PathBuf, options: &ScanOptions) {
+    let config = load_config(config_path);
+    let mut problems = 0;
 
-                    let link_fn = if symbolic {
-                        soft_link
-                    } else {
-                        hard_link
-                    };
+    for dir in data_directories(&config) {
+        let dir_display = print::blue(dir.display());
 
-                    if let Err(error) = link_fn(file.path(), &merged_path) {
-                        eprintln!(
-                            "Symlink attempt for {} failed due to error: {}",
-                            file.path().display(),
-                            error.to_string()
-                        );
+        match diagnose_data_dir(&dir) {
+            DataDirDiagnosis::Healthy => {
+                println!("{}{dir_display}", print::success_prefix())
+            }
+            DataDirDiagnosis::Missing => {
+                problems += 1;
+                println!("{}{dir_display} does not exist", print::err_prefix())
+            }
+            DataDirDiagnosis::Nested(candidates) => {
+                problems += 1;
+                println!(
+                    "{}{dir_display} has no content of its own, but these subdirectories do. It \
+                     should probably point at one of them instead:",
+                    print::warn_prefix()
+                );
 
-                        if allow_copying {
-                            if let Err(error) = fs::copy(file.path(), &merged_path) {
-                                eprintln!(
-                                    "Fallback file copying was enabled, but copying {} to {} failed due to {}!",
-                                    file.path().display(),
-                                    merged_path.display(),
-                                    error.to_string()
-                                );
-                            }
-                        }
-                    } else {
-                        let new_metadata = metadata(&merged_path).unwrap();
-                        let old_metadata = metadata(file.path()).unwrap();
-                        assert_eq!(new_metadata.len(), old_metadata.len());
-                        println!("Successfully wrote {} to {}", file.path().display(), merged_path.display());
-                    };
-                } else {
-                    if !extract_archives {
-                        println!(
-                            "Skipping {}, which is loaded from a BSA file at: {}",
-                            relative_path.display(),
-                            file.parent_archive_path().unwrap()
-                        )
-                    } else {
-                        match file.open() {
-                            Ok(mut data) => {
-                                let mut buf: Vec<u8> = Vec::new();
-                                if let Ok(_) = data.read_to_end(&mut buf) {
-                                    if let Err(error) = fs::write(&merged_path, buf) {
-                                        eprintln!(
-                                            "Extracting archived file {} to {} failed due to {}!",
-                                            relative_path.display(),
-                                            merged_path.display(),
-                                            error.to_string()
-                                        );
-                                    };
-                                };
-                            }
-                            Err(error) => {
-                                eprintln!("Failed to open archived file: {}", error.to_string())
-                            }
-                        };
-                    }
+                for candidate in candidates {
+                    println!("    {}", print::green(candidate.display()));
                 }
-            });
+            }
+            DataDirDiagnosis::Unrecognized => {
+                problems += 1;
+                println!(
+                    "{}{dir_display} contains no recognizable asset folders, content files, \
+                     or archives",
+                    print::warn_prefix()
+                )
+            }
         }
-        Commands::Extract {
-            source_file,
-            target_dir,
-        } => match vfs.get_file(&source_file) {
-            Some(file) => {
-                let mut dir_meta = metadata(&target_dir);
-
-                if dir_meta.is_err() {
-                    fs::create_dir_all(&target_dir)?;
-                    dir_meta = metadata(&target_dir);
-                }
 
-                let dir_meta = dir_meta?;
+        for broken in VFS::broken_links(&dir, options) {
+            problems += 1;
+            println!(
+                "{}{} is a symlink to {}, which does not exist",
+                print::warn_prefix(),
+                print::blue(broken.link.display()),
+                print::green(broken.target.display())
+            );
+        }
+    }
 
-                if dir_meta.is_dir() {
-                    match source_file.file_name() {
-                        Some(name) => {
-                            let target_path = target_dir.join(name);
+    if problems > 0 {
+        eprintln!(
+            "{}",
+            print::yellow(format!("Found {problems} problems in the data directories"))
+        );
+        std::process::exit(VFSToolExitCode::DoctorFoundProblems.into());
+    }
+}
 
-                            if file.is_loose() {
-                                if let Err(error) = fs::copy(file.path(), &target_path) {
-                                    eprintln!(
-                                        "{}Failed extracting loose file from the vfs: {}",
-                                        print::err_prefix(),
-                                        print::red(error.to_string()),
-                                    );
-                                } else {
-                                    println!(
-                                        "{}Successfully extracted {} to {}",
-                                        print::success_prefix(),
-                                        print::green(file.path().display()),
-                                        print::blue(target_dir.display())
-                                    );
-                                };
-                            } else {
-                                match file.open() {
-                                    Ok(mut data) => {
-                                        let mut buf: Vec<u8> = Vec::new();
-                                        if let Ok(_) = data.read_to_end(&mut buf) {
-                                            if let Err(error) = fs::write(&target_path, buf) {
-                                                eprintln!(
-                                                    "{}Extracting archived file {} to {} failed due to {}!",
-                                                    print::err_prefix(),
-                                                    print::green(source_file.display()),
-                                                    print::blue(target_path.display()),
-                                                    print::red(error.to_string()),
-                                                );
-                                            } else {
-                                                println!(
-                                                    "{}Successfully extracted {} to {}",
-                                                    print::success_prefix(),
-                                                    print::green(file.path().display()),
-                                                    print::blue(target_dir.display()),
-                                                );
-                                            };
-                                        };
-                                    }
-                                    Err(error) => {
-                                        eprintln!(
-                                            "{}Failed to open archived file: {}",
-                                            print::err_prefix(),
-                                            print::green(error.to_string())
-                                        )
-                                    }
-                                }
-                            }
-                        }
-                        None => eprintln!(
-                            "{}Source file {} does not have a file name! Cannot extract it!",
-                            print::err_prefix(),
-                            print::green(source_file.display()),
-                        ),
-                    };
-                } else {
-                    eprintln!(
-                        "{}Provided argument {} is not a directory! Cannot extract here!",
-                        print::err_prefix(),
-                        print::green(target_dir.display()),
-                    );
-                }
-            }
-            None => eprintln!(
-                "{}Couldn't locate {} in the vfs!",
-                print::err_prefix(),
-                print::green(source_file.display()),
-            ),
-        },
-        Commands::Find {
-            path,
-            format,
-            output,
-        } => {
-            // Lossy compare could produce false positives, but only if there are non-unicode
-            // characters at the same position in both the path and string being matched and the
-            // rest of the string is the same
-            let path_string = normalize_path(path).to_string_lossy().to_string();
-            let path_regex: regex::Regex = match regex::RegexBuilder::new(&path_string)
-                .case_insensitive(true)
-                .build()
-            {
-                Ok(regex) => regex,
-                Err(error) => {
-                    eprintln!("{error}");
-                    std::process::exit(VFSToolExitCode::BadRegex.into());
-                }
-            };
+/// Reads one regex per line from `path`, skipping blank lines and lines starting with `#`
+fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
 
-            let tree = vfs.tree_filtered(args.use_relative, |file| {
-                let normalized = normalize_path(file.path());
-                path_regex.is_match(&normalized.to_string_lossy())
-            });
+/// Looks up every path listed in `manifest`, exiting with `ManifestFilesMissing` if any of them
+/// aren't in the VFS
+fn check_manifest(vfs: &VFS, manifest: &Path) -> Result<()> {
+    let contents = fs::read_to_string(manifest)?;
+    let shadowed = vfs.shadowed_report();
+    let mut missing = 0;
 
-            write_serialized_vfs(output, format, &tree)?;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
-        Commands::FindFile {
-            path,
-            simple,
-            only_physical,
-        } => {
-            let file = match vfs.get_file(&path) {
-                Some(found_file) => found_file,
-                None => {
-                    if !simple {
-                        eprintln!(
-                            "{}Failed to locate {} in the provided VFS.",
-                            print::err_prefix(),
-                            print::blue(path.display()),
-                        )
-                    }
 
-                    // Ugly, make a dedicated enum for exit values later
-                    std::process::exit(VFSToolExitCode::FindFailed.into());
-                }
-            };
+        let path_display = print::blue(line);
 
-            let path_display = match file.is_archive() {
-                true => match only_physical {
-                    Some(true) => {
-                        if !simple {
-                            eprintln!(
-                                "{}Failed to locate {} in loose files of the provided VFS.",
-                                print::err_prefix(),
-                                print::blue(path.display()),
-                            )
-                        }
+        let Some(file) = vfs.get_file(line) else {
+            missing += 1;
+            println!("{}{path_display} is missing", print::err_prefix());
+            continue;
+        };
 
-                        std::process::exit(VFSToolExitCode::FileNotInLooseDirectories.into());
-                    }
-                    _ => PathBuf::from(file.parent_archive_path().unwrap())
-                        .join(&path)
-                        .to_string_lossy()
-                        .to_string(),
-                },
-                false => file.path().to_string_lossy().to_string(),
-            };
+        match shadowed.get(&normalize_path(line)) {
+            Some(report) => println!(
+                "{}{path_display} is provided by {}, hiding {} other copies",
+                print::warn_prefix(),
+                print::green(report.winner.display()),
+                report.shadowed.len()
+            ),
+            None => println!(
+                "{}{path_display} is provided by {}",
+                print::success_prefix(),
+                print::green(file.canonical_path())
+            ),
+        }
+    }
 
-            if simple {
-                print!("{}", path_display);
+    if missing > 0 {
+        eprintln!(
+            "{}",
+            print::yellow(format!("{missing} expected files are missing from the VFS"))
+        );
+        std::process::exit(VFSToolExitCode::ManifestFilesMissing.into());
+    }
+
+    Ok(())
+}
+
+/// Formats a byte count with binary units, eg `1.5 MiB`
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} {}", UNITS[0]),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Prints the `top` largest files in the VFS. Each thread keeps its own bounded heap of the
+/// biggest files it has seen, and the heaps are merged at the end.
+fn print_largest_files(vfs: &VFS, top: usize) {
+    type Largest<'a> = BinaryHeap<Reverse<(u64, &'a PathBuf)>>;
+
+    fn keep_largest<'a>(heap: &mut Largest<'a>, entry: (u64, &'a PathBuf), top: usize) {
+        heap.push(Reverse(entry));
+        if heap.len() > top {
+            heap.pop();
+        }
+    }
+
+    let (largest, unknown) = vfs
+        .par_iter()
+        .fold(
+            || (Largest::new(), 0usize),
+            |(mut heap, mut unknown), (path, file)| {
+                match file.size() {
+                    Ok(size) => keep_largest(&mut heap, (size, path), top),
+                    Err(_) => unknown += 1,
+                }
+                (heap, unknown)
+            },
+        )
+        .reduce(
+            || (Largest::new(), 0usize),
+            |(mut heap, unknown), (other, other_unknown)| {
+                for Reverse(entry) in other {
+                    keep_largest(&mut heap, entry, top);
+                }
+                (heap, unknown + other_unknown)
+            },
+        );
+
+    for Reverse((size, path)) in largest.into_sorted_vec() {
+        let source = vfs
+            .get_file(path)
+            .map(VfsFile::canonical_path)
+            .unwrap_or_default();
+
+        println!(
+            "{:>10}  {}  {}",
+            human_size(size),
+            print::blue(path.display()),
+            print::green(source)
+        );
+    }
+
+    if unknown > 0 {
+        eprintln!(
+            "{}",
+            print::yellow(format!(
+                "Skipped {unknown} files whose size couldn't be read"
+            ))
+        );
+    }
+}
+
+/// Renders `VFS::archive_report` as a table, or serialized when a format other than `tree` is
+/// requested
+fn render_archive_report(
+    report: &[ArchiveStats],
+    format: Option<OutputFormat>,
+    layout: SerializeLayout,
+) -> io::Result<String> {
+    if let Some(write_type) = format.and_then(serialize_type) {
+        return VFS::serialize_archive_report(report, write_type, layout);
+    }
+
+    let mut output = format!(
+        "{:<6} {:>8} {:>8} {:>10}  {}\n",
+        "Format", "Entries", "Winning", "Size", "Path"
+    );
+
+    for stats in report {
+        let format = match stats.format {
+            vfstool_lib::ArchiveFormat::Tes3 => "tes3",
+            vfstool_lib::ArchiveFormat::Tes4 => "tes4",
+            vfstool_lib::ArchiveFormat::Fo4 => "fo4",
+        };
+        let size = stats.size.map_or_else(|| "?".to_string(), human_size);
+
+        output.push_str(&format!(
+            "{format:<6} {:>8} {:>8} {size:>10}  {}\n",
+            stats.entries,
+            stats.winning,
+            stats.path.display()
+        ));
+    }
+
+    Ok(output)
+}
+
+/// Writes the result of `VFS::case_conflicts`, either serialized or as one
+/// `path -> [Spelling, spelling]` line per path
+fn render_case_conflicts(
+    report: &BTreeMap<PathBuf, Vec<PathBuf>>,
+    format: Option<OutputFormat>,
+    layout: SerializeLayout,
+) -> io::Result<String> {
+    if let Some(write_type) = format.and_then(serialize_type) {
+        return VFS::serialize_case_conflicts(report, write_type, layout);
+    }
+
+    let mut output = String::new();
+
+    for (path, spellings) in report {
+        let spellings: Vec<_> = spellings
+            .iter()
+            .map(|spelled| spelled.display().to_string())
+            .collect();
+        output.push_str(&format!(
+            "{} -> [{}]\n",
+            path.display(),
+            spellings.join(", ")
+        ));
+    }
+
+    Ok(output)
+}
+
+fn archive_to_pack_format(format: ArchiveFormat) -> PackFormat {
+    match format {
+        ArchiveFormat::Tes3 => PackFormat::Tes3,
+        ArchiveFormat::Tes4 => PackFormat::Tes4,
+        ArchiveFormat::Sse => PackFormat::Sse,
+        ArchiveFormat::Fo4 => PackFormat::Fo4,
+    }
+}
+
+fn to_pack_compression(compression: Compression) -> PackCompression {
+    match compression {
+        Compression::None => PackCompression::None,
+        Compression::Fast => PackCompression::Fast,
+        Compression::Best => PackCompression::Best,
+    }
+}
+
+/// Reads the ignore file given with `--ignore-file`, or else `.vfsignore` beside `config_path`
+/// if it exists, exiting if the file can't be read or holds a malformed pattern
+fn load_ignore_rules(ignore_file: Option<PathBuf>, config_path: &Path) -> IgnoreRules {
+    let ignore_file = match ignore_file {
+        Some(ignore_file) => ignore_file,
+        None => match config_path.parent().map(|dir| dir.join(".vfsignore")) {
+            Some(default) if default.is_file() => default,
+            _ => return IgnoreRules::default(),
+        },
+    };
+
+    IgnoreRules::from_file(&ignore_file).unwrap_or_else(|error| {
+        eprintln!(
+            "{}Couldn't use ignore file {}: {error}",
+            print::err_prefix(),
+            ignore_file.display()
+        );
+        std::process::exit(VFSToolExitCode::BadIgnoreFile.into());
+    })
+}
+
+/// Loads the `openmw.cfg` chain rooted at `config_path`, exiting if it can't be read
+fn load_config(config_path: PathBuf) -> OpenMWConfiguration {
+    match OpenMWConfiguration::new(Some(config_path)) {
+        Err(config_err) => {
+            eprintln!("Failed to load configuration file: {config_err}");
+            std::process::exit(VFSToolExitCode::FailedToLoadOpenMWConfig.into());
+        }
+        Ok(config) => config,
+    }
+}
+
+/// Data directories in load order, resolved the same way OpenMW does: `replace=data` drops every
+/// earlier entry, the engine's `resources/vfs` comes first and `data-local` comes last.
+/// Everything which loads data directories goes through here, so they always agree.
+fn data_directories(config: &OpenMWConfiguration) -> Vec<PathBuf> {
+    config
+        .data_directories_iter()
+        .map(|dir| dir.parsed().to_owned())
+        .collect()
+}
+
+/// Builds the VFS described by `config`. With `no_archives`, fallback archives are skipped
+/// entirely and only loose files are loaded. With `auto_archives`, the archives named after
+/// each content file are loaded too, see `plugin_archives`. Repeated archives and files left out
+/// by `--skip-unreadable` are reported as warnings.
+fn construct_vfs(
+    config: &OpenMWConfiguration,
+    options: &ScanOptions,
+    no_archives: bool,
+    auto_archives: bool,
+) -> VFS {
+    let mut archives: Vec<&str> = config
+        .fallback_archives_iter()
+        .map(|archive| archive.value().as_str())
+        .collect();
+
+    let plugin_archives = if auto_archives {
+        plugin_archives(config, &archives)
+    } else {
+        Vec::new()
+    };
+    archives.extend(plugin_archives.iter().map(String::as_str));
+
+    let vfs = VFS::from_directories_with(
+        data_directories(config),
+        (!no_archives).then_some(archives),
+        options,
+    );
+
+    for repeated in vfs.repeated_archives() {
+        eprintln!(
+            "{}Archive {} is listed more than once, so only its last entry is loaded",
+            print::warn_prefix(),
+            print::blue(repeated.display())
+        );
+    }
+
+    for skipped in vfs.unreadable() {
+        eprintln!(
+            "{}Skipping unreadable file {}: {}",
+            print::warn_prefix(),
+            print::blue(skipped.path.display()),
+            print::red(&skipped.error)
+        );
+    }
+
+    vfs
+}
+
+/// Archives named after each content file, eg `MyMod.bsa` or `MyMod.ba2` for `MyMod.esp`, in
+/// content file order. Ones which are already in `explicit` are left out, so they keep their
+/// place in the load order, and ones which don't exist are skipped when the VFS is built.
+fn plugin_archives(config: &OpenMWConfiguration, explicit: &[&str]) -> Vec<String> {
+    let mut archives: Vec<String> = Vec::new();
+
+    for plugin in config.content_files_iter() {
+        let Some(stem) = Path::new(plugin.value()).file_stem() else {
+            continue;
+        };
+
+        for extension in ["bsa", "ba2"] {
+            let archive = format!("{}.{extension}", stem.to_string_lossy());
+            let mut listed = explicit
+                .iter()
+                .copied()
+                .chain(archives.iter().map(String::as_str));
+
+            if !listed.any(|name| name.eq_ignore_ascii_case(&archive)) {
+                archives.push(archive);
+            }
+        }
+    }
+
+    archives
+}
+
+/// Applies `--strip-prefix`, if given, to every root of the tree
+fn strip_tree_prefix(mut tree: DisplayTree, prefix: Option<&Path>) -> DisplayTree {
+    if let Some(prefix) = prefix {
+        tree.values_mut().for_each(|node| node.strip_prefix(prefix));
+    }
+
+    tree
+}
+
+/// Applies `--depth`, if given, to every root of the tree
+fn truncate_tree(mut tree: DisplayTree, depth: Option<usize>) -> DisplayTree {
+    if let Some(depth) = depth {
+        tree.values_mut().for_each(|node| node.truncate(depth));
+    }
+
+    tree
+}
+
+/// File extension used for a serialized tree in the given format
+fn output_extension(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Json => "json",
+        OutputFormat::Yaml => "yaml",
+        OutputFormat::Toml => "toml",
+        OutputFormat::Jsonl => "jsonl",
+        OutputFormat::Tree => "txt",
+    }
+}
+
+/// The format `--format` asked for, or else the one matching the extension of `--output`, eg
+/// `.yml`. Falls back to yaml when neither says.
+fn resolve_format(format: Option<OutputFormat>, output: Option<&Path>) -> OutputFormat {
+    format
+        .or_else(|| {
+            let extension = output?.extension()?.to_str()?.to_ascii_lowercase();
+
+            match extension.as_str() {
+                "json" => Some(OutputFormat::Json),
+                "yaml" | "yml" => Some(OutputFormat::Yaml),
+                "toml" => Some(OutputFormat::Toml),
+                "jsonl" => Some(OutputFormat::Jsonl),
+                "txt" => Some(OutputFormat::Tree),
+                _ => None,
+            }
+        })
+        .unwrap_or(OutputFormat::Yaml)
+}
+
+/// Writes each top-level directory of the tree to its own file inside of `dir`, eg
+/// `meshes.yaml`, `textures.yaml`. Files sitting directly in the root go to a file named after
+/// the root itself.
+fn write_split_vfs(
+    dir: &Path,
+    format: OutputFormat,
+    files: &vfstool_lib::DisplayTree,
+    render: &Rendering,
+) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let write_subtree = |name: &std::ffi::OsStr, subtree: DisplayTree| -> io::Result<()> {
+        let mut file_name = name.to_os_string();
+        file_name.push(".");
+        file_name.push(output_extension(format));
+
+        let mut file = io::BufWriter::new(fs::File::create(dir.join(file_name))?);
+        write_tree(&subtree, format, render, &mut file)?;
+        file.flush()
+    };
+
+    for (root, node) in files {
+        if !node.files.is_empty() || node.truncated_files > 0 {
+            let mut root_node = DirectoryNode::new();
+            root_node.files = node.files.clone();
+            root_node.truncated_files = node.truncated_files;
+            root_node.show_sources = node.show_sources;
+
+            write_subtree(
+                root.file_name().unwrap_or("root".as_ref()),
+                DisplayTree::from([(root.clone(), root_node)]),
+            )?;
+        }
+
+        for (subdir, subdir_node) in &node.subdirs {
+            write_subtree(
+                subdir.file_name().unwrap_or(subdir.as_os_str()),
+                DisplayTree::from([(subdir.clone(), subdir_node.clone())]),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_serialized_vfs(
+    path: Option<PathBuf>,
+    format: OutputFormat,
+    files: &vfstool_lib::DisplayTree,
+    split: bool,
+    render: &Rendering,
+) -> io::Result<()> {
+    if split && let Some(dir) = &path {
+        return write_split_vfs(dir, format, files, render);
+    }
+
+    stream_output(path, |writer| write_tree(files, format, render, writer))
+}
+
+/// Writes the results of several searches, keyed by their query. As text, each tree follows a
+/// line naming its query.
+fn write_sections(
+    path: Option<PathBuf>,
+    format: OutputFormat,
+    sections: &BTreeMap<String, DisplayTree>,
+    render: &Rendering,
+) -> io::Result<()> {
+    stream_output(path, |writer| match serialize_type(format) {
+        Some(write_type) => {
+            VFS::serialize_sections_to_writer(sections, write_type, render.layout, writer)
+        }
+        None => {
+            for (query, tree) in sections {
+                warn_nameless(tree);
+                writeln!(writer, "{query}:")?;
+                writer.write_all(VFS::display_tree(tree, &render.style).as_bytes())?;
+            }
+
+            Ok(())
+        }
+    })
+}
+
+/// Hands `write` a buffered stdout, or a buffered file at `path` when one is given. Output is
+/// streamed straight into the destination, so large trees are never held as one string.
+fn stream_output(
+    path: Option<PathBuf>,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    match path {
+        None => {
+            let mut stdout = io::BufWriter::new(io::stdout().lock());
+            write(&mut stdout)?;
+            writeln!(stdout)?;
+            stdout.flush()
+        }
+        Some(path) => {
+            let mut file = io::BufWriter::new(create_output(&path)?);
+            write(&mut file)?;
+            file.flush()
+        }
+    }
+}
+
+/// Prints `serialized` to stdout, or writes it to `path` when one is given
+fn write_output(path: Option<PathBuf>, serialized: &str) -> io::Result<()> {
+    match path {
+        None => println!("{serialized}"),
+        Some(path) => write!(create_output(&path)?, "{serialized}")?,
+    }
+
+    Ok(())
+}
+
+/// Creates an output file, along with any missing parent directories
+fn create_output(path: &Path) -> io::Result<fs::File> {
+    let parent = path
+        .parent()
+        .expect("Failed to extract parent directory from output param!");
+    fs::create_dir_all(parent)?;
+
+    fs::File::create(path)
+}
+
+/// Canonicalizes a path which may not exist yet, by canonicalizing its deepest existing ancestor
+/// and appending the rest. Paths with no existing ancestor are only made absolute.
+fn canonicalize_lenient(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = fs::canonicalize(ancestor) {
+            let rest = path
+                .strip_prefix(ancestor)
+                .expect("Ancestors always prefix their descendants");
+            return canonical.join(rest);
+        }
+    }
+
+    path
+}
+
+/// Whether a file's lowercased extension is in the (already lowercased) exclusion set.
+/// Files without an extension are only excluded by an empty entry, eg `--exclude-extension ""`.
+/// The ArchiveInvalidation dummy archive is never excluded, as it must stay in place for texture
+/// replacement to work.
+fn has_excluded_extension(file: &VfsFile, excluded: &[String]) -> bool {
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    excluded.contains(&extension) && !vfstool_lib::is_invalidation_archive(file.path())
+}
+
+fn collapse_into_archive(
+    vfs: &VFS,
+    archive_path: &Path,
+    format: ArchiveFormat,
+    compression: Option<Compression>,
+    excluded: &[String],
+) -> Result<()> {
+    let mut skipped = 0;
+
+    if compression.is_some() && matches!(format, ArchiveFormat::Tes3) {
+        eprintln!(
+            "{}TES3 archives can't be compressed, so --compression is ignored",
+            print::warn_prefix()
+        );
+    }
+
+    let entries = vfs.iter_sorted().filter_map(|(relative_path, file)| {
+        if has_excluded_extension(file, excluded) {
+            skipped += 1;
+            return None;
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+
+        match file.open().and_then(|mut data| data.read_to_end(&mut buf)) {
+            Ok(_) => {
+                let compression = match compression {
+                    Some(compression) => to_pack_compression(compression),
+                    None if file.is_compressed() => PackCompression::Fast,
+                    None => PackCompression::None,
+                };
+
+                Some((relative_path.to_owned(), buf, compression))
+            }
+            Err(error) => {
+                eprintln!(
+                    "{}Failed to read {} for packing: {}",
+                    print::err_prefix(),
+                    print::green(relative_path.display()),
+                    print::red(error),
+                );
+                None
+            }
+        }
+    });
+
+    if let Some(parent) = archive_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut archive_file = io::BufWriter::new(fs::File::create(archive_path)?);
+    let collisions = archives::pack(entries, archive_to_pack_format(format), &mut archive_file)?;
+    archive_file.flush()?;
+
+    if !excluded.is_empty() {
+        println!("Skipped {skipped} files with excluded extensions");
+    }
+
+    for collision in &collisions {
+        eprintln!(
+            "[ WARNING ]: Skipping {}, as its archive key collides with a file already packed",
+            collision.display()
+        );
+    }
+
+    println!(
+        "{}Packed the VFS into {}",
+        print::success_prefix(),
+        print::blue(archive_path.display()),
+    );
+
+    Ok(())
+}
+
+/// Resolves a VFS path to where its file actually lives, for `find-file`. Fails with the exit
+/// code to report if the path isn't in the VFS, or if it's archived when `only_physical` is set.
+fn find_file_path(
+    vfs: &VFS,
+    path: &Path,
+    only_physical: bool,
+) -> std::result::Result<String, VFSToolExitCode> {
+    let file = vfs.get_file(path).ok_or(VFSToolExitCode::FindFailed)?;
+
+    if only_physical && file.is_archive() {
+        return Err(VFSToolExitCode::FileNotInLooseDirectories);
+    }
+
+    Ok(file.canonical_path())
+}
+
+fn print_found_file(path: &Path, path_display: &str, simple: bool) {
+    if simple {
+        println!("{path_display}");
+    } else {
+        println!(
+            "{}Successfully found VFS File {} at path {}",
+            print::success_prefix(),
+            print::blue(path.display()),
+            print::green(path_display),
+        )
+    }
+}
+
+fn print_find_file_error(path: &Path, code: &VFSToolExitCode, simple: bool) {
+    let location = match code {
+        VFSToolExitCode::FileNotInLooseDirectories => "loose files of the provided VFS",
+        _ => "the provided VFS",
+    };
+
+    if simple {
+        eprintln!("Failed to locate {} in {location}.", path.display());
+    } else {
+        eprintln!(
+            "{}Failed to locate {} in {location}.",
+            print::err_prefix(),
+            print::blue(path.display()),
+        )
+    }
+}
+
+/// Whether a VFS path falls inside of one of the `--only` prefixes, which are already normalized.
+/// Everything does when there are none.
+fn in_scope(path: &Path, only: &[PathBuf]) -> bool {
+    only.is_empty() || only.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// The first data directory or archive of `vfs` which is `target` or sits inside of it. Pruning
+/// such a target would delete that source's files, since they aren't VFS paths relative to it.
+fn source_inside(vfs: &VFS, target: &Path) -> Option<PathBuf> {
+    let target = canonicalize_lenient(target);
+    let sources = vfs.sources();
+
+    sources
+        .dirs
+        .into_iter()
+        .map(|(dir, _count)| dir)
+        .chain(sources.archives.into_iter().map(|(path, ..)| path))
+        .find(|source| canonicalize_lenient(source).starts_with(&target))
+}
+
+/// Removes every file under a collapse `target` which no longer maps to a path in the VFS, along
+/// with any directories this leaves empty. Only files `in_scope` of `only` are considered, and
+/// the ones in `written`, eg renamed copies made by the same collapse, are kept.
+/// Nothing is removed with `dry_run`, or if removal isn't confirmed when `assume_yes` isn't set.
+fn prune_collapsed(
+    vfs: &VFS,
+    target: &Path,
+    only: &[PathBuf],
+    written: &HashSet<PathBuf>,
+    dry_run: bool,
+    assume_yes: bool,
+) -> io::Result<()> {
+    let mut stale = Vec::new();
+    find_stale_files(vfs, target, target, only, &mut stale)?;
+    stale.retain(|path| !written.contains(path));
+    stale.sort();
+
+    if stale.is_empty() {
+        println!(
+            "{}Nothing to prune in {}",
+            print::success_prefix(),
+            print::blue(target.display())
+        );
+        return Ok(());
+    }
+
+    for path in &stale {
+        println!("Stale: {}", path.display());
+    }
+
+    let summary = format!("{} stale files in {}", stale.len(), target.display());
+
+    if dry_run {
+        println!("{}Would remove {summary}", print::warn_prefix());
+        return Ok(());
+    }
+
+    if !assume_yes && !confirm(&format!("Remove {summary}?"))? {
+        println!("Nothing was removed");
+        return Ok(());
+    }
+
+    for path in &stale {
+        fs::remove_file(path)?;
+
+        // Only empty directories can be removed, so the first failure means the rest are in use
+        for dir in path.ancestors().skip(1).take_while(|dir| *dir != target) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    println!("{}Removed {summary}", print::success_prefix());
+    Ok(())
+}
+
+/// Collects every file under `dir` whose path relative to `target` is `in_scope` of `only` but
+/// isn't in the VFS. Symlinks are treated as files, since a symbolic collapse is made of them.
+fn find_stale_files(
+    vfs: &VFS,
+    target: &Path,
+    dir: &Path,
+    only: &[PathBuf],
+    stale: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_dir() {
+            find_stale_files(vfs, target, &path, only, stale)?;
+        } else if let Ok(relative_path) = path.strip_prefix(target)
+            && in_scope(&normalize_path(relative_path), only)
+            && vfs.get_file(relative_path).is_none()
+        {
+            stale.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no. Without a terminal to ask on, the
+/// answer is always no.
+fn confirm(question: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        eprintln!(
+            "{}Not asking to confirm without a terminal. Use --yes to skip confirmation.",
+            print::warn_prefix()
+        );
+        return Ok(false);
+    }
+
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Picks where a file bound for `target` is written under `policy`, or `None` if it should be
+/// skipped. Exits when `policy` is `Error` and `target` is already taken.
+fn resolve_target(target: &Path, policy: OverwritePolicy) -> Option<PathBuf> {
+    // Dangling symlinks from an earlier collapse still take up their path
+    let is_taken = |path: &Path| fs::symlink_metadata(long_path(path)).is_ok();
+
+    if !is_taken(target) {
+        return Some(target.to_path_buf());
+    }
+
+    match policy {
+        OverwritePolicy::Overwrite => Some(target.to_path_buf()),
+        OverwritePolicy::Skip => None,
+        OverwritePolicy::Rename => {
+            let stem = target.file_stem().unwrap_or_default();
+
+            (1..)
+                .map(|suffix: usize| {
+                    let mut name = stem.to_os_string();
+                    name.push(format!("_{suffix}"));
+
+                    if let Some(extension) = target.extension() {
+                        name.push(".");
+                        name.push(extension);
+                    }
+
+                    target.with_file_name(name)
+                })
+                .find(|candidate| !is_taken(candidate))
+        }
+        OverwritePolicy::Error => {
+            eprintln!(
+                "{}Refusing to replace {}, as it already exists!",
+                print::err_prefix(),
+                print::blue(target.display()),
+            );
+            std::process::exit(VFSToolExitCode::TargetExists.into());
+        }
+    }
+}
+
+/// Whether an IO error may go away by itself, eg a file briefly locked by antivirus, so the
+/// operation is worth trying again
+fn is_transient(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    let sharing_violation = cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 33));
+
+    sharing_violation
+        || matches!(
+            error.kind(),
+            io::ErrorKind::PermissionDenied | io::ErrorKind::Interrupted
+        )
+}
+
+/// Runs `operation` on `target`, trying again up to `retries` times while it fails with a
+/// transient error. The first retry waits 50ms, and each one after it twice as long.
+fn with_retries<T>(
+    retries: u32,
+    target: &Path,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut delay = std::time::Duration::from_millis(50);
+    let mut attempt = 0;
+
+    loop {
+        match operation() {
+            Err(error) if attempt < retries && is_transient(&error) => {
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => {
+                if result.is_ok() && attempt > 0 {
+                    progress!(
+                        "{}Writing {} succeeded after {attempt} retries",
+                        print::warn_prefix(),
+                        print::blue(target.display())
+                    );
+                }
+
+                return result;
+            }
+        }
+    }
+}
+
+/// Copies `file` out of the VFS to `target_path`, returning whether it succeeded
+fn extract_file(file: &VfsFile, source_file: &Path, target_path: &Path) -> bool {
+    let target_dir = target_path.parent().unwrap_or(target_path);
+
+    let result = if file.is_loose() {
+        fs::copy(file.path(), target_path)
+            .map(|_bytes| ())
+            .map_err(|error| {
+                eprintln!(
+                    "{}Failed extracting loose file from the vfs: {}",
+                    print::err_prefix(),
+                    print::red(error.to_string()),
+                );
+            })
+    } else {
+        match file.open() {
+            Ok(mut data) => {
+                let mut buf: Vec<u8> = Vec::new();
+                data.read_to_end(&mut buf)
+                    .and_then(|_| fs::write(target_path, buf))
+                    .map_err(|error| {
+                        eprintln!(
+                            "{}Extracting archived file {} to {} failed due to {}!",
+                            print::err_prefix(),
+                            print::green(source_file.display()),
+                            print::blue(target_path.display()),
+                            print::red(error.to_string()),
+                        );
+                    })
+            }
+            Err(error) => {
+                eprintln!(
+                    "{}Failed to open archived file: {}",
+                    print::err_prefix(),
+                    print::green(error.to_string())
+                );
+                Err(())
+            }
+        }
+    };
+
+    if result.is_ok() {
+        progress!(
+            "{}Successfully extracted {} to {}",
+            print::success_prefix(),
+            print::green(file.path().display()),
+            print::blue(target_dir.display())
+        );
+    }
+
+    result.is_ok()
+}
+
+/// Whether a VFS path contains any glob metacharacters
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '[', '{'])
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    print::init(args.color);
+    print::set_quiet(args.quiet);
+
+    if args.single_threaded {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build_global()
+            .map_err(io::Error::other)?;
+    }
+
+    let mut scan_options = ScanOptions {
+        skip_unreadable: args.skip_unreadable,
+        resolve_symlinks: args.resolve_symlinked_sources,
+        ignore_invalidation: args.ignore_invalidation,
+        max_depth: if args.no_recurse {
+            Some(1)
+        } else {
+            args.max_depth
+        },
+        ignore: IgnoreRules::default(),
+        priority_overrides: Vec::new(),
+    };
+
+    let (config, mut vfs) = match &args.archive {
+        Some(archive) => {
+            if matches!(args.command, Commands::Doctor | Commands::Remaining { .. }) {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::ArgumentConflict,
+                        "doctor and remaining need an openmw.cfg, so they can't be used with --archive",
+                    )
+                    .exit();
+            }
+
+            match VFS::from_archive(archive) {
+                Ok(vfs) => (None, vfs),
+                Err(error) => {
+                    eprintln!("{}{error}", print::err_prefix());
+                    std::process::exit(VFSToolExitCode::UnreadableArchive.into());
+                }
+            }
+        }
+        None => {
+            let config_dir = args.config.unwrap_or(openmw_config::default_config_path());
+            let resolved_config_dir = validate_config_dir(&config_dir)?;
+            scan_options.ignore = load_ignore_rules(args.ignore_file, &resolved_config_dir);
+
+            // Doctor checks the config as written, so it must run before anything is loaded from it
+            if matches!(args.command, Commands::Doctor) {
+                doctor(resolved_config_dir, &scan_options);
+                return Ok(());
+            }
+
+            let config = load_config(resolved_config_dir);
+            let vfs = construct_vfs(&config, &scan_options, args.no_archives, args.auto_archives);
+            (Some(config), vfs)
+        }
+    };
+
+    // Only listings leave the containers out. Collapse and extract write files out, and would
+    // lose the archives entirely unless their contents were also being extracted
+    if args.hide_archive_files
+        && !matches!(
+            args.command,
+            Commands::Collapse { .. } | Commands::Extract { .. }
+        )
+    {
+        vfs.hide_archive_files();
+    }
+
+    let mut tree_style = if args.ascii {
+        TreeStyle::ascii()
+    } else {
+        TreeStyle::unicode()
+    };
+
+    if let Some(dir_glyph) = args.dir_glyph {
+        tree_style.dir_prefix = dir_glyph;
+    }
+
+    if let Some(file_glyph) = args.file_glyph {
+        tree_style.file_prefix = file_glyph;
+    }
+
+    let render = Rendering {
+        style: tree_style,
+        layout: if args.compact {
+            SerializeLayout::Compact
+        } else {
+            SerializeLayout::Pretty
+        },
+        priorities: args.with_priority.then_some(&vfs),
+    };
+
+    let path_display = if args.use_relative {
+        PathDisplay::Relative
+    } else {
+        args.paths.into()
+    };
+
+    match args.command {
+        Commands::Collapse {
+            collapse_into,
+            into_archive,
+            archive_format,
+            compression,
+            allow_copying,
+            extract_archives,
+            symbolic,
+            exclude_extensions,
+            only,
+            force,
+            prune,
+            dry_run,
+            yes,
+            overwrite,
+            retries,
+            name_case,
+        } => {
+            let only: Vec<PathBuf> = only.iter().map(normalize_path).collect();
+            if !only.is_empty() {
+                vfs.retain(|path, _file| in_scope(path, &only));
+            }
+
+            let mut excluded: Vec<String> = exclude_extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+                .collect();
+
+            // Since we extract files *out of* BSA archives
+            // Don't bother including them in the collapsed directory
+            if extract_archives {
+                excluded.extend(["bsa".to_string(), "ba2".to_string()]);
+            }
+
+            let Some(collapse_into) = collapse_into else {
+                let archive_path = into_archive.expect("clap requires a target for Collapse");
+                return collapse_into_archive(
+                    &vfs,
+                    &archive_path,
+                    archive_format,
+                    compression,
+                    &excluded,
+                );
+            };
+
+            if !force {
+                let target = canonicalize_lenient(&collapse_into);
+
+                if let Some((source, _count)) = vfs
+                    .sources()
+                    .dirs
+                    .iter()
+                    .find(|(source, _count)| target.starts_with(canonicalize_lenient(source)))
+                {
+                    eprintln!(
+                        "{}Refusing to collapse into {}, as it is inside of the data directory {}! Use --force to do it anyway.",
+                        print::err_prefix(),
+                        print::blue(collapse_into.display()),
+                        print::green(source.display()),
+                    );
+                    std::process::exit(VFSToolExitCode::CollapseIntoSource.into());
+                }
+            }
+
+            // Even with --force, pruning a target holding a source would delete its real files
+            if prune && let Some(source) = source_inside(&vfs, &collapse_into) {
+                eprintln!(
+                    "{}Refusing to prune {}, as it contains the data directory or archive {}!",
+                    print::err_prefix(),
+                    print::blue(collapse_into.display()),
+                    print::green(source.display()),
+                );
+                std::process::exit(VFSToolExitCode::CollapseIntoSource.into());
+            }
+
+            if metadata(&collapse_into).is_err() {
+                fs::create_dir_all(&collapse_into)?;
+            };
+
+            let mut summary = Summary::start();
+            // Renamed copies aren't VFS paths, so pruning has to be told they were just written
+            let mut renamed = HashSet::new();
+
+            // Sorted, so the log reads the same on every run
+            vfs.iter_sorted().for_each(|(relative_path, file)| {
+                if has_excluded_extension(file, &excluded) {
+                    progress!("Skipping {}, as its extension is excluded", relative_path.display());
+                    summary.skipped += 1;
+                    return;
+                }
+
+                if !file.is_loose() && !extract_archives {
+                    progress!(
+                        "Skipping {}, which is loaded from a BSA file at: {}",
+                        relative_path.display(),
+                        file.parent_archive_path().unwrap()
+                    );
+                    summary.skipped += 1;
+                    return;
+                }
+
+                let named_path = collapse_into.join(name_case.apply(relative_path, file));
+                let Some(merged_path) = resolve_target(&named_path, overwrite) else {
+                    progress!("Skipping {}, as it already exists", relative_path.display());
+                    summary.skipped += 1;
+                    return;
+                };
+                if merged_path != named_path {
+                    renamed.insert(merged_path.clone());
+                }
+                // Deep mod trees can go past Windows' path length limit, so filesystem calls
+                // use the extended-length spelling, while messages keep the plain one
+                let (source, target) = (long_path(file.path()), long_path(&merged_path));
+                let merged_dir = target.parent().unwrap();
+
+                if metadata(merged_dir).is_err() {
+                    fs::create_dir_all(merged_dir).unwrap();
+                };
+
+                if file.is_loose() {
+                    assert!(source.exists());
+
+                    if metadata(&target).is_ok() {
+                        fs::remove_file(&target).unwrap();
+                    }
+
+                    let link_fn = if symbolic {
+                        soft_link
+                    } else {
+                        hard_link
+                    };
+
+                    if let Err(error) =
+                        with_retries(retries, &merged_path, || link_fn(&source, &target))
+                    {
+                        eprintln!(
+                            "Symlink attempt for {} failed due to error: {}",
+                            file.path().display(),
+                            error
+                        );
+
+                        if !allow_copying {
+                            summary.record(false);
+                        } else if let Err(error) =
+                            with_retries(retries, &merged_path, || fs::copy(&source, &target))
+                        {
+                            eprintln!(
+                                "Fallback file copying was enabled, but copying {} to {} failed due to {}!",
+                                file.path().display(),
+                                merged_path.display(),
+                                error
+                            );
+                            summary.record(false);
+                        } else {
+                            summary.record(true);
+                        }
+                    } else {
+                        let new_metadata = metadata(&target).unwrap();
+                        let old_metadata = metadata(&source).unwrap();
+                        assert_eq!(new_metadata.len(), old_metadata.len());
+                        progress!("Successfully wrote {} to {}", file.path().display(), merged_path.display());
+                        summary.record(true);
+                    };
+                } else {
+                    let extracted = match file.open() {
+                        Ok(mut data) => {
+                            let mut buf: Vec<u8> = Vec::new();
+                            data.read_to_end(&mut buf)
+                                .and_then(|_| fs::write(&target, buf))
+                                .inspect_err(|error| {
+                                    eprintln!(
+                                        "Extracting archived file {} to {} failed due to {}!",
+                                        relative_path.display(),
+                                        merged_path.display(),
+                                        error
+                                    );
+                                })
+                                .is_ok()
+                        }
+                        Err(error) => {
+                            eprintln!("Failed to open archived file: {}", error);
+                            false
+                        }
+                    };
+
+                    summary.record(extracted);
+                }
+            });
+
+            summary.print();
+
+            if prune {
+                prune_collapsed(&vfs, &collapse_into, &only, &renamed, dry_run, yes)?;
+            }
+        }
+        Commands::Extract {
+            source_file,
+            target_dir,
+            preserve_tree,
+            stdout,
+            overwrite,
+            name_case,
+        } => {
+            let source_string = source_file.to_string_lossy();
+
+            let matches: Vec<(PathBuf, &VfsFile)> = if is_glob(&source_string) {
+                let glob_regex = match glob_to_regex(&source_string) {
+                    Ok(regex) => regex,
+                    Err(error) => {
+                        eprintln!("{error}");
+                        std::process::exit(VFSToolExitCode::BadRegex.into());
+                    }
+                };
+
+                let mut matches: Vec<(PathBuf, &VfsFile)> = vfs
+                    .iter()
+                    .filter(|(path, _)| glob_regex.is_match(&path.to_string_lossy()))
+                    .map(|(path, file)| (path.to_owned(), file))
+                    .collect();
+
+                matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+                matches
             } else {
-                println!(
-                    "{}Successfully found VFS File {} at path {}",
-                    print::success_prefix(),
-                    print::blue(&path.display()),
-                    print::green(&path_display),
-                )
+                vfs.get_file(&source_file)
+                    .map(|file| vec![(source_file.clone(), file)])
+                    .unwrap_or_default()
+            };
+
+            if matches.is_empty() {
+                eprintln!(
+                    "{}Couldn't locate {} in the vfs!",
+                    print::err_prefix(),
+                    print::green(source_file.display()),
+                );
+                return Ok(());
+            }
+
+            if stdout {
+                let mut out = io::stdout().lock();
+
+                for (_relative_path, file) in &matches {
+                    io::copy(&mut file.open()?, &mut out)?;
+                }
+
+                return Ok(());
+            }
+
+            let target_dir = target_dir.expect("clap requires a target directory without --stdout");
+            let mut dir_meta = metadata(&target_dir);
+
+            if dir_meta.is_err() {
+                fs::create_dir_all(&target_dir)?;
+                dir_meta = metadata(&target_dir);
+            }
+
+            if !dir_meta?.is_dir() {
+                eprintln!(
+                    "{}Provided argument {} is not a directory! Cannot extract here!",
+                    print::err_prefix(),
+                    print::green(target_dir.display()),
+                );
+                return Ok(());
+            }
+
+            // Flattening several matches into one directory can make them overwrite each other
+            if !preserve_tree {
+                let mut seen_names = std::collections::HashSet::new();
+
+                for (relative_path, _) in &matches {
+                    let name = relative_path.file_name().map(normalize_path);
+
+                    if !seen_names.insert(name) {
+                        eprintln!(
+                            "{}Multiple matches for {} share the file name of {}! Use --preserve-tree to extract them.",
+                            print::err_prefix(),
+                            print::green(source_file.display()),
+                            print::blue(relative_path.display()),
+                        );
+                        std::process::exit(VFSToolExitCode::ExtractCollision.into());
+                    }
+                }
+            }
+
+            let mut summary = Summary::start();
+
+            for (relative_path, file) in matches {
+                let named_path = name_case.apply(&relative_path, file);
+                let target_path = if preserve_tree {
+                    target_dir.join(&named_path)
+                } else {
+                    match named_path.file_name() {
+                        Some(name) => target_dir.join(name),
+                        None => {
+                            eprintln!(
+                                "{}Source file {} does not have a file name! Cannot extract it!",
+                                print::err_prefix(),
+                                print::green(relative_path.display()),
+                            );
+                            summary.record(false);
+                            continue;
+                        }
+                    }
+                };
+
+                let Some(target_path) = resolve_target(&target_path, overwrite) else {
+                    summary.skipped += 1;
+                    progress!(
+                        "{}Skipping {}, as {} already exists",
+                        print::warn_prefix(),
+                        print::green(relative_path.display()),
+                        print::blue(target_path.display()),
+                    );
+                    continue;
+                };
+
+                if let Some(parent) = target_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                summary.record(extract_file(file, &relative_path, &target_path));
             }
+
+            summary.print();
         }
-        Commands::Remaining {
-            filter_path,
-            replacements_only,
+        Commands::Find {
+            path,
+            patterns_from,
+            tag_matches,
+            group_by,
+            from_archive,
+            anchor,
             format,
             output,
+            split,
+            depth,
+            strip_prefix,
         } => {
-            let config = match openmw_config::OpenMWConfiguration::new(Some(resolved_config_dir)) {
-                Err(config_err) => {
-                    eprintln!("Failed to load openmw.cfg for comparison: {config_err}");
-                    std::process::exit(VFSToolExitCode::FailedToLoadOpenMWConfig.into());
-                }
-                Ok(config) => config,
+            let format = resolve_format(format, output.as_deref());
+            let from_archive = from_archive.as_deref().map(normalize_str);
+            let in_archive = |file: &VfsFile| {
+                from_archive.as_ref().is_none_or(|wanted| {
+                    file.parent_archive_name()
+                        .is_some_and(|name| normalize_str(&name) == *wanted)
+                })
             };
 
-            let mut paths = config
-                .data_directories_iter()
-                .map(|dir| dir.parsed().to_owned())
-                .collect();
+            let build_tree = |keep: &dyn Fn(&VfsFile) -> bool| match group_by {
+                GroupBy::Directory => vfs.tree_filtered(path_display, keep),
+                GroupBy::Source => vfs.tree_by_source(keep),
+            };
 
-            filter_data_paths(&filter_path, &mut paths);
+            let tree = if let Some(patterns_file) = patterns_from {
+                let patterns = read_patterns(&patterns_file)?;
+                let anchored = patterns.iter().map(|pattern| anchor.apply(pattern));
+                let regexes = match regex::RegexSetBuilder::new(anchored)
+                    .case_insensitive(true)
+                    .build()
+                {
+                    Ok(regexes) => regexes,
+                    Err(error) => {
+                        eprintln!("{error}");
+                        std::process::exit(VFSToolExitCode::BadRegex.into());
+                    }
+                };
+
+                // Tree files carry their display paths, so tags are recorded while filtering the
+                // tree itself. Each file is only matched against the whole set once.
+                let tags = RefCell::new(HashMap::new());
+                let tree = build_tree(&|file| {
+                    let matched = regexes.matches(&anchor.subject(file));
+                    let keep = in_archive(file) && matched.matched_any();
+
+                    if keep && tag_matches {
+                        tags.borrow_mut().insert(file.path().to_path_buf(), matched);
+                    }
 
-            let filtered_vfs = VFS::from_directories(&paths, None);
-            let filter_normalized = normalize_path(&filter_path);
+                    keep
+                });
+
+                if tag_matches {
+                    let tags = tags.into_inner();
+                    let sections: BTreeMap<String, DisplayTree> = patterns
+                        .iter()
+                        .enumerate()
+                        .map(|(index, pattern)| {
+                            let mut section = tree.clone();
+                            section.values_mut().for_each(|node| {
+                                node.filter(&|file: &VfsFile| {
+                                    tags.get(file.path())
+                                        .is_some_and(|matched| matched.matched(index))
+                                })
+                            });
+
+                            let section = strip_tree_prefix(section, strip_prefix.as_deref());
+                            (pattern.clone(), truncate_tree(section, depth))
+                        })
+                        .collect();
+
+                    write_sections(output, format, &sections, &render)?;
+                    return Ok(());
+                }
 
-            let files_remaining = vfs.tree_filtered(args.use_relative, |file| {
-                let path = file.path();
-                // Check if there's a file whose ending matches this path, but not this exact path
-                if replacements_only {
-                    filtered_vfs.has_normalized_not_exact(path)
+                tree
+            } else if let Some(path) = path
+                .as_ref()
+                .filter(|path| path.to_string_lossy().ends_with(['/', '\\']))
+            {
+                // A trailing separator asks for everything inside of a directory, as the VFS only
+                // stores files and a regex would have nothing to match
+                if group_by == GroupBy::Source {
+                    let prefix = normalize_path(path);
+                    build_tree(&|file| {
+                        in_archive(file) && file.relative_path().starts_with(&prefix)
+                    })
                 } else {
-                    normalize_path(path).starts_with(&filter_normalized)
+                    let mut tree = vfs.tree_under(path_display, path);
+                    tree.values_mut().for_each(|node| node.filter(&in_archive));
+                    tree
                 }
-            });
+            } else {
+                let path = path.expect("clap requires a path unless --patterns-from is given");
+
+                // Lossy compare could produce false positives, but only if there are non-unicode
+                // characters at the same position in both the path and string being matched and
+                // the rest of the string is the same
+                let path_string = normalize_path(&path).to_string_lossy().to_string();
+                let path_regex: regex::Regex =
+                    match regex::RegexBuilder::new(&anchor.apply(&path_string))
+                        .case_insensitive(true)
+                        .build()
+                    {
+                        Ok(regex) => regex,
+                        Err(error) => {
+                            eprintln!("{error}");
+                            std::process::exit(VFSToolExitCode::BadRegex.into());
+                        }
+                    };
+
+                build_tree(&|file| in_archive(file) && path_regex.is_match(&anchor.subject(file)))
+            };
+
+            let tree = strip_tree_prefix(tree, strip_prefix.as_deref());
+            let tree = truncate_tree(tree, depth);
+            write_serialized_vfs(output, format, &tree, split, &render)?;
+        }
+        Commands::FindFile {
+            path,
+            simple,
+            only_physical,
+        } => {
+            let only_physical = only_physical.unwrap_or(false);
+
+            if path.as_os_str() == "-" {
+                let mut failed = false;
+
+                for line in io::stdin().lines() {
+                    let line = line?;
+                    let path = Path::new(line.trim());
+
+                    if path.as_os_str().is_empty() {
+                        continue;
+                    }
+
+                    match find_file_path(&vfs, path, only_physical) {
+                        Ok(path_display) => print_found_file(path, &path_display, simple),
+                        Err(code) => {
+                            failed = true;
+                            print_find_file_error(path, &code, simple);
+                        }
+                    }
+                }
+
+                if failed {
+                    std::process::exit(VFSToolExitCode::FindFailed.into());
+                }
+
+                return Ok(());
+            }
+
+            match find_file_path(&vfs, &path, only_physical) {
+                Ok(path_display) => {
+                    // A lone result is printed without a newline, so it can be used as-is
+                    if simple {
+                        print!("{path_display}");
+                    } else {
+                        print_found_file(&path, &path_display, simple);
+                    }
+                }
+                Err(code) => {
+                    if !simple {
+                        print_find_file_error(&path, &code, simple);
+                    }
+
+                    std::process::exit(code.into());
+                }
+            }
+        }
+        Commands::Remaining {
+            filter_path,
+            replacements_only,
+            winning_only,
+            format,
+            output,
+            split,
+            depth,
+            strip_prefix,
+        } => {
+            let format = resolve_format(format, output.as_deref());
+
+            // Compare against the same resolved directories the full VFS was built from, so
+            // `replace=data` and `data-local` apply to both sides alike
+            let config = config.expect("Remaining is refused with --archive");
+            let mut paths = data_directories(&config);
+            filter_data_paths(&filter_path, &mut paths);
+
+            if paths.is_empty() {
+                eprintln!(
+                    "{}{} isn't, doesn't contain, and isn't inside of any configured data directory, or was removed by replace=data",
+                    print::warn_prefix(),
+                    print::blue(filter_path.display()),
+                );
+            }
+
+            let filtered_vfs = VFS::from_directories_with(&paths, None, &scan_options);
+            let filter_normalized = if scan_options.resolve_symlinks {
+                normalize_path(canonicalize_lenient(&filter_path))
+            } else {
+                normalize_path(&filter_path)
+            };
 
-            write_serialized_vfs(output, format, &files_remaining)?;
+            let files_remaining = if winning_only {
+                // Keep this directory's copy only if the full VFS resolves the same key to it
+                filtered_vfs.tree_filtered(path_display, |file| {
+                    normalize_path(file.path()).starts_with(&filter_normalized)
+                        && file
+                            .source_dir()
+                            .and_then(|root| file.path().strip_prefix(root).ok())
+                            .and_then(|relative_path| vfs.get_file(relative_path))
+                            .is_some_and(|winner| winner.path() == file.path())
+                })
+            } else {
+                vfs.tree_filtered(path_display, |file| {
+                    let path = file.path();
+                    // Check if there's a file whose ending matches this path, but not this exact path
+                    if replacements_only {
+                        filtered_vfs.has_normalized_not_exact(path)
+                    } else {
+                        normalize_path(path).starts_with(&filter_normalized)
+                    }
+                })
+            };
+
+            let files_remaining = strip_tree_prefix(files_remaining, strip_prefix.as_deref());
+            let files_remaining = truncate_tree(files_remaining, depth);
+            write_serialized_vfs(output, format, &files_remaining, split, &render)?;
+        }
+        Commands::Doctor => unreachable!("Doctor is handled before the VFS is built"),
+        Commands::Shadowed { format, output } => {
+            let serialized = render_shadowed(&vfs.shadowed_report(), format, &render)?;
+            write_output(output, &serialized)?;
+        }
+        Commands::Check { manifest } => check_manifest(&vfs, &manifest)?,
+        Commands::Size { top } => print_largest_files(&vfs, top),
+        Commands::ListArchives { format, output } => {
+            let serialized = render_archive_report(&vfs.archive_report(), format, render.layout)?;
+            write_output(output, &serialized)?;
+        }
+        Commands::CaseCheck { format, output } => {
+            let serialized = render_case_conflicts(&vfs.case_conflicts(), format, render.layout)?;
+            write_output(output, &serialized)?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_prefixes_match_whole_directories() {
+        let only = vec![normalize_path("Meshes/"), normalize_path("textures\\tx")];
+
+        assert!(in_scope(Path::new("meshes/a.nif"), &only));
+        assert!(in_scope(Path::new("textures/tx/b.dds"), &only));
+        assert!(!in_scope(Path::new("meshesx/a.nif"), &only));
+        assert!(!in_scope(Path::new("textures/c.dds"), &only));
+        assert!(in_scope(Path::new("anything.esp"), &[]));
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let target = Path::new("meshes/a.nif");
+
+        let mut attempts = 0;
+        let result = with_retries(3, target, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result: io::Result<()> = with_retries(3, target, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+
+        attempts = 0;
+        let result: io::Result<()> = with_retries(2, target, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn anchored_patterns_match_vfs_paths() {
+        let file = VfsFile::from_source_dir(
+            "/mods/Meshes Mod/Meshes/XBase.nif",
+            std::sync::Arc::from(Path::new("/mods/Meshes Mod")),
+        );
+        let matches = |anchor: Anchor, pattern: &str| {
+            regex::Regex::new(&anchor.apply(pattern))
+                .unwrap()
+                .is_match(&anchor.subject(&file))
+        };
+
+        assert!(matches(Anchor::Anywhere, "mod/meshes"));
+        assert!(matches(Anchor::Prefix, "meshes/x"));
+        assert!(!matches(Anchor::Prefix, "mod/meshes"));
+        assert!(matches(Anchor::Exact, "meshes/xbase.nif|textures"));
+        assert!(!matches(Anchor::Exact, "meshes/xbase"));
+    }
+
+    #[test]
+    fn extensionless_files_are_only_excluded_explicitly() {
+        let readme = VfsFile::from("/mods/base/README");
+        let mesh = VfsFile::from("/mods/base/meshes/a.NIF");
+        let excluded = |tokens: &[&str]| -> Vec<String> {
+            tokens.iter().map(|token| token.to_string()).collect()
+        };
+
+        assert!(has_excluded_extension(&mesh, &excluded(&["nif"])));
+        assert!(!has_excluded_extension(&readme, &excluded(&["nif", "txt"])));
+        assert!(has_excluded_extension(&readme, &excluded(&[""])));
+        assert!(!has_excluded_extension(&mesh, &excluded(&[""])));
+    }
+
+    #[test]
+    fn name_case_picks_the_written_spelling() {
+        let key = Path::new("meshes/xbase.nif");
+        let loose = VfsFile::from_source_dir(
+            "/mods/base/Meshes/XBase.nif",
+            std::sync::Arc::from(Path::new("/mods/base")),
+        );
+
+        assert_eq!(NameCase::Lowercase.apply(key, &loose), key);
+        assert_eq!(
+            NameCase::Original.apply(key, &loose),
+            Path::new("Meshes/XBase.nif")
+        );
+
+        // Without a data directory, the spelling would be the whole path on disk
+        let rootless = VfsFile::from("/elsewhere/XBase.nif");
+        assert_eq!(NameCase::Original.apply(key, &rootless), key);
+    }
+
+    #[test]
+    fn prune_refuses_targets_holding_a_source() {
+        let root = std::env::temp_dir().join("vfstool_prune_holding_source");
+        let data_dir = root.join("Morrowind").join("Data Files");
+        fs::create_dir_all(data_dir.join("meshes")).unwrap();
+        fs::write(data_dir.join("meshes/x.nif"), "").unwrap();
+
+        let vfs = VFS::from_directories([&data_dir], None);
+
+        // Collapsing into the install folder would walk, and prune, the data directory itself
+        let holding = source_inside(&vfs, &root.join("Morrowind")).unwrap();
+        assert_eq!(
+            canonicalize_lenient(&holding),
+            canonicalize_lenient(&data_dir)
+        );
+        assert!(source_inside(&vfs, &data_dir).is_some());
+        assert!(source_inside(&vfs, &root.join("Collapsed")).is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn cli_definition_is_valid() {
+        // Clap only checks argument relationships, eg `conflicts_with`, once a command is parsed
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn replaced_data_directories_match_between_vfs_and_remaining() {
+        let root = std::env::temp_dir().join("vfstool_replace_data");
+        let (replaced, kept) = (root.join("replaced"), root.join("kept"));
+
+        for dir in [&replaced, &kept] {
+            fs::create_dir_all(dir.join("meshes")).unwrap();
+            fs::write(dir.join("meshes/a.nif"), "").unwrap();
+        }
+
+        fs::write(
+            root.join("openmw.cfg"),
+            format!(
+                "data=\"{}\"\nreplace=data\ndata=\"{}\"\n",
+                replaced.display(),
+                kept.display()
+            ),
+        )
+        .unwrap();
+
+        let config = load_config(root.clone());
+        assert_eq!(data_directories(&config), vec![kept.clone()]);
+
+        let vfs = construct_vfs(&config, &ScanOptions::default(), false, false);
+
+        let mut filtered = data_directories(&config);
+        filter_data_paths(&replaced, &mut filtered);
+        assert!(filtered.is_empty());
+
+        let mut filtered = data_directories(&config);
+        filter_data_paths(&kept, &mut filtered);
+        let filtered_vfs = VFS::from_directories(&filtered, None);
+
+        assert_eq!(
+            vfs.get_file("meshes/a.nif").unwrap().path(),
+            filtered_vfs.get_file("meshes/a.nif").unwrap().path()
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn filter_data_paths_matches_leniently() {
+        let data_dirs = vec![
+            PathBuf::from("/games/Mods/Armor/Data Files"),
+            PathBuf::from("/games/Mods/Weapons"),
+        ];
+
+        let matching = |filter: &str| {
+            let mut paths = data_dirs.clone();
+            filter_data_paths(Path::new(filter), &mut paths);
+            paths
+        };
+
+        assert_eq!(matching("/games/Mods/Weapons"), data_dirs[1..]);
+        assert_eq!(matching("/games/mods/weapons/"), data_dirs[1..]);
+        assert_eq!(matching("/games/Mods/Weapons/meshes/w"), data_dirs[1..]);
+        assert_eq!(matching("/games/Mods/Armor/"), data_dirs[..1]);
+        assert_eq!(matching("/games/mods"), data_dirs);
+        assert!(matching("/games/Mods/Weap").is_empty());
+    }
+
+    #[test]
+    fn plugin_archives_load_after_fallback_archives() {
+        let root = std::env::temp_dir().join("vfstool_plugin_archives");
+        let data = root.join("data");
+        fs::create_dir_all(&data).unwrap();
+        fs::write(data.join("MyMod.esp"), "").unwrap();
+
+        for name in ["MyMod.bsa", "Explicit.bsa"] {
+            let entry = (
+                PathBuf::from("textures/t.dds"),
+                name.as_bytes().to_vec(),
+                PackCompression::None,
+            );
+            let mut archive = fs::File::create(data.join(name)).unwrap();
+            archives::pack([entry], PackFormat::Tes3, &mut archive).unwrap();
+        }
+
+        fs::write(
+            root.join("openmw.cfg"),
+            format!(
+                "data=\"{}\"\nfallback-archive=Explicit.bsa\ncontent=MyMod.esp\n",
+                data.display()
+            ),
+        )
+        .unwrap();
+        let config = load_config(root.clone());
+
+        assert_eq!(
+            plugin_archives(&config, &["explicit.bsa"]),
+            ["MyMod.bsa", "MyMod.ba2"]
+        );
+        assert!(plugin_archives(&config, &["MYMOD.BSA", "mymod.ba2"]).is_empty());
+
+        let winner = |auto_archives| {
+            let vfs = construct_vfs(&config, &ScanOptions::default(), false, auto_archives);
+            vfs.get_file("textures/t.dds")
+                .unwrap()
+                .parent_archive_name()
+        };
+        assert_eq!(winner(false).as_deref(), Some("Explicit.bsa"));
+        assert_eq!(winner(true).as_deref(), Some("MyMod.bsa"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn format_follows_output_extension() {
+        let resolve = |format, output: Option<&str>| {
+            output_extension(resolve_format(format, output.map(Path::new)))
+        };
+
+        assert_eq!(resolve(None, Some("tree.json")), "json");
+        assert_eq!(resolve(None, Some("tree.YML")), "yaml");
+        assert_eq!(resolve(None, Some("tree.toml")), "toml");
+        assert_eq!(resolve(None, Some("tree.bin")), "yaml");
+        assert_eq!(resolve(None, None), "yaml");
+        assert_eq!(resolve(Some(OutputFormat::Toml), Some("tree.json")), "toml");
+    }
+}
@@ -0,0 +1,105 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs,
+    hint::black_box,
+    io::Read,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use vfstool_lib::VFS;
+
+const FILE_COUNT: usize = 2_000;
+const FILE_SIZE: usize = 512;
+
+/// Counts every allocation, so the two ways of reading can be compared by more than time
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn create_data_dir(root: &Path) {
+    let folder = root.join("scripts");
+    fs::create_dir_all(&folder).unwrap();
+
+    for index in 0..FILE_COUNT {
+        fs::write(
+            folder.join(format!("script{index}.lua")),
+            vec![index as u8; FILE_SIZE],
+        )
+        .unwrap();
+    }
+}
+
+/// Reads every file through a fresh `Vec` each, as a caller of `open` has to
+fn read_with_open(vfs: &VFS) -> usize {
+    vfs.iter()
+        .map(|(_path, file)| {
+            let mut data = Vec::new();
+            file.open().unwrap().read_to_end(&mut data).unwrap();
+            data.len()
+        })
+        .sum()
+}
+
+/// Reads every file into one buffer which is reused across files
+fn read_with_open_into(vfs: &VFS) -> usize {
+    let mut buf = Vec::new();
+
+    vfs.iter()
+        .map(|(_path, file)| {
+            file.open_into(&mut buf).unwrap();
+            buf.len()
+        })
+        .sum()
+}
+
+fn allocations_during(read: impl Fn() -> usize) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(read());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn buffer_reuse(c: &mut Criterion) {
+    let root = std::env::temp_dir().join("vfstool_bench_buffer_reuse");
+    let _ = fs::remove_dir_all(&root);
+    create_data_dir(&root);
+
+    let vfs = VFS::from_directories([&root], None);
+
+    eprintln!(
+        "Allocations reading {FILE_COUNT} files: {} with open, {} with open_into",
+        allocations_during(|| read_with_open(&vfs)),
+        allocations_during(|| read_with_open_into(&vfs)),
+    );
+
+    let mut group = c.benchmark_group("small_file_reads");
+    group.sample_size(20);
+
+    group.bench_with_input(BenchmarkId::new("open", FILE_COUNT), &vfs, |b, vfs| {
+        b.iter(|| black_box(read_with_open(vfs)))
+    });
+
+    group.bench_with_input(BenchmarkId::new("open_into", FILE_COUNT), &vfs, |b, vfs| {
+        b.iter(|| black_box(read_with_open_into(vfs)))
+    });
+
+    group.finish();
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, buffer_reuse);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    fs,
+    hint::black_box,
+    path::{Path, PathBuf},
+};
+use vfstool_lib::VFS;
+
+const FILE_COUNTS: &[usize] = &[1_000, 10_000];
+const DIRS_PER_SET: usize = 4;
+
+/// Lays out `file_count` files across a few data directories, with a nested folder structure
+/// similar to a real `Data Files` tree. Every directory after the first overrides some of the
+/// previous one's files.
+fn create_data_dirs(root: &Path, file_count: usize) -> Vec<PathBuf> {
+    let per_dir = file_count / DIRS_PER_SET;
+
+    (0..DIRS_PER_SET)
+        .map(|dir_index| {
+            let dir = root.join(format!("data{dir_index}"));
+            let start = dir_index * per_dir / 2;
+
+            for file_index in start..start + per_dir {
+                let folder = dir
+                    .join(["meshes", "textures", "sound", "icons"][file_index % 4])
+                    .join(format!("group{}", file_index % 32));
+
+                fs::create_dir_all(&folder).unwrap();
+                fs::write(folder.join(format!("file{file_index}.dat")), b"").unwrap();
+            }
+
+            dir
+        })
+        .collect()
+}
+
+fn construction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("construction");
+    group.sample_size(20);
+
+    for &file_count in FILE_COUNTS {
+        let root = std::env::temp_dir().join(format!("vfstool_bench_{file_count}"));
+        let _ = fs::remove_dir_all(&root);
+        let dirs = create_data_dirs(&root, file_count);
+
+        group.bench_with_input(
+            BenchmarkId::new("scan_only", file_count),
+            &dirs,
+            |b, dirs| b.iter(|| black_box(VFS::scan_only(dirs))),
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("from_directories", file_count),
+            &dirs,
+            |b, dirs| b.iter(|| black_box(VFS::from_directories(dirs, None))),
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, construction);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{hint::black_box, path::PathBuf};
+use vfstool_lib::{VFS, VfsFile, normalize_path, normalize_str};
+
+const FILE_COUNT: usize = 100_000;
+const TOP_LEVEL_DIRS: &[&str] = &[
+    "Meshes", "Textures", "Sound", "Icons", "Music", "BookArt", "Fonts", "Splash",
+];
+/// Queries as a user would type them, matching many files, a few, and none at all
+const QUERIES: &[&str] = &["Textures\\", "Group8\\Sub3", "NoSuchFile.NIF"];
+
+/// Spells each of `file_count` paths the way mods ship them, with mixed case and backslashes
+fn raw_paths(file_count: usize) -> Vec<String> {
+    (0..file_count)
+        .map(|index| {
+            format!(
+                "{}\\Group{}\\Sub{}\\File{index}.DAT",
+                TOP_LEVEL_DIRS[index % TOP_LEVEL_DIRS.len()],
+                index % 64,
+                index % 16
+            )
+        })
+        .collect()
+}
+
+/// Builds a VFS holding every path in `raw_paths`, without touching the disk
+fn synthetic_vfs(raw_paths: &[String]) -> VFS {
+    raw_paths
+        .iter()
+        .map(|raw| {
+            let file = VfsFile::from(PathBuf::from("/data").join(raw));
+            (normalize_path(raw), file)
+        })
+        .collect()
+}
+
+/// What `paths_matching` did before `normalize_str`: normalize through a `PathBuf`, then turn it
+/// straight back into a `String`
+fn through_path(raw: &str) -> String {
+    normalize_path(raw).to_string_lossy().into_owned()
+}
+
+fn normalize(c: &mut Criterion) {
+    let raw_paths = raw_paths(FILE_COUNT);
+
+    let mut group = c.benchmark_group("normalize_every_path");
+
+    group.bench_function("normalize_path", |b| {
+        b.iter(|| {
+            black_box(
+                raw_paths
+                    .iter()
+                    .map(|raw| through_path(raw).len())
+                    .sum::<usize>(),
+            )
+        })
+    });
+
+    group.bench_function("normalize_str", |b| {
+        b.iter(|| {
+            black_box(
+                raw_paths
+                    .iter()
+                    .map(|raw| normalize_str(raw).len())
+                    .sum::<usize>(),
+            )
+        })
+    });
+
+    group.finish();
+
+    // The query is only normalized once per call, so over a large file_map the scan dominates
+    let vfs = synthetic_vfs(&raw_paths);
+    let mut group = c.benchmark_group("paths_matching");
+
+    for query in QUERIES {
+        group.bench_with_input(
+            BenchmarkId::new("normalize_path", query),
+            query,
+            |b, query| {
+                b.iter(|| {
+                    let query = through_path(query);
+                    black_box(
+                        vfs.iter()
+                            .filter(|(path, _file)| path.to_string_lossy().contains(&query))
+                            .count(),
+                    )
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("normalize_str", query),
+            query,
+            |b, query| b.iter(|| black_box(vfs.paths_matching(query).count())),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, normalize);
+criterion_main!(benches);
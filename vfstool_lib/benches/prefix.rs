@@ -0,0 +1,65 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{hint::black_box, path::PathBuf};
+use vfstool_lib::{VFS, VfsFile};
+
+const FILE_COUNT: usize = 100_000;
+const TOP_LEVEL_DIRS: &[&str] = &[
+    "meshes", "textures", "sound", "icons", "music", "bookart", "fonts", "splash",
+];
+/// A whole top-level folder, one of its groups, and a single file
+const PREFIXES: &[&str] = &[
+    "textures",
+    "meshes/group8",
+    "sound/group10/sub10/file10.dat",
+];
+
+/// Builds a VFS of `file_count` loose files spread over the usual top-level folders, without
+/// touching the disk
+fn synthetic_vfs(file_count: usize) -> VFS {
+    (0..file_count)
+        .map(|index| {
+            let relative = PathBuf::from(format!(
+                "{}/group{}/sub{}/file{index}.dat",
+                TOP_LEVEL_DIRS[index % TOP_LEVEL_DIRS.len()],
+                index % 64,
+                index % 16
+            ));
+            let file = VfsFile::from(PathBuf::from("/data").join(&relative));
+
+            (relative, file)
+        })
+        .collect()
+}
+
+fn prefix(c: &mut Criterion) {
+    let vfs = synthetic_vfs(FILE_COUNT);
+    vfs.build_prefix_index();
+
+    let mut group = c.benchmark_group("paths_with");
+
+    for prefix in PREFIXES {
+        group.bench_with_input(BenchmarkId::new("linear", prefix), prefix, |b, prefix| {
+            b.iter(|| black_box(vfs.paths_with(prefix).count()))
+        });
+
+        group.bench_with_input(BenchmarkId::new("indexed", prefix), prefix, |b, prefix| {
+            b.iter(|| black_box(vfs.paths_with_indexed(prefix).count()))
+        });
+    }
+
+    group.finish();
+
+    c.bench_function("build_prefix_index", |b| {
+        b.iter_batched(
+            || synthetic_vfs(FILE_COUNT),
+            |vfs| {
+                vfs.build_prefix_index();
+                vfs
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, prefix);
+criterion_main!(benches);
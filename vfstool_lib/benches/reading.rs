@@ -0,0 +1,82 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    fs::{self, File},
+    hint::black_box,
+    io::Read,
+    path::Path,
+};
+use vfstool_lib::VFS;
+
+const FILE_COUNT: usize = 500;
+const FILE_SIZE: usize = 64 * 1024;
+/// Reads of a few bytes each, like walking the block headers of a NIF
+const HEADER_READS: usize = 256;
+const READ_SIZE: usize = 4;
+
+/// Sums the first `HEADER_READS` small reads of `reader`, the access pattern of header parsing
+fn parse_header(mut reader: impl Read) -> u64 {
+    let mut buf = [0u8; READ_SIZE];
+    let mut sum = 0u64;
+
+    for _ in 0..HEADER_READS {
+        reader.read_exact(&mut buf).unwrap();
+        sum += u64::from(u32::from_le_bytes(buf));
+    }
+
+    sum
+}
+
+fn create_data_dir(root: &Path) {
+    let folder = root.join("meshes");
+    fs::create_dir_all(&folder).unwrap();
+
+    for index in 0..FILE_COUNT {
+        fs::write(
+            folder.join(format!("file{index}.nif")),
+            vec![index as u8; FILE_SIZE],
+        )
+        .unwrap();
+    }
+}
+
+fn reading(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header_parsing");
+    group.sample_size(20);
+
+    let root = std::env::temp_dir().join("vfstool_bench_reading");
+    let _ = fs::remove_dir_all(&root);
+    create_data_dir(&root);
+
+    let vfs = VFS::from_directories([&root], None);
+
+    // Reads straight from `File`, which is what `open` returned before it was buffered
+    group.bench_with_input(
+        BenchmarkId::new("unbuffered_file", FILE_COUNT),
+        &vfs,
+        |b, vfs| {
+            b.iter(|| {
+                black_box(
+                    vfs.iter()
+                        .map(|(_path, file)| parse_header(File::open(file.path()).unwrap()))
+                        .sum::<u64>(),
+                )
+            })
+        },
+    );
+
+    group.bench_with_input(BenchmarkId::new("vfs_open", FILE_COUNT), &vfs, |b, vfs| {
+        b.iter(|| {
+            black_box(
+                vfs.iter()
+                    .map(|(_path, file)| parse_header(file.open().unwrap()))
+                    .sum::<u64>(),
+            )
+        })
+    });
+
+    group.finish();
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(benches, reading);
+criterion_main!(benches);
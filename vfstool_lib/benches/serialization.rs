@@ -0,0 +1,139 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    io,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use vfstool_lib::{SerializeLayout, SerializeType, VFS, VfsFile};
+
+const FILE_COUNTS: &[usize] = &[10_000, 100_000];
+
+/// Wraps the system allocator to track the most memory held at once
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+/// Runs `f`, returning how far above the starting point memory use peaked
+fn peak_bytes(f: impl FnOnce()) -> usize {
+    let start = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(start, Ordering::Relaxed);
+    f();
+
+    PEAK.load(Ordering::Relaxed) - start
+}
+
+/// Builds a VFS in memory with `file_count` files spread across a few levels of folders, similar
+/// to a large modlist's `Data Files`
+fn synthetic_vfs(file_count: usize) -> VFS {
+    (0..file_count)
+        .map(|index| {
+            let relative = format!(
+                "{}/group{}/set{}/file{index}.dat",
+                ["meshes", "textures", "sound", "icons"][index % 4],
+                index % 64,
+                index % 16
+            );
+            (PathBuf::from(&relative), VfsFile::from(relative))
+        })
+        .collect()
+}
+
+/// Compares JSON Lines written on the global thread pool against a single thread, with the
+/// serial, structured JSON writer for reference. Also reports the peak memory of serializing to
+/// a `String` against streaming into a writer, since criterion only measures time.
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialization");
+    group.sample_size(20);
+
+    let single_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    for &file_count in FILE_COUNTS {
+        let tree = synthetic_vfs(file_count).tree(true);
+
+        for format in [SerializeType::Json, SerializeType::Yaml] {
+            let to_string = peak_bytes(|| {
+                black_box(VFS::serialize_from_tree(&tree, format).unwrap());
+            });
+            let to_writer = peak_bytes(|| {
+                VFS::serialize_to_writer(&tree, format, SerializeLayout::Standard, io::sink())
+                    .unwrap();
+            });
+
+            println!(
+                "serialization/peak_memory/{format:?}/{file_count}: \
+                 {to_string} bytes to a String, {to_writer} bytes to a writer"
+            );
+        }
+
+        group.bench_with_input(BenchmarkId::new("json", file_count), &tree, |b, tree| {
+            b.iter(|| black_box(VFS::serialize_from_tree(tree, SerializeType::Json)))
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("json_to_writer", file_count),
+            &tree,
+            |b, tree| {
+                b.iter(|| {
+                    VFS::serialize_to_writer(
+                        tree,
+                        SerializeType::Json,
+                        SerializeLayout::Standard,
+                        io::sink(),
+                    )
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("json_lines_serial", file_count),
+            &tree,
+            |b, tree| {
+                b.iter(|| {
+                    single_thread.install(|| {
+                        black_box(VFS::serialize_from_tree(tree, SerializeType::JsonLines))
+                    })
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("json_lines_parallel", file_count),
+            &tree,
+            |b, tree| {
+                b.iter(|| black_box(VFS::serialize_from_tree(tree, SerializeType::JsonLines)))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::{hint::black_box, path::PathBuf};
+use vfstool_lib::{PathDisplay, VFS, VfsFile};
+
+const FILE_COUNTS: &[usize] = &[20_000, 200_000];
+const TOP_LEVEL_DIRS: &[&str] = &[
+    "meshes", "textures", "sound", "icons", "music", "bookart", "fonts", "splash",
+];
+
+/// Builds a VFS of `file_count` loose files spread over the usual top-level folders, without
+/// touching the disk
+fn synthetic_vfs(file_count: usize) -> VFS {
+    (0..file_count)
+        .map(|index| {
+            let relative = PathBuf::from(format!(
+                "{}/group{}/file{index}.dat",
+                TOP_LEVEL_DIRS[index % TOP_LEVEL_DIRS.len()],
+                index % 64
+            ));
+            let file = VfsFile::from(PathBuf::from("/data").join(&relative));
+
+            (relative, file)
+        })
+        .collect()
+}
+
+fn tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree");
+    group.sample_size(20);
+
+    for &file_count in FILE_COUNTS {
+        let vfs = synthetic_vfs(file_count);
+
+        group.bench_with_input(BenchmarkId::new("tree", file_count), &vfs, |b, vfs| {
+            b.iter(|| black_box(vfs.tree(PathDisplay::Relative)))
+        });
+
+        group.bench_with_input(BenchmarkId::new("par_tree", file_count), &vfs, |b, vfs| {
+            b.iter(|| black_box(vfs.par_tree(PathDisplay::Relative)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, tree);
+criterion_main!(benches);
@@ -1,8 +1,16 @@
-use crate::{DisplayTree, VfsFile};
-use std::collections::BTreeMap;
+use crate::{DisplayTree, VfsFile, normalize_str};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
 
 #[cfg(feature = "serialize")]
-use serde::{Serialize, Serializer, ser::SerializeMap};
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+};
 
 /// Represents a directory node in the Virtual File System (VFS).
 ///
@@ -14,7 +22,7 @@ use serde::{Serialize, Serializer, ser::SerializeMap};
 ///
 /// ```
 /// use std::collections::BTreeMap;
-/// use dw_vfs_lib::{directory_node::DirectoryNode, VfsFile};
+/// use vfstool_lib::{directory_node::DirectoryNode, VfsFile};
 ///
 /// let mut node = DirectoryNode::new();
 ///
@@ -31,10 +39,15 @@ use serde::{Serialize, Serializer, ser::SerializeMap};
 /// ```
 ///
 /// The `sort` and `filter` methods allow organizing and modifying the directory contents.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DirectoryNode {
     pub files: Vec<VfsFile>,
     pub subdirs: DisplayTree,
+    /// Number of files below this node which were dropped by `truncate`
+    pub truncated_files: usize,
+    /// Whether each file is shown along with the resolved source it's read from, as set by
+    /// `PathDisplay::Both`
+    pub show_sources: bool,
 }
 
 impl DirectoryNode {
@@ -42,14 +55,204 @@ impl DirectoryNode {
         Self {
             files: Vec::new(),
             subdirs: BTreeMap::new(),
+            truncated_files: 0,
+            show_sources: false,
         }
     }
 
+    /// Total number of files in this node and every subdirectory, including truncated ones
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+            + self.truncated_files
+            + self
+                .subdirs
+                .values()
+                .map(DirectoryNode::file_count)
+                .sum::<usize>()
+    }
+
+    /// Every file in this node or below it which has no file name, eg a malformed archive entry.
+    /// The text form of a tree leaves these out, so callers can use this to report them.
+    pub fn nameless_files(&self) -> Vec<&VfsFile> {
+        let mut nameless: Vec<&VfsFile> = self
+            .files
+            .iter()
+            .filter(|file| file.path().file_name().is_none())
+            .collect();
+
+        for subdir in self.subdirs.values() {
+            nameless.extend(subdir.nameless_files());
+        }
+
+        nameless
+    }
+
+    /// How many levels of directories there are below this node, so `truncate(node.depth())`
+    /// keeps everything. A node without subdirectories has a depth of 0.
+    pub fn depth(&self) -> usize {
+        self.subdirs
+            .values()
+            .map(|subdir| subdir.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Drops everything more than `depth` directories below this node, keeping only a count of
+    /// the files which were removed. A depth of 0 collapses this node itself into a count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::{directory_node::DirectoryNode, VfsFile};
+    ///
+    /// let mut node = DirectoryNode::new();
+    /// let mut subdir = DirectoryNode::new();
+    /// subdir.files.push(VfsFile::from("sub/nested.txt"));
+    /// node.subdirs.insert("sub".into(), subdir);
+    ///
+    /// node.truncate(0);
+    ///
+    /// assert!(node.subdirs.is_empty());
+    /// assert_eq!(node.truncated_files, 1);
+    /// ```
+    pub fn truncate(&mut self, depth: usize) {
+        if depth == 0 {
+            self.truncated_files = self.file_count();
+            self.files.clear();
+            self.subdirs.clear();
+        } else {
+            self.subdirs
+                .values_mut()
+                .for_each(|subdir| subdir.truncate(depth - 1));
+        }
+    }
+
+    /// Collects the path of every file in this node or below it whose name matches `name`,
+    /// ignoring case the same way VFS keys do. Files keep the paths they were stored with, so for
+    /// nodes built by `VFS::tree` these are their full paths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use vfstool_lib::{directory_node::DirectoryNode, VfsFile};
+    ///
+    /// let mut node = DirectoryNode::new();
+    /// let mut subdir = DirectoryNode::new();
+    /// subdir.files.push(VfsFile::from("meshes/XBase_Anim.nif"));
+    /// node.subdirs.insert("meshes".into(), subdir);
+    ///
+    /// assert_eq!(node.find("xbase_anim.nif"), vec![PathBuf::from("meshes/XBase_Anim.nif")]);
+    /// ```
+    pub fn find(&self, name: &str) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        self.find_normalized(&normalize_str(name), &mut found);
+        found
+    }
+
+    fn find_normalized(&self, name: &str, found: &mut Vec<PathBuf>) {
+        found.extend(
+            self.files
+                .iter()
+                .filter(|file| {
+                    file.file_name()
+                        .is_some_and(|file_name| same_name(file_name, name))
+                })
+                .map(|file| file.path().to_path_buf()),
+        );
+
+        for subdir in self.subdirs.values() {
+            subdir.find_normalized(name, found);
+        }
+    }
+
+    /// Descends through subdirectories one component of `path` at a time, ignoring case the same
+    /// way VFS keys do.
+    /// Each component is matched against the last component of a subdirectory's key, so this
+    /// works both for trees built by `VFS::tree`, which key subdirectories by their full path,
+    /// and for hand-built ones keyed by name. An empty path returns this node.
+    pub fn get_dir(&self, path: &Path) -> Option<&DirectoryNode> {
+        path.components().try_fold(self, |node, component| {
+            let component = normalize_str(&component.as_os_str().to_string_lossy());
+
+            node.subdirs.iter().find_map(|(key, subdir)| {
+                key.file_name()
+                    .is_some_and(|name| same_name(name, &component))
+                    .then_some(subdir)
+            })
+        })
+    }
+
+    /// Re-roots this node at the subdirectory `prefix` below it, matched the same way as by
+    /// `get_dir`. Only what's inside of that subdirectory is kept, and everything below it is
+    /// re-keyed relative to it, so the prefix is gone from every path. If there's no such
+    /// subdirectory, the node is left empty.
+    pub fn strip_prefix(&mut self, prefix: &Path) {
+        let empty = DirectoryNode {
+            show_sources: self.show_sources,
+            ..DirectoryNode::new()
+        };
+        let mut node = std::mem::replace(self, empty);
+        let mut stripped = None;
+
+        // Roots are already where the tree starts, eg `/` for absolute paths
+        for component in prefix
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+        {
+            let component = normalize_str(&component.as_os_str().to_string_lossy());
+            let key = node.subdirs.keys().find(|key| {
+                key.file_name()
+                    .is_some_and(|name| same_name(name, &component))
+            });
+
+            let Some(key) = key.cloned() else {
+                return;
+            };
+
+            node = node
+                .subdirs
+                .remove(&key)
+                .expect("Key was just found in the subdirectories");
+            stripped = Some(key);
+        }
+
+        if let Some(stripped) = stripped {
+            node.rekey(&stripped);
+        }
+
+        *self = node;
+    }
+
+    /// Removes `base` from the front of every subdirectory key below this node
+    fn rekey(&mut self, base: &Path) {
+        self.subdirs = std::mem::take(&mut self.subdirs)
+            .into_iter()
+            .map(|(path, mut subdir)| {
+                subdir.rekey(base);
+                let path = path
+                    .strip_prefix(base)
+                    .map_or(path.clone(), Path::to_path_buf);
+                (path, subdir)
+            })
+            .collect();
+    }
+
     /// Sorts the files in the directory by name and recursively sorts subdirectories.
     ///
     /// This ensures files appear in a consistent order.
     /// Useful when serializing or displaying directory contents.
+    ///
+    /// Files whose names only differ by case, eg `Foo.nif` and `foo.nif`, are the same file to
+    /// the VFS, so only the one added to `files` last is kept, the same way later sources win.
     pub fn sort(&mut self) {
+        let mut seen = std::collections::HashSet::new();
+        self.files.reverse();
+        self.files.retain(|file| {
+            file.file_name()
+                .is_none_or(|name| seen.insert(crate::normalize_str(&name.to_string_lossy())))
+        });
+
         self.files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
         self.subdirs.values_mut().for_each(|dir| dir.sort());
     }
@@ -65,14 +268,14 @@ impl DirectoryNode {
     ///
     /// ```
     /// # use std::ffi::OsStr;
-    /// use dw_vfs_lib::{directory_node::DirectoryNode, VfsFile};
+    /// use vfstool_lib::{directory_node::DirectoryNode, VfsFile};
     ///
     /// let mut node = DirectoryNode::new();
     ///
     /// node.files.push(VfsFile::from("keep.txt"));
     /// node.files.push(VfsFile::from("remove.txt"));
     ///
-    /// node.filter(&|file| file.file_name() == Some("keep.txt"));
+    /// node.filter(&|file| file.file_name() == Some(OsStr::new("keep.txt")));
     ///
     /// assert_eq!(node.files.len(), 1);
     /// ```
@@ -83,9 +286,31 @@ impl DirectoryNode {
         self.files.retain(file_filter);
         self.subdirs.retain(|_path, subdir| {
             subdir.filter(file_filter);
-            !subdir.files.is_empty() || !subdir.subdirs.is_empty()
+            !subdir.files.is_empty() || !subdir.subdirs.is_empty() || subdir.truncated_files > 0
         });
     }
+
+    /// Re-keys a deserialized node so that subdirectories and files carry their full path below
+    /// `parent`, as they do when built by `VFS::tree`. Serialized trees only store the last
+    /// component of each name.
+    #[cfg(feature = "serialize")]
+    pub(crate) fn rebase(&mut self, parent: &Path) {
+        self.files = self
+            .files
+            .iter()
+            .filter_map(|file| file.file_name())
+            .map(|name| VfsFile::from(parent.join(name)))
+            .collect();
+
+        self.subdirs = std::mem::take(&mut self.subdirs)
+            .into_iter()
+            .map(|(name, mut subdir)| {
+                let path = parent.join(name);
+                subdir.rebase(&path);
+                (path, subdir)
+            })
+            .collect();
+    }
 }
 
 #[cfg(feature = "serialize")]
@@ -94,39 +319,173 @@ impl Serialize for DirectoryNode {
     where
         S: Serializer,
     {
+        SerializableNode {
+            node: self,
+            priority: None,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Whether a path component names the same thing as `normalized`, which must already have gone
+/// through `normalize_str`
+fn same_name(name: &OsStr, normalized: &str) -> bool {
+    normalize_str(&name.to_string_lossy()) == normalized
+}
+
+/// Looks up the load-order priority of a file's source
+#[cfg(feature = "serialize")]
+pub(crate) type PriorityFn<'a> = &'a (dyn Fn(&VfsFile) -> Option<usize> + Sync);
+
+/// Serialized form of a `DirectoryNode`. Files are written by name, or as `{name, priority}`
+/// objects when a priority lookup is given.
+#[cfg(feature = "serialize")]
+pub(crate) struct SerializableNode<'a> {
+    pub(crate) node: &'a DirectoryNode,
+    pub(crate) priority: Option<PriorityFn<'a>>,
+}
+
+/// A file written as an object, for when there's more to say about it than its name
+#[cfg(feature = "serialize")]
+#[derive(Serialize)]
+struct DetailedFile<'a> {
+    name: std::borrow::Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+/// A file as read back from the `.` key, which may be either form `Serialize` writes
+#[cfg(feature = "serialize")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredFile {
+    Name(String),
+    Detailed { name: String },
+}
+
+#[cfg(feature = "serialize")]
+impl Serialize for SerializableNode<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let node = self.node;
+        // Files and counts go first, since TOML can't have plain values after a table
         let mut map = serializer.serialize_map(Some(
-            self.subdirs.len() + if self.files.is_empty() { 0 } else { 1 },
+            node.subdirs.len()
+                + usize::from(!node.files.is_empty())
+                + usize::from(node.truncated_files > 0),
         ))?;
 
-        if !self.files.is_empty() {
-            map.serialize_entry(
-                ".",
-                &self
-                    .files
-                    .iter()
-                    .filter_map(|file| file.file_name())
-                    .map(|file| file.to_string_lossy())
-                    .collect::<Vec<std::borrow::Cow<'_, str>>>(),
-            )?;
+        if !node.files.is_empty() {
+            let named_files = node
+                .files
+                .iter()
+                .filter_map(|file| file.file_name().map(|name| (file, name.to_string_lossy())));
+
+            if self.priority.is_none() && !node.show_sources {
+                map.serialize_entry(
+                    ".",
+                    &named_files
+                        .map(|(_file, name)| name)
+                        .collect::<Vec<std::borrow::Cow<'_, str>>>(),
+                )?
+            } else {
+                map.serialize_entry(
+                    ".",
+                    &named_files
+                        .map(|(file, name)| DetailedFile {
+                            name,
+                            priority: self.priority.and_then(|priority| priority(file)),
+                            source: node.show_sources.then(|| file.canonical_path()),
+                        })
+                        .collect::<Vec<DetailedFile<'_>>>(),
+                )?
+            }
+        }
+
+        if node.truncated_files > 0 {
+            map.serialize_entry("...", &format!("{} files", node.truncated_files))?;
         }
 
-        for (dir_name, subdir) in &self.subdirs {
+        for (dir_name, subdir) in &node.subdirs {
             let dir_key = dir_name.file_name().unwrap_or_default().to_string_lossy();
 
-            map.serialize_entry(&dir_key, subdir)?;
+            map.serialize_entry(
+                &dir_key,
+                &SerializableNode {
+                    node: subdir,
+                    priority: self.priority,
+                },
+            )?;
         }
 
         map.end()
     }
 }
 
+/// Reads back the layout written by `Serialize`: the `.` key holds the names of files in this
+/// directory, either as strings or as objects with a `name`, `...` holds the count left by
+/// `truncate`, and every other key is a subdirectory. Priorities and sources aren't kept.
+/// Names are stored as-is, so `rebase` must be used to restore full paths.
+#[cfg(feature = "serialize")]
+impl<'de> Deserialize<'de> for DirectoryNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = DirectoryNode;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a map of file lists and subdirectories")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut node = DirectoryNode::new();
+
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "." {
+                        node.files
+                            .extend(
+                                map.next_value::<Vec<StoredFile>>()?
+                                    .into_iter()
+                                    .map(|file| match file {
+                                        StoredFile::Name(name) | StoredFile::Detailed { name } => {
+                                            VfsFile::from(name)
+                                        }
+                                    }),
+                            );
+                    } else if key == "..." {
+                        let summary = map.next_value::<String>()?;
+                        node.truncated_files = summary
+                            .trim_end_matches(" files")
+                            .parse()
+                            .map_err(serde::de::Error::custom)?;
+                    } else {
+                        node.subdirs.insert(key.into(), map.next_value()?);
+                    }
+                }
+
+                Ok(node)
+            }
+        }
+
+        deserializer.deserialize_map(NodeVisitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json;
-    use serde_yaml_with_quirks;
     use std::path::PathBuf;
-    use toml;
 
     fn sample_directory_node() -> DirectoryNode {
         let mut root = DirectoryNode::new();
@@ -159,6 +518,74 @@ mod tests {
         root
     }
 
+    #[test]
+    fn depth_counts_nested_directories() {
+        let mut root = sample_directory_node();
+        assert_eq!(root.depth(), 2);
+        assert_eq!(root.subdirs[&PathBuf::from("subdir1")].depth(), 1);
+        assert_eq!(DirectoryNode::new().depth(), 0);
+
+        root.truncate(1);
+        assert_eq!(root.depth(), 1);
+    }
+
+    #[test]
+    fn sort_keeps_one_file_per_name() {
+        let mut node = DirectoryNode::new();
+        node.files.push(VfsFile::from("/base/meshes/Foo.nif"));
+        node.files.push(VfsFile::from("/base/meshes/bar.nif"));
+        node.files.push(VfsFile::from("/patch/meshes/foo.nif"));
+
+        node.sort();
+
+        // The last copy pushed wins, as the highest priority source does in the VFS
+        let paths: Vec<_> = node.files.iter().map(VfsFile::path).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/base/meshes/bar.nif"),
+                Path::new("/patch/meshes/foo.nif")
+            ]
+        );
+        #[cfg(feature = "serialize")]
+        assert_eq!(
+            serde_json::to_string(&node).unwrap(),
+            r#"{".":["bar.nif","foo.nif"]}"#
+        );
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn truncated_nodes_serialize_as_counts() {
+        let mut node = sample_directory_node();
+        let total = node.file_count();
+
+        node.truncate(2);
+
+        assert_eq!(node.file_count(), total);
+        for subdir in node.subdirs.values() {
+            assert_eq!(subdir.files.len(), 3);
+
+            let child = subdir.subdirs.values().next().unwrap();
+            assert!(child.files.is_empty());
+            assert_eq!(child.truncated_files, 3);
+        }
+
+        node.truncate(1);
+        for subdir in node.subdirs.values() {
+            assert!(subdir.files.is_empty() && subdir.subdirs.is_empty());
+            assert_eq!(subdir.truncated_files, 6);
+        }
+
+        node.truncate(0);
+        let json_output = serde_json::to_string(&node).expect("JSON serialization failed");
+        assert_eq!(json_output, r#"{"...":"18 files"}"#);
+
+        let loaded: DirectoryNode = serde_json::from_str(&json_output).unwrap();
+        assert_eq!(loaded.truncated_files, 18);
+    }
+
+    #[cfg(feature = "serialize")]
     #[test]
     fn serialize_to_json() {
         let node = sample_directory_node();
@@ -214,6 +641,7 @@ mod tests {
         assert_eq!(json_output, expected);
     }
 
+    #[cfg(feature = "serialize")]
     #[test]
     fn serialize_to_toml() {
         let node = sample_directory_node();
@@ -266,55 +694,165 @@ mod tests {
         assert_eq!(toml_output, expected);
     }
 
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn toml_with_root_files_round_trips() {
+        let mut root = sample_directory_node();
+        root.files = vec![VfsFile::from("Morrowind.esm"), VfsFile::from("readme.txt")];
+        root.subdirs
+            .get_mut(&PathBuf::from("subdir2"))
+            .unwrap()
+            .truncate(0);
+
+        let toml_output = toml::to_string_pretty(&root).expect("TOML serialization failed");
+        let parsed: toml::Table =
+            toml::from_str(&toml_output).expect("Output should be valid TOML");
+        assert_eq!(parsed["."].as_array().unwrap().len(), 2);
+        assert_eq!(parsed["subdir2"]["..."].as_str(), Some("6 files"));
+
+        let loaded: DirectoryNode = toml::from_str(&toml_output).unwrap();
+        assert_eq!(loaded.files.len(), 2);
+        assert_eq!(loaded.file_count(), root.file_count());
+        assert_eq!(toml::to_string_pretty(&loaded).unwrap(), toml_output);
+
+        let priority = |_: &VfsFile| Some(0);
+        let prioritized = toml::to_string(&SerializableNode {
+            node: &root,
+            priority: Some(&priority),
+        })
+        .expect("Prioritized TOML serialization failed");
+        let parsed: toml::Table =
+            toml::from_str(&prioritized).expect("Output should be valid TOML");
+        assert_eq!(parsed["."][1]["name"].as_str(), Some("readme.txt"));
+        assert_eq!(parsed["subdir1"]["."][0]["priority"].as_integer(), Some(0));
+    }
+
+    #[cfg(feature = "serialize")]
     #[test]
     fn serialize_to_yaml() {
         let node = sample_directory_node();
-        let yaml_output =
-            serde_yaml_with_quirks::to_string(&node).expect("YAML serialization failed");
+        let yaml_output = serde_yaml::to_string(&node).expect("YAML serialization failed");
 
         println!("{}", &yaml_output);
 
-        let expected = r#"---
-subdir1:
-  ".":
-    - file1_1.txt
-    - file1_2.txt
-    - file1_3.txt
+        let expected = r#"subdir1:
+  .:
+  - file1_1.txt
+  - file1_2.txt
+  - file1_3.txt
   child_subdir1:
-    ".":
-      - nested_file1_1.txt
-      - nested_file1_2.txt
-      - nested_file1_3.txt
+    .:
+    - nested_file1_1.txt
+    - nested_file1_2.txt
+    - nested_file1_3.txt
 subdir2:
-  ".":
-    - file2_1.txt
-    - file2_2.txt
-    - file2_3.txt
+  .:
+  - file2_1.txt
+  - file2_2.txt
+  - file2_3.txt
   child_subdir2:
-    ".":
-      - nested_file2_1.txt
-      - nested_file2_2.txt
-      - nested_file2_3.txt
+    .:
+    - nested_file2_1.txt
+    - nested_file2_2.txt
+    - nested_file2_3.txt
 subdir3:
-  ".":
-    - file3_1.txt
-    - file3_2.txt
-    - file3_3.txt
+  .:
+  - file3_1.txt
+  - file3_2.txt
+  - file3_3.txt
   child_subdir3:
-    ".":
-      - nested_file3_1.txt
-      - nested_file3_2.txt
-      - nested_file3_3.txt
+    .:
+    - nested_file3_1.txt
+    - nested_file3_2.txt
+    - nested_file3_3.txt
 "#;
 
         assert_eq!(yaml_output, expected);
     }
 
+    #[test]
+    fn find_and_get_dir() {
+        let root = sample_directory_node();
+
+        assert_eq!(
+            root.find("NESTED_FILE2_3.txt"),
+            vec![PathBuf::from("nested_file2_3.txt")]
+        );
+        assert!(root.find("missing.txt").is_empty());
+
+        let child = root
+            .get_dir(Path::new("subdir3/Child_Subdir3"))
+            .expect("Nested directory should be found");
+        assert_eq!(child.files.len(), 3);
+        assert_eq!(child.find("nested_file3_1.txt").len(), 1);
+
+        assert!(root.get_dir(Path::new("subdir3/child_subdir1")).is_none());
+        assert_eq!(root.get_dir(Path::new("")).unwrap().subdirs.len(), 3);
+    }
+
+    #[test]
+    fn lookups_fold_non_ascii_case() {
+        let mut root = DirectoryNode::new();
+        let mut textures = DirectoryNode::new();
+        textures.files.push(VfsFile::from("Тексты/Évé.dds"));
+        root.subdirs.insert("Тексты".into(), textures);
+
+        assert_eq!(root.find("ÉVÉ.DDS"), vec![PathBuf::from("Тексты/Évé.dds")]);
+        assert!(root.get_dir(Path::new("тексты")).is_some());
+
+        root.strip_prefix(Path::new("ТЕКСТЫ"));
+        assert_eq!(root.files.len(), 1);
+    }
+
+    #[test]
+    fn strip_prefix_reroots_node() {
+        // Keyed by full path, the way `VFS::tree` builds them
+        let mut root = DirectoryNode::new();
+        let mut textures = DirectoryNode::new();
+        let mut terrain = DirectoryNode::new();
+        terrain
+            .files
+            .push(VfsFile::from("textures/terrain/tx_ash.dds"));
+        textures.files.push(VfsFile::from("textures/tx_bone.dds"));
+        textures.subdirs.insert("textures/terrain".into(), terrain);
+        root.subdirs.insert("textures".into(), textures);
+        root.subdirs.insert("meshes".into(), DirectoryNode::new());
+        root.files.push(VfsFile::from("readme.txt"));
+
+        let mut stripped = root.clone();
+        stripped.strip_prefix(Path::new("Textures/"));
+        assert_eq!(stripped.files.len(), 1);
+        assert_eq!(
+            stripped.subdirs.keys().collect::<Vec<_>>(),
+            [Path::new("terrain")]
+        );
+        assert_eq!(
+            stripped.get_dir(Path::new("terrain")).unwrap().files.len(),
+            1
+        );
+
+        let mut nested = root.clone();
+        nested.strip_prefix(Path::new("textures/terrain"));
+        assert_eq!(nested.file_count(), 1);
+        assert!(nested.subdirs.is_empty());
+
+        let mut missing = root.clone();
+        missing.strip_prefix(Path::new("sound"));
+        assert_eq!(missing.file_count(), 0);
+
+        let mut unchanged = root.clone();
+        unchanged.strip_prefix(Path::new(""));
+        assert_eq!(unchanged.file_count(), root.file_count());
+    }
+
     #[test]
     fn test_directory_node_filter() {
         let mut root = sample_directory_node();
 
-        root.filter(&|file| file.file_name().map_or(false, |name| name.contains('2')));
+        root.filter(&|file| {
+            file.file_name()
+                .map_or(false, |name| name.to_string_lossy().contains('2'))
+        });
 
         assert_eq!(
             root.subdirs.len(),
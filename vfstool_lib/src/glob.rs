@@ -0,0 +1,98 @@
+use crate::normalize_str;
+use regex::Regex;
+
+/// Compiles `glob` into a regex matching the whole of a normalized VFS path, eg for extracting
+/// every file a pattern names. The glob is normalized first, so it may use either separator and
+/// any case.
+///
+/// - `*` and `?` stay within one path component, while `**` crosses them
+/// - `**/` also matches no directory at all, so `meshes/**/a.nif` takes `meshes/a.nif`
+/// - `[...]` is a character class, negated by starting it with `!` or `^`
+/// - `{a,b}` matches either alternative
+///
+/// # Examples
+///
+/// ```
+/// use vfstool_lib::glob_to_regex;
+///
+/// let regex = glob_to_regex("Meshes\\**\\[!x]*.nif").unwrap();
+///
+/// assert!(regex.is_match("meshes/a.nif"));
+/// assert!(regex.is_match("meshes/r/base_anim.nif"));
+/// assert!(!regex.is_match("meshes/xbase_anim.nif"));
+/// ```
+pub fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^{}$", translate(&normalize_str(glob))))
+}
+
+/// Translates an already normalized glob into unanchored regex syntax, for callers which add
+/// their own anchors around it
+pub(crate) fn translate(glob: &str) -> String {
+    let mut pattern = String::new();
+    let mut chars = glob.chars().peekable();
+    let mut brace_depth = 0;
+
+    while let Some(char) = chars.next() {
+        match char {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    pattern.push_str("(?:.*/)?");
+                } else {
+                    pattern.push_str(".*");
+                }
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => {
+                pattern.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    pattern.push('^');
+                }
+                for class_char in chars.by_ref() {
+                    pattern.push(class_char);
+                    if class_char == ']' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                brace_depth += 1;
+                pattern.push_str("(?:");
+            }
+            '}' if brace_depth > 0 => {
+                brace_depth -= 1;
+                pattern.push(')');
+            }
+            ',' if brace_depth > 0 => pattern.push('|'),
+            _ => pattern.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_whole_vfs_paths() {
+        let nested = glob_to_regex("meshes/**/a.nif").unwrap();
+        assert!(nested.is_match("meshes/a.nif"));
+        assert!(nested.is_match("meshes/x/y/a.nif"));
+        assert!(!nested.is_match("meshes/ba.nif"));
+        assert!(!nested.is_match("other/meshes/a.nif"));
+
+        let negated = glob_to_regex("textures/[!abc]*.dds").unwrap();
+        assert!(negated.is_match("textures/tx_bone.dds"));
+        assert!(!negated.is_match("textures/a_bone.dds"));
+        assert!(!negated.is_match("textures/tx/bone.dds"));
+
+        let braces = glob_to_regex("Sound\\{fx,vo}\\*.WAV").unwrap();
+        assert!(braces.is_match("sound/fx/hit.wav"));
+        assert!(braces.is_match("sound/vo/line.wav"));
+        assert!(!braces.is_match("sound/music/hit.wav"));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{glob, normalize_str};
+use regex::Regex;
+use std::{
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+/// One line of an ignore file
+#[derive(Clone, Debug)]
+struct IgnoreRule {
+    matcher: Regex,
+    /// Written as `!pattern`, so a match puts the path back in
+    negated: bool,
+}
+
+/// Glob patterns, read from a `.vfsignore` file, for loose files to leave out of a VFS.
+///
+/// Lines follow `.gitignore` conventions:
+/// - blank lines and lines starting with `#` are skipped
+/// - a pattern with no `/` in it matches a file or directory name at any depth, eg `*.bak`
+/// - otherwise it's anchored to the root of the data directory, eg `/textures/tx_test.dds`
+/// - a trailing `/` only matches directories, taking everything under them, eg `.git/`
+/// - `*` and `?` stay within one path component, `**` crosses them, and globs otherwise work
+///   as in [`glob_to_regex`](crate::glob_to_regex)
+/// - `!pattern` puts back a path an earlier pattern left out
+///
+/// The last pattern to match a path decides whether it's ignored. Matching ignores case and
+/// treats `\` like `/`, the same as VFS paths.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Parses the contents of an ignore file, failing with `InvalidData` on a malformed pattern
+    pub fn parse(contents: &str) -> Result<Self> {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+
+                let matcher = Self::pattern_to_regex(pattern).map_err(|err| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid ignore pattern {line}: {err}"),
+                    )
+                })?;
+
+                Ok(IgnoreRule { matcher, negated })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Reads and parses the ignore file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Whether there are no patterns, so nothing is ignored
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the file at `path`, relative to its data directory, should be left out
+    pub fn is_ignored(&self, path: impl AsRef<Path>) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let path = normalize_str(&path.as_ref().to_string_lossy());
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matcher.is_match(&path))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    fn pattern_to_regex(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+        let pattern = normalize_str(pattern);
+        let (pattern, directory_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern.as_str(), false),
+        };
+
+        let mut regex = String::from("^");
+        let pattern = match pattern.strip_prefix('/') {
+            Some(anchored) => anchored,
+            None if pattern.contains('/') => pattern,
+            None => {
+                regex.push_str("(?:.*/)?");
+                pattern
+            }
+        };
+
+        regex.push_str(&glob::translate(pattern));
+
+        // A match on a directory takes everything inside of it
+        regex.push_str(if directory_only { "/.*$" } else { "(?:/.*)?$" });
+        Regex::new(&regex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_follow_gitignore_rules() {
+        let rules = IgnoreRules::parse(
+            "# editor leftovers\n\
+             *.bak\n\
+             \n\
+             .git/\n\
+             /Textures/Test/\n\
+             meshes/**/*.psd\n\
+             !keep.bak\n",
+        )
+        .unwrap();
+
+        assert!(rules.is_ignored("a.bak"));
+        assert!(rules.is_ignored("meshes/deep/B.BAK"));
+        assert!(!rules.is_ignored("keep.bak"));
+        assert!(!rules.is_ignored("a.bakery"));
+
+        assert!(rules.is_ignored(".git/objects/ab"));
+        assert!(!rules.is_ignored(".git"));
+        assert!(rules.is_ignored("mod/.git/HEAD"));
+
+        assert!(rules.is_ignored("textures/test/a.dds"));
+        assert!(!rules.is_ignored("mod/textures/test/a.dds"));
+
+        assert!(rules.is_ignored("meshes/a.psd"));
+        assert!(rules.is_ignored("meshes/x/y/a.psd"));
+        assert!(!rules.is_ignored("meshes/a.nif"));
+        assert!(rules.is_ignored("meshes\\x\\a.psd"));
+    }
+
+    #[test]
+    fn malformed_pattern_is_invalid_data() {
+        let err = IgnoreRules::parse("[z-a]").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,52 +1,196 @@
 pub mod directory_node;
+pub mod glob;
+pub mod ignore;
 pub mod vfs;
 pub mod vfs_file;
 
 pub(crate) use directory_node::DirectoryNode;
-pub use vfs::VFS;
-pub use vfs_file::VfsFile;
+pub use glob::glob_to_regex;
+pub use ignore::IgnoreRules;
+pub use vfs::{PathDisplay, ScanOptions, VFS};
+pub use vfs_file::{ReadSeek, VfsFile};
 
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
     path::{Path, PathBuf},
 };
 
 pub type DisplayTree = BTreeMap<PathBuf, DirectoryNode>;
 
+/// Container format of an archive loaded into the VFS
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ArchiveFormat {
+    /// Morrowind BSA
+    Tes3,
+    /// Oblivion, Fallout 3/New Vegas and Skyrim BSA
+    Tes4,
+    /// Fallout 4 BA2
+    Fo4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SerializeType {
     Json,
     Yaml,
     Toml,
+    /// One compact JSON object per line. Trees are written a line per file, in parallel.
+    JsonLines,
+}
+
+/// How JSON and TOML output is laid out. YAML has a single layout and JSON Lines is always
+/// compact, so both ignore it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SerializeLayout {
+    /// Compact JSON and pretty-printed TOML, as `VFS::serialize_from_tree` has always written
+    #[default]
+    Standard,
+    /// Pretty-printed JSON and TOML
+    Pretty,
+    /// Compact JSON and TOML
+    Compact,
 }
 
+/// Turns a path into the key it's stored under in the VFS: backslashes become forward slashes and
+/// the whole path is lowercased, using Unicode rules so eg `É` and `é` are the same file.
+/// Paths which aren't valid UTF-8 can't be case folded, so only their ASCII is lowercased.
 pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref().as_os_str();
+
+    if let Some(path) = path.to_str() {
+        return PathBuf::from(normalize_str(path));
+    }
+
     let normalized = path
-        .as_ref()
-        .as_os_str()
-        .as_encoded_bytes()
-        .iter()
-        .map(|&byte| match byte {
-            b'\\' => '/' as u8,
-            b'A'..=b'Z' => byte + 32,
-            _ => byte,
-        })
+        .to_ascii_lowercase()
+        .into_encoded_bytes()
+        .into_iter()
+        .map(|byte| if byte == b'\\' { b'/' } else { byte })
         .collect::<Vec<_>>();
 
+    // Only ASCII bytes were swapped for other ASCII bytes, so the encoding is still valid
     PathBuf::from(unsafe { std::ffi::OsString::from_encoded_bytes_unchecked(normalized) })
 }
 
+/// String-only counterpart to `normalize_path`, for callers which already hold a `&str` and
+/// want a `String` back without going through `PathBuf`.
+pub fn normalize_str(s: &str) -> String {
+    s.to_lowercase().replace('\\', "/")
+}
+
+/// Paths this long or longer, in UTF-16 units, can't be opened on Windows without the `\\?\`
+/// extended-length prefix
+pub const MAX_PATH: usize = 260;
+
+/// Spells `path` so Windows can open it even when it's longer than `MAX_PATH`, which deep mod
+/// trees easily are. Such paths are made absolute and given the `\\?\` extended-length prefix.
+/// Shorter paths, and every path on other platforms, are returned as they are.
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    // A relative path may only cross the limit once it's resolved against the current directory
+    #[cfg(windows)]
+    if (path.is_relative() || path.as_os_str().len() >= MAX_PATH)
+        && let Ok(absolute) = std::path::absolute(path)
+        && let Some(extended) = absolute.to_str().and_then(extended_length_path)
+    {
+        return Cow::Owned(PathBuf::from(extended));
+    }
+
+    Cow::Borrowed(path)
+}
+
+/// Adds the `\\?\` prefix to an absolute drive or UNC path which is at least `MAX_PATH` long.
+/// Windows doesn't normalize prefixed paths, so separators and `.`/`..` components are resolved
+/// here instead. Anything else, including paths which already have a prefix, gives `None`.
+#[cfg(any(windows, test))]
+fn extended_length_path(path: &str) -> Option<String> {
+    if path.encode_utf16().count() < MAX_PATH
+        || path.starts_with(r"\\?\")
+        || path.starts_with(r"\\.\")
+    {
+        return None;
+    }
+
+    let path = path.replace('/', "\\");
+    let bytes = path.as_bytes();
+
+    // The server and share of a UNC path, or the drive, can't be left with `..`
+    let (prefix, rest, root_len) = if let Some(unc) = path.strip_prefix(r"\\") {
+        (r"\\?\UNC\", unc, 2)
+    } else if bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1..3] == *b":\\" {
+        (r"\\?\", path.as_str(), 1)
+    } else {
+        return None;
+    };
+
+    let mut components = Vec::new();
+
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.len() > root_len {
+                    components.pop();
+                }
+            }
+            component => components.push(component),
+        }
+    }
+
+    Some(format!("{prefix}{}", components.join("\\")))
+}
+
+/// Undoes the `\\?\` prefix `fs::canonicalize` puts on every path on Windows, so displayed paths
+/// read the way users type them. Verbatim UNC paths go back to `\\server\share`.
+pub(crate) fn without_verbatim_prefix(path: &str) -> Cow<'_, str> {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        return Cow::Owned(format!(r"\\{unc}"));
+    }
+
+    Cow::Borrowed(path.strip_prefix(r"\\?\").unwrap_or(path))
+}
+
+/// File name of the dummy archive installed by ArchiveInvalidation tools. It only needs to exist
+/// for replacement textures to be picked up, so its entries are placeholders rather than content.
+pub const INVALIDATION_ARCHIVE: &str = "archiveinvalidationinvalidated!.bsa";
+
+/// Whether `path` names the ArchiveInvalidation dummy archive, ignoring case
+pub fn is_invalidation_archive<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .is_some_and(|name| name.eq_ignore_ascii_case(INVALIDATION_ARCHIVE))
+}
+
 #[cfg(feature = "bsa")]
 pub mod archives {
     use std::{
-        collections::HashMap,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
         fs::File,
+        io::{self, Write},
         path::{Path, PathBuf},
-        sync::Arc,
+        sync::{Arc, Mutex, OnceLock},
     };
 
     use super::VfsFile;
     use ba2::{self, prelude::*, tes3::Archive as TES3Archive};
 
+    /// Path of a file inside of a TES4 archive directory. Files at the root of the archive are
+    /// stored in a directory named `.`, which is left out so they land at the root of the VFS.
+    fn tes4_entry_path(dir: &str, file: &str) -> String {
+        match dir {
+            "" | "." => file.to_string(),
+            dir => format!("{dir}\\{file}"),
+        }
+    }
+
+    fn to_pack_err<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+
     #[derive(Debug)]
     pub enum TypedArchive {
         Tes3(ba2::tes3::Archive<'static>),
@@ -62,6 +206,12 @@ pub mod archives {
         file_handle: File,
         archive: TypedArchive,
         path: PathBuf,
+        // TES4-family archives share a container format, but Skyrim SE (v105) uses LZ4 where
+        // Oblivion, Fallout 3/NV and Skyrim LE use zlib, so the header version must be kept
+        tes4_version: Option<ba2::tes4::Version>,
+        // FO4 archives are either general or textures-only, which decides how entries are laid out
+        fo4_options: Option<ba2::fo4::ArchiveOptions>,
+        cache: OnceLock<Arc<EntryCache>>,
     }
 
     impl StoredArchive {
@@ -72,99 +222,813 @@ pub mod archives {
         pub fn path(&self) -> &Path {
             &self.path
         }
+
+        /// Whether this is the ArchiveInvalidation dummy archive
+        pub fn is_invalidation(&self) -> bool {
+            crate::is_invalidation_archive(&self.path)
+        }
+
+        pub fn format(&self) -> crate::ArchiveFormat {
+            match &self.archive {
+                TypedArchive::Tes3(_) => crate::ArchiveFormat::Tes3,
+                TypedArchive::Tes4(_) => crate::ArchiveFormat::Tes4,
+                TypedArchive::Fo4(_) => crate::ArchiveFormat::Fo4,
+            }
+        }
+
+        /// Number of files stored in the archive, whether or not they're shadowed in the VFS
+        pub fn len(&self) -> usize {
+            match &self.archive {
+                TypedArchive::Tes3(archive) => archive.len(),
+                TypedArchive::Tes4(archive) => archive.iter().map(|(_, dir)| dir.len()).sum(),
+                TypedArchive::Fo4(archive) => archive.len(),
+            }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        /// Iterates over the normalized paths of every file stored in the archive.
+        ///
+        /// This reads the archive itself rather than the merged VFS, so entries which are
+        /// shadowed by loose files or later archives are included.
+        pub fn entries(&self) -> Box<dyn Iterator<Item = PathBuf> + '_> {
+            match &self.archive {
+                TypedArchive::Tes3(data) => Box::new(
+                    data.iter()
+                        .map(|(key, _value)| crate::normalize_path(key.name().to_string())),
+                ),
+                TypedArchive::Tes4(data) => Box::new(data.iter().flat_map(|(dir_key, dir)| {
+                    dir.iter().map(move |(key, _value)| {
+                        crate::normalize_path(tes4_entry_path(
+                            &dir_key.name().to_string(),
+                            &key.name().to_string(),
+                        ))
+                    })
+                })),
+                TypedArchive::Fo4(data) => Box::new(
+                    data.iter()
+                        .map(|(key, _value)| crate::normalize_path(key.name().to_string())),
+                ),
+            }
+        }
+
+        /// Header version of a TES4-family archive, used to pick the decompression codec.
+        /// Always `None` for TES3 and FO4 archives.
+        pub fn tes4_version(&self) -> Option<ba2::tes4::Version> {
+            self.tes4_version
+        }
+
+        /// Whether an FO4 archive is a general (`GNRL`) one or holds only textures (`DX10`), as
+        /// read from its header. Always `None` for TES3 and TES4-family archives.
+        pub fn fo4_kind(&self) -> Option<ba2::fo4::Format> {
+            self.fo4_options.map(|options| options.format())
+        }
+
+        /// Header version of an FO4 archive. Always `None` for TES3 and TES4-family archives.
+        pub fn fo4_version(&self) -> Option<ba2::fo4::Version> {
+            self.fo4_options.map(|options| options.version())
+        }
+
+        /// Cache of decompressed entries, if one was attached with `set_cache`
+        pub fn cache(&self) -> Option<&Arc<EntryCache>> {
+            self.cache.get()
+        }
+
+        /// Attaches a cache which `VfsFile::open` will keep decompressed entries in.
+        /// An archive only ever uses the first cache it's given.
+        pub fn set_cache(&self, cache: Arc<EntryCache>) {
+            let _ = self.cache.set(cache);
+        }
+    }
+
+    type CacheKey = (PathBuf, PathBuf);
+
+    #[derive(Default)]
+    struct CacheState {
+        entries: HashMap<CacheKey, (Arc<[u8]>, u64)>,
+        /// Last use of each entry, oldest first
+        recency: BTreeMap<u64, CacheKey>,
+        total_bytes: usize,
+        clock: u64,
+    }
+
+    /// Least-recently-used cache of decompressed archive entries, keyed by archive path and entry
+    /// path. Bounded by the total size of the cached bytes rather than the number of entries.
+    pub struct EntryCache {
+        capacity: usize,
+        state: Mutex<CacheState>,
+    }
+
+    /// Summarizes the cache instead of printing every cached byte
+    impl std::fmt::Debug for EntryCache {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let mut debug = f.debug_struct("EntryCache");
+            debug.field("capacity", &self.capacity);
+
+            if let Ok(state) = self.state.try_lock() {
+                debug
+                    .field("len", &state.entries.len())
+                    .field("total_bytes", &state.total_bytes);
+            }
+
+            debug.finish_non_exhaustive()
+        }
+    }
+
+    impl EntryCache {
+        pub fn new(capacity_bytes: usize) -> Self {
+            Self {
+                capacity: capacity_bytes,
+                state: Mutex::new(CacheState::default()),
+            }
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.capacity
+        }
+
+        /// Number of bytes currently held
+        pub fn total_bytes(&self) -> usize {
+            self.state
+                .lock()
+                .expect("Entry cache lock was poisoned")
+                .total_bytes
+        }
+
+        pub fn len(&self) -> usize {
+            self.state
+                .lock()
+                .expect("Entry cache lock was poisoned")
+                .entries
+                .len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        /// Looks up an entry, marking it as the most recently used
+        pub fn get(&self, archive: &Path, entry: &Path) -> Option<Arc<[u8]>> {
+            let mut state = self.state.lock().expect("Entry cache lock was poisoned");
+            let key = (archive.to_path_buf(), entry.to_path_buf());
+
+            state.clock += 1;
+            let now = state.clock;
+
+            let (data, last_used) = state.entries.get_mut(&key)?;
+            let (data, previous) = (Arc::clone(data), std::mem::replace(last_used, now));
+
+            state.recency.remove(&previous);
+            state.recency.insert(now, key);
+
+            Some(data)
+        }
+
+        /// Stores an entry, evicting the least recently used ones until it fits.
+        /// Entries larger than the whole cache are never stored.
+        pub fn insert(&self, archive: &Path, entry: &Path, data: Arc<[u8]>) {
+            if data.len() > self.capacity {
+                return;
+            }
+
+            let mut state = self.state.lock().expect("Entry cache lock was poisoned");
+            let key = (archive.to_path_buf(), entry.to_path_buf());
+
+            if let Some((old, last_used)) = state.entries.remove(&key) {
+                state.total_bytes -= old.len();
+                state.recency.remove(&last_used);
+            }
+
+            while state.total_bytes + data.len() > self.capacity {
+                let Some((_, oldest)) = state.recency.pop_first() else {
+                    break;
+                };
+
+                if let Some((evicted, _)) = state.entries.remove(&oldest) {
+                    state.total_bytes -= evicted.len();
+                }
+            }
+
+            state.clock += 1;
+            let now = state.clock;
+
+            state.total_bytes += data.len();
+            state.recency.insert(now, key.clone());
+            state.entries.insert(key, (data, now));
+        }
     }
 
     pub type ArchiveList = Vec<Arc<StoredArchive>>;
 
-    pub fn from_set(file_map: &HashMap<PathBuf, VfsFile>, archive_list: Vec<&str>) -> ArchiveList {
-        archive_list
+    /// Opens every archive in `archive_list` which can be found, in the order given.
+    ///
+    /// Each entry is first looked up as a loose file in `file_map`. Otherwise it's treated as a
+    /// path on disk: absolute paths are used as-is, and relative ones are tried inside each of
+    /// `data_dirs`, later directories first.
+    ///
+    /// An archive listed more than once is only opened at its last position. Each one is
+    /// returned alongside the opened archives, so the caller can warn about it.
+    pub fn from_set(
+        file_map: &HashMap<PathBuf, VfsFile>,
+        archive_list: Vec<&str>,
+        data_dirs: &[PathBuf],
+    ) -> (ArchiveList, Vec<PathBuf>) {
+        let (located, repeated) = locate_set(file_map, archive_list, data_dirs);
+        let opened = located.iter().filter_map(|path| open(path)).collect();
+
+        (opened, repeated)
+    }
+
+    /// Where each archive `from_set` would open lives on disk, in load order, without opening
+    /// any of them, along with every archive which was listed more than once
+    pub fn locate_set(
+        file_map: &HashMap<PathBuf, VfsFile>,
+        archive_list: Vec<&str>,
+        data_dirs: &[PathBuf],
+    ) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let located: Vec<PathBuf> = archive_list
             .into_iter()
-            .filter_map(|archive| {
-                let archive_path = PathBuf::from(archive.to_ascii_lowercase());
-                // Try to get the archive from the file map
-                file_map.get(&archive_path).and_then(|valid_archive| {
-                    let path = valid_archive.path();
-                    // Attempt to open the archive file
-                    File::open(&path).ok().and_then(|mut file_handle| {
-                        // Attempt to read the archive
-                        match ba2::guess_format(&mut file_handle) {
-                            None => None,
-                            Some(format) => match format {
-                                ba2::FileFormat::TES3 => {
-                                    TES3Archive::read(&file_handle).ok().map(|archive| {
-                                        Arc::new(StoredArchive {
-                                            file_handle,
-                                            archive: TypedArchive::Tes3(archive),
-                                            path: path.to_path_buf(),
-                                        })
-                                    })
-                                }
-                                ba2::FileFormat::TES4 => ba2::tes4::Archive::read(&file_handle)
-                                    .ok()
-                                    .map(|(archive, _meta)| {
-                                        Arc::new(StoredArchive {
-                                            file_handle,
-                                            archive: TypedArchive::Tes4(archive),
-                                            path: path.to_path_buf(),
-                                        })
-                                    }),
-                                ba2::FileFormat::FO4 => ba2::fo4::Archive::read(&file_handle)
-                                    .ok()
-                                    .map(|(archive, _meta)| {
-                                        Arc::new(StoredArchive {
-                                            file_handle,
-                                            archive: TypedArchive::Fo4(archive),
-                                            path: path.to_path_buf(),
-                                        })
-                                    }),
-                            },
-                        }
-                    })
-                })
+            .filter_map(|archive| locate(file_map, archive, data_dirs))
+            .collect();
+
+        keep_last_of_each(located)
+    }
+
+    /// Drops every archive path which shows up again later in `located`, comparing normalized
+    /// paths. Also returns each archive which was repeated, once and sorted.
+    fn keep_last_of_each(located: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut seen = HashSet::new();
+        let mut repeated = BTreeSet::new();
+
+        let mut kept: Vec<PathBuf> = located
+            .into_iter()
+            .rev()
+            .filter(|path| {
+                let normalized = super::normalize_path(path);
+                let first = !seen.contains(&normalized);
+
+                if first {
+                    seen.insert(normalized);
+                } else {
+                    repeated.insert(path.clone());
+                }
+
+                first
             })
-            .collect()
+            .collect();
+        kept.reverse();
+
+        (kept, repeated.into_iter().collect())
     }
 
-    #[cfg(feature = "bsa")]
-    pub fn file_map(archives: ArchiveList) -> HashMap<PathBuf, VfsFile> {
-        archives
+    /// Where an entry of the fallback archive list lives on disk, if anywhere
+    fn locate(
+        file_map: &HashMap<PathBuf, VfsFile>,
+        archive: &str,
+        data_dirs: &[PathBuf],
+    ) -> Option<PathBuf> {
+        if let Some(loose) = file_map.get(&super::normalize_path(archive)) {
+            return Some(loose.path().to_path_buf());
+        }
+
+        let archive = Path::new(archive);
+
+        if archive.is_absolute() {
+            return archive.is_file().then(|| archive.to_path_buf());
+        }
+
+        data_dirs
             .iter()
-            .flat_map(|stored_archive| {
-                let iter: Box<dyn Iterator<Item = (PathBuf, VfsFile)>> =
-                    match &stored_archive.archive {
-                        TypedArchive::Tes3(data) => Box::new(data.iter().map(|(key, _value)| {
-                            let name_string = key.name().to_string();
-                            let normalized = crate::normalize_path(&name_string);
-                            (
-                                normalized,
-                                VfsFile::from_archive(&name_string, Arc::clone(stored_archive)),
-                            )
-                        })),
-                        TypedArchive::Tes4(data) => {
-                            Box::new(data.iter().flat_map(move |(dir_key, dir)| {
-                                let dir_string = dir_key.name();
-                                dir.iter().map(move |(key, _value)| {
-                                    let archive_path = format!("{}\\{}", dir_string, key.name());
-                                    let normalized = crate::normalize_path(&archive_path);
-                                    let vfs_file = VfsFile::from_archive(
-                                        &normalized.to_string_lossy(),
-                                        Arc::clone(stored_archive),
-                                    );
-                                    (normalized, vfs_file)
-                                })
-                            }))
+            .rev()
+            .map(|dir| dir.join(archive))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Reads the archive at `path`, returning `None` if it can't be opened or isn't a known
+    /// archive format
+    pub(crate) fn open(path: &Path) -> Option<Arc<StoredArchive>> {
+        let mut file_handle = File::open(path).ok()?;
+
+        match ba2::guess_format(&mut file_handle)? {
+            ba2::FileFormat::TES3 => TES3Archive::read(&file_handle).ok().map(|archive| {
+                Arc::new(StoredArchive {
+                    file_handle,
+                    archive: TypedArchive::Tes3(archive),
+                    path: path.to_path_buf(),
+                    tes4_version: None,
+                    fo4_options: None,
+                    cache: OnceLock::new(),
+                })
+            }),
+            ba2::FileFormat::TES4 => {
+                ba2::tes4::Archive::read(&file_handle)
+                    .ok()
+                    .map(|(archive, meta)| {
+                        Arc::new(StoredArchive {
+                            file_handle,
+                            archive: TypedArchive::Tes4(archive),
+                            path: path.to_path_buf(),
+                            tes4_version: Some(meta.version()),
+                            fo4_options: None,
+                            cache: OnceLock::new(),
+                        })
+                    })
+            }
+            ba2::FileFormat::FO4 => {
+                ba2::fo4::Archive::read(&file_handle)
+                    .ok()
+                    .map(|(archive, meta)| {
+                        Arc::new(StoredArchive {
+                            file_handle,
+                            archive: TypedArchive::Fo4(archive),
+                            path: path.to_path_buf(),
+                            tes4_version: None,
+                            fo4_options: Some(meta),
+                            cache: OnceLock::new(),
+                        })
+                    })
+            }
+        }
+    }
+
+    /// Archive layouts which `pack` is able to produce
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum PackFormat {
+        /// Morrowind
+        #[default]
+        Tes3,
+        /// Oblivion, Fallout 3/New Vegas and Skyrim LE, using zlib compression
+        Tes4,
+        /// Skyrim SE, using LZ4 compression
+        Sse,
+        /// Fallout 4 general archives
+        Fo4,
+    }
+
+    /// How an entry is stored when packed into a TES4-family or FO4 archive. TES3 archives can't
+    /// be compressed, so their entries are always stored as-is.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum PackCompression {
+        /// Store the entry uncompressed
+        #[default]
+        None,
+        /// Compress using the format's standard settings
+        Fast,
+        /// Favor a smaller archive over packing speed. FO4 archives use the higher ratio settings
+        /// of the Xbox release, while TES4-family archives only have one setting, the same as
+        /// `Fast`.
+        Best,
+    }
+
+    /// Picks the TES4 content flag an archive directory should be advertised under, based on
+    /// its top-level folder. Anything unrecognized is considered miscellaneous.
+    fn tes4_type_for(dir: &str) -> ba2::tes4::ArchiveTypes {
+        use ba2::tes4::ArchiveTypes;
+
+        match dir.split('\\').next().unwrap_or_default() {
+            "meshes" => ArchiveTypes::MESHES,
+            "textures" => ArchiveTypes::TEXTURES,
+            "menus" | "interface" => ArchiveTypes::MENUS,
+            "sound" => match dir.starts_with("sound\\voice") {
+                true => ArchiveTypes::VOICES,
+                false => ArchiveTypes::SOUNDS,
+            },
+            "shaders" => ArchiveTypes::SHADERS,
+            "trees" => ArchiveTypes::TREES,
+            "fonts" => ArchiveTypes::FONTS,
+            _ => ArchiveTypes::MISC,
+        }
+    }
+
+    /// Writes `entries`, keyed by their normalized VFS path, into a new archive of the given
+    /// `format`. Each entry is compressed as its `PackCompression` asks, which TES3 ignores.
+    ///
+    /// Entries whose archive key collides with one which was already staged are skipped, and
+    /// returned so the caller may report them.
+    pub fn pack<W: Write>(
+        entries: impl IntoIterator<Item = (PathBuf, Vec<u8>, PackCompression)>,
+        format: PackFormat,
+        dst: &mut W,
+    ) -> io::Result<Vec<PathBuf>> {
+        let mut collisions = Vec::new();
+
+        // Bethesda archives conventionally store backslash-separated names
+        let entries = entries.into_iter().map(|(path, data, compression)| {
+            let name = crate::normalize_str(&path.to_string_lossy()).replace('/', "\\");
+            (path, name, data.into_boxed_slice(), compression)
+        });
+
+        match format {
+            PackFormat::Tes3 => {
+                let mut archive = TES3Archive::new();
+
+                for (path, name, data, _compression) in entries {
+                    let key: ba2::tes3::ArchiveKey = name.into();
+
+                    if archive.get(&key).is_some() {
+                        collisions.push(path);
+                        continue;
+                    }
+
+                    archive.insert(key, ba2::tes3::File::from(data));
+                }
+
+                archive.write(dst).map_err(to_pack_err)?;
+            }
+            PackFormat::Tes4 | PackFormat::Sse => {
+                use ba2::tes4::{
+                    Archive, ArchiveFlags, ArchiveKey, ArchiveOptions, ArchiveTypes, Directory,
+                    DirectoryKey, File as Tes4File, FileCompressionOptions, Version,
+                };
+
+                let version = match format {
+                    PackFormat::Sse => Version::SSE,
+                    _ => Version::TES4,
+                };
+                let options = FileCompressionOptions::builder().version(version).build();
+
+                let mut archive = Archive::new();
+                let mut types = ArchiveTypes::empty();
+                let mut any_compressed = false;
+
+                for (path, name, data, compression) in entries {
+                    let (dir_name, file_name) = name.rsplit_once('\\').unwrap_or((".", &name));
+
+                    let dir_key: ArchiveKey = dir_name.to_string().into();
+                    let file_key: DirectoryKey = file_name.to_string().into();
+
+                    if archive
+                        .get(&dir_key)
+                        .is_some_and(|dir| dir.get(&file_key).is_some())
+                    {
+                        collisions.push(path);
+                        continue;
+                    }
+
+                    let file = Tes4File::from_decompressed(data);
+                    let file = match compression {
+                        PackCompression::None => file,
+                        PackCompression::Fast | PackCompression::Best => {
+                            any_compressed = true;
+                            file.compress(&options).map_err(to_pack_err)?
                         }
-                        TypedArchive::Fo4(data) => Box::new(data.iter().map(|(key, _value)| {
-                            let name_string = key.name().to_string();
-                            let normalized = crate::normalize_path(&name_string);
-                            (
-                                normalized,
-                                VfsFile::from_archive(&name_string, Arc::clone(stored_archive)),
-                            )
-                        })),
                     };
-                iter
-            })
-            .collect()
+
+                    types |= tes4_type_for(dir_name);
+
+                    match archive.get_mut(&dir_key) {
+                        Some(dir) => {
+                            dir.insert(file_key, file);
+                        }
+                        None => {
+                            let dir: Directory = [(file_key, file)].into_iter().collect();
+                            archive.insert(dir_key, dir);
+                        }
+                    }
+                }
+
+                // Entries which differ from the archive-wide flag are marked individually
+                let mut flags = ArchiveFlags::DIRECTORY_STRINGS | ArchiveFlags::FILE_STRINGS;
+                if any_compressed {
+                    flags |= ArchiveFlags::COMPRESSED;
+                }
+
+                let options = ArchiveOptions::builder()
+                    .version(version)
+                    .types(types)
+                    .flags(flags)
+                    .build();
+
+                archive.write(dst, &options).map_err(to_pack_err)?;
+            }
+            PackFormat::Fo4 => {
+                use ba2::fo4::{
+                    Archive, ArchiveKey, ArchiveOptions, Chunk, ChunkCompressionOptions,
+                    CompressionLevel, File as Fo4File,
+                };
+
+                let mut archive = Archive::new();
+
+                for (path, name, data, compression) in entries {
+                    let key: ArchiveKey = name.into();
+
+                    if archive.get(&key).is_some() {
+                        collisions.push(path);
+                        continue;
+                    }
+
+                    let chunk = Chunk::from_decompressed(data);
+                    let level = match compression {
+                        PackCompression::None => None,
+                        PackCompression::Fast => Some(CompressionLevel::FO4),
+                        PackCompression::Best => Some(CompressionLevel::FO4Xbox),
+                    };
+                    let chunk = match level {
+                        Some(level) => {
+                            let options = ChunkCompressionOptions::builder()
+                                .compression_level(level)
+                                .build();
+                            chunk.compress(&options).map_err(to_pack_err)?
+                        }
+                        None => chunk,
+                    };
+
+                    let file: Fo4File = [chunk].into_iter().collect();
+                    archive.insert(key, file);
+                }
+
+                archive
+                    .write(dst, &ArchiveOptions::builder().strings(true).build())
+                    .map_err(to_pack_err)?;
+            }
+        }
+
+        Ok(collisions)
+    }
+
+    #[cfg(feature = "bsa")]
+    pub fn file_map(archives: ArchiveList) -> HashMap<PathBuf, VfsFile> {
+        entries(&archives).collect()
+    }
+
+    /// Every file in the given archives keyed by normalized path, in load order.
+    /// Unlike `file_map`, entries shadowed by a later archive are still yielded.
+    #[cfg(feature = "bsa")]
+    pub fn entries(archives: &ArchiveList) -> impl Iterator<Item = (PathBuf, VfsFile)> + '_ {
+        archives.iter().flat_map(|stored_archive| {
+            let iter: Box<dyn Iterator<Item = (PathBuf, VfsFile)>> = match &stored_archive.archive {
+                TypedArchive::Tes3(data) => Box::new(data.iter().map(|(key, _value)| {
+                    let name_string = key.name().to_string();
+                    let normalized = crate::normalize_path(&name_string);
+                    (
+                        normalized,
+                        VfsFile::from_archive(&name_string, Arc::clone(stored_archive)),
+                    )
+                })),
+                TypedArchive::Tes4(data) => {
+                    Box::new(data.iter().flat_map(move |(dir_key, dir)| {
+                        let dir_string = dir_key.name().to_string();
+                        dir.iter().map(move |(key, _value)| {
+                            let archive_path =
+                                tes4_entry_path(&dir_string, &key.name().to_string());
+                            let normalized = crate::normalize_path(&archive_path);
+                            // ba2 only folds ASCII case when it hashes a key, so the entry keeps
+                            // its stored spelling and only the VFS key is Unicode lowercased
+                            let vfs_file = VfsFile::from_archive(
+                                archive_path.replace('\\', "/"),
+                                Arc::clone(stored_archive),
+                            );
+                            (normalized, vfs_file)
+                        })
+                    }))
+                }
+                TypedArchive::Fo4(data) => Box::new(data.iter().map(|(key, _value)| {
+                    let name_string = key.name().to_string();
+                    let normalized = crate::normalize_path(&name_string);
+                    (
+                        normalized,
+                        VfsFile::from_archive(&name_string, Arc::clone(stored_archive)),
+                    )
+                })),
+            };
+            iter
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn entry_cache_evicts_least_recently_used_by_size() {
+        use archives::EntryCache;
+        use std::{path::Path, sync::Arc};
+
+        let cache = EntryCache::new(10);
+        let archive = Path::new("textures.bsa");
+        let entry = |bytes: usize| -> Arc<[u8]> { vec![0u8; bytes].into() };
+
+        cache.insert(archive, Path::new("a.dds"), entry(4));
+        cache.insert(archive, Path::new("b.dds"), entry(4));
+        assert_eq!(cache.total_bytes(), 8);
+
+        // Touching `a` leaves `b` as the oldest entry
+        assert!(cache.get(archive, Path::new("a.dds")).is_some());
+        cache.insert(archive, Path::new("c.dds"), entry(4));
+
+        assert!(cache.get(archive, Path::new("b.dds")).is_none());
+        assert!(cache.get(archive, Path::new("a.dds")).is_some());
+        assert!(cache.get(archive, Path::new("c.dds")).is_some());
+        assert_eq!(cache.total_bytes(), 8);
+
+        // Larger than the whole cache, so it's skipped rather than flushing everything
+        cache.insert(archive, Path::new("huge.dds"), entry(11));
+        assert_eq!(cache.len(), 2);
+
+        // Same entry path in another archive is a separate entry
+        assert!(
+            cache
+                .get(Path::new("other.bsa"), Path::new("a.dds"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn normalize_str_matches_normalize_path() {
+        for input in [
+            "Meshes\\XBase_Anim.nif",
+            "textures/Tx_Bone_01.DDS",
+            "Data Files\\Sound/Fx\\FOOT",
+            "already/normal.txt",
+            "Textures\\Élévation.DDS",
+            "Meshes/Straße/ẞ.nif",
+            "Меши\\Дерево.NIF",
+        ] {
+            assert_eq!(
+                normalize_str(input),
+                normalize_path(input).to_string_lossy(),
+                "{input} normalized differently as a str and as a path"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_path_folds_unicode_case() {
+        for (input, expected) in [
+            ("Textures\\Élévation.DDS", "textures/élévation.dds"),
+            ("Meshes/STRAẞE/Straße.nif", "meshes/straße/straße.nif"),
+            ("Меши\\Дерево.NIF", "меши/дерево.nif"),
+        ] {
+            assert_eq!(normalize_path(input), Path::new(expected));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn normalize_path_keeps_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"Meshes\\\xFFA.nif"));
+        assert_eq!(
+            normalize_path(path).as_os_str().as_bytes(),
+            b"meshes/\xFFa.nif"
+        );
+    }
+
+    #[test]
+    fn verbatim_prefixes_are_removed() {
+        assert_eq!(
+            without_verbatim_prefix(r"\\?\C:\Games\Morrowind"),
+            r"C:\Games\Morrowind"
+        );
+        assert_eq!(
+            without_verbatim_prefix(r"\\?\UNC\server\share\a.nif"),
+            r"\\server\share\a.nif"
+        );
+        assert_eq!(
+            without_verbatim_prefix("/games/morrowind"),
+            "/games/morrowind"
+        );
+    }
+
+    #[test]
+    fn extended_length_paths() {
+        let long_dir = "Mod Folder\\".repeat(30);
+
+        assert_eq!(extended_length_path(r"C:\Games\Morrowind\Data Files"), None);
+        assert_eq!(
+            extended_length_path(&format!(r"C:\{long_dir}Meshes\a.nif")),
+            Some(format!(r"\\?\C:\{long_dir}Meshes\a.nif"))
+        );
+        assert_eq!(
+            extended_length_path(&format!(r"D:/{long_dir}./Meshes/../Textures/a.dds")),
+            Some(format!(r"\\?\D:\{long_dir}Textures\a.dds"))
+        );
+        assert_eq!(
+            extended_length_path(&format!(r"\\server\share\{long_dir}a.nif")),
+            Some(format!(r"\\?\UNC\server\share\{long_dir}a.nif"))
+        );
+        assert_eq!(extended_length_path(r"\\server\share\a.nif"), None);
+        assert_eq!(
+            extended_length_path(&format!(r"\\server\share\..\..\{long_dir}a.nif")),
+            Some(format!(r"\\?\UNC\server\share\{long_dir}a.nif"))
+        );
+
+        for unchanged in [
+            format!(r"\\?\C:\{long_dir}a.nif"),
+            format!(r"\\.\C:\{long_dir}a.nif"),
+            format!(r"{long_dir}a.nif"),
+        ] {
+            assert_eq!(extended_length_path(&unchanged), None, "{unchanged}");
+        }
+
+        let short = Path::new("meshes/a.nif");
+        assert!(matches!(long_path(short), Cow::Borrowed(path) if path == short));
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn packed_archives_round_trip() {
+        use archives::{PackCompression, PackFormat};
+        use std::io::Read;
+
+        let pack_dir = std::env::current_dir().unwrap().join("packed_archives");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+
+        let entries = || {
+            vec![
+                (
+                    PathBuf::from("meshes/xbase_anim.nif"),
+                    b"anim".to_vec(),
+                    PackCompression::Best,
+                ),
+                (
+                    PathBuf::from("textures/tx_bone_01.dds"),
+                    b"bone".to_vec(),
+                    PackCompression::None,
+                ),
+                (
+                    PathBuf::from("Textures\\TX_Bone_01.DDS"),
+                    b"dupe".to_vec(),
+                    PackCompression::Fast,
+                ),
+            ]
+        };
+
+        for (name, format) in [
+            ("packed.bsa", PackFormat::Tes3),
+            ("packed_tes4.bsa", PackFormat::Tes4),
+            ("packed_sse.bsa", PackFormat::Sse),
+            ("packed.ba2", PackFormat::Fo4),
+        ] {
+            let mut dst = std::fs::File::create(pack_dir.join(name)).unwrap();
+            let collisions = archives::pack(entries(), format, &mut dst).unwrap();
+            drop(dst);
+
+            assert_eq!(
+                collisions,
+                vec![PathBuf::from("Textures\\TX_Bone_01.DDS")],
+                "{format:?} should report the colliding key"
+            );
+
+            let vfs = VFS::from_directories([&pack_dir], Some(vec![name]));
+
+            for (path, expected, compressed) in [
+                ("meshes/xbase_anim.nif", "anim", format != PackFormat::Tes3),
+                ("textures/tx_bone_01.dds", "bone", false),
+            ] {
+                let file = vfs
+                    .get_file(path)
+                    .unwrap_or_else(|| panic!("{path} missing from {format:?} archive"));
+                assert_eq!(
+                    file.is_compressed(),
+                    compressed,
+                    "{path} has the wrong compression in {format:?} archive"
+                );
+
+                let mut contents = String::new();
+                file.open().unwrap().read_to_string(&mut contents).unwrap();
+
+                assert_eq!(contents, expected);
+            }
+        }
+
+        std::fs::remove_dir_all(&pack_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn archives_expose_their_fo4_kind() {
+        use archives::{PackCompression, PackFormat};
+
+        let pack_dir = std::env::current_dir().unwrap().join("fo4_kind");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+
+        for (name, format) in [
+            ("general.ba2", PackFormat::Fo4),
+            ("plain.bsa", PackFormat::Tes3),
+        ] {
+            let entry = (
+                PathBuf::from("meshes/a.nif"),
+                b"mesh".to_vec(),
+                PackCompression::None,
+            );
+            let mut dst = std::fs::File::create(pack_dir.join(name)).unwrap();
+            archives::pack(vec![entry], format, &mut dst).unwrap();
+        }
+
+        let general = archives::open(&pack_dir.join("general.ba2")).unwrap();
+        assert_eq!(general.fo4_kind(), Some(ba2::fo4::Format::GNRL));
+        assert!(general.fo4_version().is_some());
+
+        let plain = archives::open(&pack_dir.join("plain.bsa")).unwrap();
+        assert_eq!(plain.fo4_kind(), None);
+        assert_eq!(plain.fo4_version(), None);
+
+        std::fs::remove_dir_all(&pack_dir).unwrap();
     }
 }
@@ -1,39 +1,445 @@
 use rayon::prelude::*;
 use walkdir::WalkDir;
 
-#[cfg(feature = "serialize")]
-use crate::SerializeType;
-#[cfg(feature = "serialize")]
-use std::io::Result;
-
 #[cfg(feature = "bsa")]
 use crate::archives;
+#[cfg(feature = "serialize")]
+use crate::{
+    SerializeLayout, SerializeType,
+    directory_node::{PriorityFn, SerializableNode},
+};
 
-use crate::{DirectoryNode, DisplayTree, VfsFile, normalize_path};
+use crate::{
+    ArchiveFormat, DirectoryNode, DisplayTree, IgnoreRules, VfsFile, normalize_path, normalize_str,
+};
 use std::{
     collections::{BTreeMap, HashMap},
+    ffi::OsStr,
     fmt::Write,
-    io::{Error, ErrorKind},
+    io::{Error, ErrorKind, Result},
     ops::Index,
     path::{Path, PathBuf},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 // Owned
 type MaybeFile<'a> = Option<&'a VfsFile>;
 type VFSTuple<'a> = (&'a Path, &'a VfsFile);
 type VFSFiles = HashMap<PathBuf, VfsFile>;
+/// Each scanned data directory with its files, in load order
+type ScannedDirs = Vec<(PathBuf, Vec<(PathBuf, VfsFile)>)>;
+/// A file found while walking a data directory, or why it was left out
+type WalkedFile = std::result::Result<(PathBuf, VfsFile), UnreadableFile>;
+
+/// How `tree` and `walk` lay out files and name the directories they sit in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathDisplay {
+    /// Loose files sit at their VFS key below a `Data Files` root, and archived files below
+    /// their archive's file name, eg `Morrowind.bsa/meshes/x.nif`
+    Relative,
+    /// Loose files sit at their full path on disk below `/`, and archived files below their
+    /// archive's full path
+    #[default]
+    Absolute,
+    /// Every file sits at its VFS key below `Data Files`, as the game sees it, and is shown along
+    /// with the resolved source it's read from
+    Both,
+}
+
+impl PathDisplay {
+    /// Key of the single root every tree in this mode is built below
+    fn root(self) -> PathBuf {
+        match self {
+            PathDisplay::Relative | PathDisplay::Both => "Data Files".into(),
+            PathDisplay::Absolute => "/".into(),
+        }
+    }
+}
+
+/// `true` is `Relative` and `false` is `Absolute`, matching the flag these modes replaced
+impl From<bool> for PathDisplay {
+    fn from(relative: bool) -> Self {
+        if relative {
+            PathDisplay::Relative
+        } else {
+            PathDisplay::Absolute
+        }
+    }
+}
+
+/// Where a file is shown in a display tree in the given mode
+fn display_path(key: &Path, entry: &VfsFile, display: PathDisplay) -> PathBuf {
+    match display {
+        PathDisplay::Relative => entry
+            .parent_archive_name()
+            .map_or_else(|| key.into(), |archive| PathBuf::from(archive).join(key)),
+        PathDisplay::Absolute => entry.parent_archive_path().map_or_else(
+            || entry.path().to_path_buf(),
+            |archive| PathBuf::from(archive).join(key),
+        ),
+        PathDisplay::Both => key.into(),
+    }
+}
+
+/// First directory below `root` that `tree` places a file at `path` in, or `None` if the file
+/// sits directly in the root
+fn top_level_dir(path: &Path, root: &Path) -> Option<PathBuf> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())?;
+    let mut current = PathBuf::new();
+
+    for component in parent.components() {
+        current.push(component);
+
+        if current != root {
+            return Some(current);
+        }
+    }
+
+    None
+}
+
+/// Matches VFS directories ignoring case, so the same logical directory spelled two ways, eg by
+/// two archives, is never split in two. Each directory keeps the first spelling it's seen with.
+///
+/// Only relative display paths are unified. Absolute paths name real directories on disk, which
+/// may well differ only by case, and `PathDisplay::Both` already uses normalized keys.
+#[derive(Default)]
+struct DirSpellings {
+    /// Normalized directory, to the spelling it was first seen with
+    first_seen: HashMap<PathBuf, PathBuf>,
+    /// Directories which were already respelled, since many files share a parent
+    respelled: HashMap<PathBuf, PathBuf>,
+}
+
+impl DirSpellings {
+    fn unify(&mut self, dir: &Path) -> PathBuf {
+        if let Some(respelled) = self.respelled.get(dir) {
+            return respelled.clone();
+        }
+
+        let mut unified = PathBuf::new();
+
+        for component in dir.components() {
+            unified.push(component);
+            unified = self
+                .first_seen
+                .entry(normalize_path(&unified))
+                .or_insert_with(|| unified.clone())
+                .clone();
+        }
+
+        self.respelled.insert(dir.to_path_buf(), unified.clone());
+        unified
+    }
+}
+
+/// Where a file comes from: its parent archive for archived files, or its own path for loose
+/// ones, so loose copies in the same data directory are still told apart
+fn source_of(file: &VfsFile) -> PathBuf {
+    if file.is_archive() {
+        file.source()
+    } else {
+        file.path().to_path_buf()
+    }
+}
+
+/// Everything which fed a VFS, in load order
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// Each data directory, with the number of files found inside it
+    pub dirs: Vec<(PathBuf, usize)>,
+    /// Each archive, with its format and number of entries
+    pub archives: Vec<(PathBuf, ArchiveFormat, usize)>,
+}
+
+/// Differences between two VFS instances, keyed by normalized VFS path
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VfsDiff {
+    /// Paths which only exist in the VFS `diff` was called on
+    pub only_in_self: Vec<PathBuf>,
+    /// Paths which only exist in the VFS passed to `diff`
+    pub only_in_other: Vec<PathBuf>,
+    /// Paths present in both, but won by a different source: (path, self source, other source)
+    pub changed: Vec<(PathBuf, PathBuf, PathBuf)>,
+}
+
+impl VfsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A step of `VFS::walk`. Directories carry the same paths `VFS::tree` uses as keys.
+#[derive(Debug)]
+pub enum WalkEvent<'a> {
+    EnterDir(&'a Path),
+    File(&'a VfsFile),
+    LeaveDir(&'a Path),
+}
+
+/// A path whose copies in lower-priority sources are hidden by a single winning source
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+pub struct ShadowedFile {
+    /// Source of the copy the VFS actually resolves to
+    pub winner: PathBuf,
+    /// Sources of every hidden copy, lowest priority first
+    pub shadowed: Vec<PathBuf>,
+}
+
+/// Summary of an archive loaded into the VFS, as listed by `archive_report`
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+pub struct ArchiveStats {
+    /// Path to the archive on disk. Serialized reports use it as the key instead.
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    pub path: PathBuf,
+    /// Zero-based position of the archive in the load order
+    pub priority: usize,
+    pub format: ArchiveFormat,
+    /// Number of files stored in the archive
+    pub entries: usize,
+    /// Number of those files the VFS resolves to, ie which aren't shadowed by a later source
+    pub winning: usize,
+    /// Size of the archive file, or `None` if it couldn't be stat'd
+    pub size: Option<u64>,
+}
+
+/// A file left out of the VFS because `ScanOptions::skip_unreadable` was set and it couldn't be
+/// stat'd or opened, as returned by `VFS::unreadable`
+#[derive(Debug)]
+pub struct UnreadableFile {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+/// A symlink inside of a data directory whose target doesn't exist, as found by `broken_links`
+#[derive(Debug, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// Path of the symlink itself
+    pub link: PathBuf,
+    /// Where the symlink points, exactly as stored in it
+    pub target: PathBuf,
+}
+
+/// Archives a VFS from `VFS::from_directories_lazy` still has to load. Opening them is the slow
+/// part of building a VFS, so `load` is meant to run on another thread while the loose files
+/// are already in use.
+#[cfg(feature = "bsa")]
+#[derive(Debug)]
+pub struct ArchiveLoader {
+    located: Vec<PathBuf>,
+    ignore_invalidation: bool,
+}
+
+#[cfg(feature = "bsa")]
+impl ArchiveLoader {
+    /// Path of every archive `load` will open, in load order
+    pub fn archives(&self) -> &[PathBuf] {
+        &self.located
+    }
+
+    /// Opens every archive, skipping ones which can't be read, the same as `from_directories`
+    pub fn load(self) -> LoadedArchives {
+        LoadedArchives {
+            archives: self
+                .located
+                .iter()
+                .filter_map(|path| archives::open(path))
+                .collect(),
+            ignore_invalidation: self.ignore_invalidation,
+        }
+    }
+}
+
+/// Archives opened by `ArchiveLoader::load`, ready to be added with `VFS::merge_archives`
+#[cfg(feature = "bsa")]
+pub struct LoadedArchives {
+    archives: archives::ArchiveList,
+    ignore_invalidation: bool,
+}
+
+/// Glyphs used to draw the text form of a `DisplayTree`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeStyle {
+    pub dir_prefix: String,
+    pub file_prefix: String,
+}
+
+impl TreeStyle {
+    /// Box-drawing glyphs, used by default
+    pub fn unicode() -> Self {
+        Self {
+            dir_prefix: "├── ".into(),
+            file_prefix: "│   ├── ".into(),
+        }
+    }
+
+    /// Plain ASCII glyphs, for terminals and logs which can't render box-drawing characters
+    pub fn ascii() -> Self {
+        Self {
+            dir_prefix: "+-- ".into(),
+            file_prefix: "|   +-- ".into(),
+        }
+    }
+
+    /// String formatter for the file tree
+    /// Includes a newline, so caller is responsible for using the appropriate writer
+    fn file_str<S: AsRef<str> + std::fmt::Display>(&self, file: S) -> String {
+        format!("{}{}\n", self.file_prefix, file,)
+    }
+
+    /// String formatter for the file tree
+    /// Includes a newline, so caller is responsible for using the appropriate writer
+    fn dir_str<S: AsRef<str> + std::fmt::Display>(&self, dir: S) -> String {
+        format!("{}{}/\n", self.dir_prefix, dir,)
+    }
+}
+
+impl Default for TreeStyle {
+    fn default() -> Self {
+        Self::unicode()
+    }
+}
+
+/// Extensions `VFS::get_texture` falls back to, in the order they're tried
+pub const TEXTURE_EXTENSIONS: [&str; 3] = ["dds", "tga", "bmp"];
+
+/// Knobs for how data directories are walked and archives are loaded while building a VFS
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Leave out files which can't be stat'd or opened, instead of failing later when they're
+    /// read. The files left out are listed by `VFS::unreadable`.
+    pub skip_unreadable: bool,
+    /// Resolve symlinks and `..` in data directories and files, so a directory reached through
+    /// a symlink in one place and directly in another is seen as the same source. Every file is
+    /// canonicalized, so this costs a filesystem call per file.
+    pub resolve_symlinks: bool,
+    /// Keep entries of `archiveinvalidationinvalidated!.bsa` out of the VFS. The archive is still
+    /// loaded, but its placeholder entries never replace files from other sources. On by default.
+    pub ignore_invalidation: bool,
+    /// How many directories deep to look for loose files in each data directory, where `1` only
+    /// takes the files directly inside of it. Unlimited when `None`, the default.
+    pub max_depth: Option<usize>,
+    /// Loose files to leave out, matched against their normalized path relative to the data
+    /// directory. Archive contents aren't filtered. Empty by default.
+    pub ignore: IgnoreRules,
+    /// Data directories which win over every other one wherever they're listed, eg to pin a
+    /// mod, with later entries here winning over earlier ones. Entries which aren't also being
+    /// scanned are ignored. Empty by default.
+    pub priority_overrides: Vec<PathBuf>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            skip_unreadable: false,
+            resolve_symlinks: false,
+            ignore_invalidation: true,
+            max_depth: None,
+            ignore: IgnoreRules::default(),
+            priority_overrides: Vec::new(),
+        }
+    }
+}
+
+impl ScanOptions {
+    /// The path a data directory or file is recorded under
+    fn source_path(&self, path: &Path) -> PathBuf {
+        if self.resolve_symlinks {
+            std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    /// Moves the directories named in `priority_overrides` to the end of the load order, in the
+    /// order they're named there, leaving every other directory where it was
+    fn apply_priority_overrides<T>(&self, dirs: &mut [(PathBuf, T)]) {
+        if self.priority_overrides.is_empty() {
+            return;
+        }
 
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let pinned: Vec<PathBuf> = self
+            .priority_overrides
+            .iter()
+            .map(|dir| canonical(dir))
+            .collect();
+
+        // Unpinned directories rank as `None`, so they stay ahead of every pinned one
+        dirs.sort_by_cached_key(|(dir, _contents)| {
+            let dir = canonical(dir);
+            pinned.iter().position(|pin| *pin == dir)
+        });
+    }
+}
+
+#[derive(Default)]
 pub struct VFS {
     file_map: VFSFiles,
+    source_dirs: Vec<(PathBuf, usize)>,
+    /// Every copy which lost to a higher-priority one at the same path, lowest priority first
+    shadowed: HashMap<PathBuf, Vec<VfsFile>>,
+    /// Files `ScanOptions::skip_unreadable` left out during construction, sorted by path
+    unreadable: Vec<UnreadableFile>,
+    /// Archives listed more than once in the archive list, which only load at their last entry
+    #[cfg(feature = "bsa")]
+    repeated_archives: Vec<PathBuf>,
+    #[cfg(feature = "bsa")]
+    archives: archives::ArchiveList,
+    /// Every path in `file_map`, sorted, for `paths_with_indexed`. Built on first use, and
+    /// dropped whenever `file_map` changes.
+    prefix_index: OnceLock<Vec<PathBuf>>,
 }
 
 impl VFS {
-    const DIR_PREFIX: &str = "├── ";
-    const FILE_PREFIX: &str = "│   ├── ";
+    /// An empty VFS, to be filled in with `insert`
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    fn new() -> Self {
-        Self {
-            file_map: HashMap::new(),
+    /// Adds `file` at the VFS path `path`, which is normalized first. It wins over any file
+    /// already at that path, which is kept as shadowed, the same as a later data directory's.
+    ///
+    /// Nothing is read from disk, so this can be used to inject generated content, or to build
+    /// a VFS for tests.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use vfstool_lib::{VFS, VfsFile};
+    ///
+    /// let mut vfs = VFS::new();
+    /// vfs.insert(PathBuf::from("Meshes\\A.nif"), VfsFile::from("/generated/a.nif"));
+    ///
+    /// assert_eq!(vfs.get_file("meshes/a.nif").unwrap().path().to_str(), Some("/generated/a.nif"));
+    /// ```
+    pub fn insert(&mut self, path: PathBuf, file: VfsFile) {
+        self.insert_normalized(normalize_path(path), file);
+    }
+
+    /// Like `insert`, for a loose file whose path on disk is `path` itself
+    pub fn insert_loose(&mut self, path: PathBuf) {
+        let file = VfsFile::from(&path);
+        self.insert(path, file);
+    }
+
+    /// Inserts a file, keeping any copy it replaces as a shadowed candidate. A copy from the
+    /// same source as its replacement is the same physical file, so it's dropped instead.
+    fn insert_normalized(&mut self, path: PathBuf, file: VfsFile) {
+        self.prefix_index.take();
+
+        if let Some(previous) = self.file_map.insert(path.clone(), file)
+            && source_of(&previous) != source_of(&self.file_map[&path])
+        {
+            self.shadowed.entry(path).or_default().push(previous);
         }
     }
 
@@ -43,10 +449,45 @@ impl VFS {
         self.file_map.get(&normalized_path)
     }
 
+    /// Like `get_file`, but only finds the file if `path` matches its original casing, as it
+    /// would have to on a case-sensitive filesystem. Only separators are normalized.
+    ///
+    /// A file found by `get_file` but not by this is only reachable because the VFS ignores case.
+    pub fn get_file_exact<P: AsRef<Path>>(&self, path: P) -> MaybeFile<'_> {
+        let wanted = unify_separators(path.as_ref());
+
+        self.get_file(&wanted)
+            .filter(|file| unify_separators(file.path()).ends_with(&wanted))
+    }
+
+    /// Looks up a texture the way OpenMW resolves texture references: the exact path first,
+    /// then the same path with each of [`TEXTURE_EXTENSIONS`] in turn, as Morrowind's meshes
+    /// often name a `.tga` or `.bmp` that only ships as `.dds`.
+    ///
+    /// OpenMW itself tries `.dds` before the exact path, so this only differs from it when both
+    /// exist. `get_file` keeps looking up the exact path alone.
+    pub fn get_texture<P: AsRef<Path>>(&self, path: P) -> MaybeFile<'_> {
+        let normalized_path = normalize_path(path);
+
+        self.file_map.get(&normalized_path).or_else(|| {
+            TEXTURE_EXTENSIONS
+                .iter()
+                .find_map(|ext| self.file_map.get(&normalized_path.with_extension(ext)))
+        })
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&PathBuf, &VfsFile)> {
         self.file_map.iter()
     }
 
+    /// Like `iter`, but in sorted path order rather than hash order, so output written while
+    /// walking the VFS is the same on every run. Shares the index `paths_with_indexed` uses.
+    pub fn iter_sorted(&self) -> impl Iterator<Item = VFSTuple<'_>> {
+        self.prefix_index()
+            .iter()
+            .map(|path| (path.as_path(), &self.file_map[path]))
+    }
+
     pub fn par_iter(&self) -> impl ParallelIterator<Item = (&PathBuf, &VfsFile)> {
         self.file_map.par_iter()
     }
@@ -56,9 +497,7 @@ impl VFS {
         &self,
         substring: S,
     ) -> impl Iterator<Item = VFSTuple<'_>> {
-        let normalized_substring = normalize_path(substring.as_ref())
-            .to_string_lossy()
-            .into_owned();
+        let normalized_substring = normalize_str(substring.as_ref());
 
         self.file_map.iter().filter_map(move |(path, file)| {
             if path.to_string_lossy().contains(&normalized_substring) {
@@ -74,9 +513,7 @@ impl VFS {
         &self,
         substring: S,
     ) -> impl ParallelIterator<Item = VFSTuple<'_>> {
-        let normalized_substring = normalize_path(substring.as_ref())
-            .to_string_lossy()
-            .into_owned();
+        let normalized_substring = normalize_str(substring.as_ref());
 
         self.file_map.par_iter().filter_map(move |(path, file)| {
             if path.to_string_lossy().contains(&normalized_substring) {
@@ -100,6 +537,41 @@ impl VFS {
         })
     }
 
+    /// Sorts every path in the VFS into an index, so `paths_with_indexed` can binary search for
+    /// a prefix instead of checking each file. The index holds a copy of every path, and is
+    /// rebuilt on the next query after the VFS changes.
+    ///
+    /// `paths_with_indexed` builds it on first use anyway, so calling this up front only moves
+    /// that cost out of the first query.
+    pub fn build_prefix_index(&self) {
+        self.prefix_index();
+    }
+
+    fn prefix_index(&self) -> &[PathBuf] {
+        self.prefix_index.get_or_init(|| {
+            let mut paths: Vec<PathBuf> = self.file_map.keys().cloned().collect();
+            paths.par_sort_unstable();
+            paths
+        })
+    }
+
+    /// Like `paths_with`, but binary searches the prefix index instead of scanning every file,
+    /// and yields paths in sorted order. Worth it when querying many prefixes of the same VFS.
+    pub fn paths_with_indexed<P: AsRef<Path>>(
+        &self,
+        prefix: P,
+    ) -> impl Iterator<Item = VFSTuple<'_>> {
+        let normalized_prefix = normalize_path(&prefix);
+        let index = self.prefix_index();
+        // Paths sort component by component, so everything below a prefix sits in one run
+        let start = index.partition_point(|path| *path < normalized_prefix);
+
+        index[start..]
+            .iter()
+            .take_while(move |path| path.starts_with(&normalized_prefix))
+            .map(|path| (path.as_path(), &self.file_map[path]))
+    }
+
     /// Given a path prefix to a location in the VFS, return an iterator to *all* of its contents.
     pub fn par_paths_with<P: AsRef<Path>>(
         &self,
@@ -118,155 +590,984 @@ impl VFS {
 
     /// Returns a parallel iterator meant to be fed into par_extend
     /// Only used when appending a directory or set of directories into the file map
+    ///
+    /// The walk stops early once `cancel` is set, leaving the contents incomplete.
     fn directory_contents_to_file_map<I: AsRef<Path> + Sync>(
         dir: I,
-    ) -> impl ParallelIterator<Item = (PathBuf, VfsFile)> {
+        options: &ScanOptions,
+        cancel: &AtomicBool,
+    ) -> impl ParallelIterator<Item = WalkedFile> {
         let dir = dir.as_ref().to_path_buf();
+        let root: Arc<Path> = Arc::from(dir.as_path());
+        let skip_unreadable = options.skip_unreadable;
+        let options = options.clone();
 
         WalkDir::new(&dir)
             .follow_links(true)
+            .max_depth(options.max_depth.unwrap_or(usize::MAX))
             .into_iter()
+            .take_while(|_| !cancel.load(Ordering::Relaxed))
             .filter_map(|entry| entry.ok().filter(|e| e.file_type().is_file()))
             .par_bridge()
-            .map(move |entry| {
+            .filter_map(move |entry| {
                 let path = entry.path();
                 let target_path = path
                     .strip_prefix(&dir)
                     .expect("Entry path should always be prefixed by scan directory!");
 
                 let normalized_path = normalize_path(target_path);
+                if options.ignore.is_ignored(&normalized_path) {
+                    return None;
+                }
+
+                if skip_unreadable && let Err(error) = Self::check_readable(path) {
+                    return Some(Err(UnreadableFile {
+                        path: path.to_path_buf(),
+                        error,
+                    }));
+                }
 
-                let vfs_file = VfsFile::from(path);
-                (normalized_path, vfs_file)
+                let vfs_file =
+                    VfsFile::from_source_dir(options.source_path(path), Arc::clone(&root));
+                Some(Ok((normalized_path, vfs_file)))
             })
     }
 
-    #[allow(unused_variables)]
-    pub fn from_directories(
-        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
-        archive_list: Option<Vec<&str>>,
-    ) -> Self {
-        let mut vfs = Self::new();
+    /// Walks `dir` the same way construction does, returning every symlink whose target is
+    /// missing, sorted by path. Construction leaves these out without a word, so this explains
+    /// files which vanished from the VFS after their link target moved.
+    pub fn broken_links(dir: impl AsRef<Path>, options: &ScanOptions) -> Vec<BrokenLink> {
+        let mut broken: Vec<BrokenLink> = WalkDir::new(dir)
+            .follow_links(true)
+            .max_depth(options.max_depth.unwrap_or(usize::MAX))
+            .into_iter()
+            .filter_map(|entry| entry.err())
+            .filter(|err| err.loop_ancestor().is_none())
+            .filter_map(|err| {
+                let link = err.path()?;
+                // A dangling link can't be followed, but can still be read
+                let target = std::fs::read_link(link).ok()?;
+
+                (!link.exists()).then(|| BrokenLink {
+                    link: link.to_path_buf(),
+                    target,
+                })
+            })
+            .collect();
+
+        broken.sort_by(|a, b| a.link.cmp(&b.link));
+        broken
+    }
+
+    /// Whether a file found during a walk can actually be stat'd and opened, and why not
+    fn check_readable(path: &Path) -> Result<()> {
+        std::fs::metadata(path).and_then(|_| std::fs::File::open(path))?;
+        Ok(())
+    }
 
-        let map: HashMap<PathBuf, VfsFile> = search_dirs
+    /// Walks every search directory in parallel, returning each one's contents in load order
+    /// along with the files left out as unreadable, or a cancellation error if `cancel` is set
+    /// before every directory is walked
+    fn scan_directories(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+        options: &ScanOptions,
+        cancel: &AtomicBool,
+    ) -> Result<(ScannedDirs, Vec<UnreadableFile>)> {
+        let walked: Vec<(PathBuf, Vec<WalkedFile>)> = search_dirs
             .into_par_iter()
-            .flat_map(Self::directory_contents_to_file_map)
+            .map(|dir| {
+                check_cancelled(cancel)?;
+
+                let dir = options.source_path(dir.as_ref());
+                let contents =
+                    Self::directory_contents_to_file_map(&dir, options, cancel).collect();
+
+                // A cancelled walk returns whatever it found so far, which must not be mistaken
+                // for the directory's full contents
+                check_cancelled(cancel)?;
+                Ok((dir, contents))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut unreadable = Vec::new();
+        let mut scanned: ScannedDirs = walked
+            .into_iter()
+            .map(|(dir, contents)| {
+                let mut found = Vec::with_capacity(contents.len());
+
+                for file in contents {
+                    match file {
+                        Ok(file) => found.push(file),
+                        Err(skipped) => unreadable.push(skipped),
+                    }
+                }
+
+                (dir, found)
+            })
             .collect();
+        unreadable.sort_by(|a, b| a.path.cmp(&b.path));
 
-        #[cfg(feature = "bsa")]
-        if let Some(list) = archive_list {
-            let archive_handles = archives::from_set(&map, list);
+        options.apply_priority_overrides(&mut scanned);
+        Ok((scanned, unreadable))
+    }
 
-            vfs.file_map.par_extend(archives::file_map(archive_handles));
-        }
+    /// Builds only the file map from loose files in `search_dirs`, later directories winning.
+    ///
+    /// Archives aren't opened and shadowed copies aren't kept, so this is the cheapest way to
+    /// learn what a set of directories contains.
+    pub fn scan_only(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+    ) -> Self {
+        let mut vfs = Self::new();
+
+        let never_cancelled = AtomicBool::new(false);
+        // Default options never skip unreadable files, so there are none to keep
+        let (dir_contents, _) =
+            Self::scan_directories(search_dirs, &ScanOptions::default(), &never_cancelled)
+                .expect("Scanning only fails when cancelled");
 
-        vfs.file_map.par_extend(map);
+        for (dir, contents) in dir_contents {
+            vfs.source_dirs.push((dir, contents.len()));
+            vfs.file_map.extend(contents);
+        }
 
         vfs
     }
 
-    /// Returns a sorted version of the VFS contents as a binary tree
-    /// Easier to display.
-    pub fn tree(&self, relative: bool) -> DisplayTree {
-        let mut tree: DisplayTree = BTreeMap::new();
-        let root_path: PathBuf = if relative { "Data Files" } else { "/" }.into();
-
-        tree.insert(root_path.clone(), DirectoryNode::new());
-
-        for (key, entry) in &self.file_map {
-            let path = PathBuf::from(
-                if relative {
-                    entry.parent_archive_name()
-                } else {
-                    entry.parent_archive_path()
-                }
-                .map_or_else(
-                    || {
-                        if relative {
-                            key.into()
-                        } else {
-                            entry.path().to_path_buf()
-                        }
-                    },
-                    |parent| PathBuf::from(parent).join(key),
-                ),
-            );
+    /// Builds the VFS from loose files in `search_dirs` and, if given, the listed archives found
+    /// inside of them. Later directories take priority over earlier ones, and all loose files
+    /// take priority over archives.
+    ///
+    /// Construction only fills the file map and its bookkeeping. Display trees are never built
+    /// here, only on request through `tree` or `tree_filtered`.
+    pub fn from_directories(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+        archive_list: Option<Vec<&str>>,
+    ) -> Self {
+        Self::from_directories_with(search_dirs, archive_list, &ScanOptions::default())
+    }
 
-            let parent = path
-                .parent()
-                .filter(|p| !p.as_os_str().is_empty())
-                .unwrap_or_else(|| root_path.as_path());
+    /// Same as `from_directories`, but walks the data directories according to `options`
+    pub fn from_directories_with(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+        archive_list: Option<Vec<&str>>,
+        options: &ScanOptions,
+    ) -> Self {
+        let never_cancelled = AtomicBool::new(false);
 
-            let mut current_path = PathBuf::new();
-            let mut current_node = tree
-                .get_mut(&root_path)
-                .expect("Root path should be guaranteed to always exist!");
+        Self::from_directories_cancellable(search_dirs, archive_list, options, &never_cancelled)
+            .expect("Construction only fails when cancelled")
+    }
 
-            for component in parent.components() {
-                current_path.push(component);
+    /// Same as `from_directories_with`, but gives up once `cancel` is set, eg from a GUI's
+    /// cancel button on another thread. The flag is checked between each stage of construction,
+    /// by every parallel directory walk, and for each file walked.
+    ///
+    /// A cancelled build returns an `ErrorKind::Interrupted` error, and nothing of the partial
+    /// VFS is kept.
+    #[allow(unused_variables)]
+    pub fn from_directories_cancellable(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+        archive_list: Option<Vec<&str>>,
+        options: &ScanOptions,
+        cancel: &AtomicBool,
+    ) -> Result<Self> {
+        let mut vfs = Self::new();
 
-                if current_path == root_path {
-                    continue;
-                }
+        let (dir_contents, unreadable) = Self::scan_directories(search_dirs, options, cancel)?;
+        vfs.unreadable = unreadable;
 
-                current_node = current_node
-                    .subdirs
-                    .entry(current_path.clone())
-                    .or_insert_with(DirectoryNode::new);
+        // Later directories take priority, so they must be merged in order
+        let mut map: HashMap<PathBuf, VfsFile> = HashMap::new();
+        let mut loose_shadowed: HashMap<PathBuf, Vec<VfsFile>> = HashMap::new();
+        for (dir, contents) in dir_contents {
+            vfs.source_dirs.push((dir, contents.len()));
+
+            for (path, file) in contents {
+                if let Some(previous) = map.insert(path.clone(), file)
+                    && previous.path() != map[&path].path()
+                {
+                    loose_shadowed.entry(path).or_default().push(previous);
+                }
             }
+        }
 
-            let new_file = match entry.is_archive() {
-                false => VfsFile::from(entry.path()),
-                #[cfg(feature = "bsa")]
-                true => VfsFile::from_archive(
-                    path.to_string_lossy(),
-                    entry.parent_archive_handle().unwrap(),
-                ),
-                #[cfg(not(feature = "bsa"))]
-                true => unimplemented!(
-                    "BSA archives are not supported in this build. Enable the 'bsa' feature of vfstool_lib to use them."
-                ),
-            };
+        // Archives sit below every loose file, in the order they were listed
+        #[cfg(feature = "bsa")]
+        if let Some(list) = archive_list {
+            check_cancelled(cancel)?;
 
-            current_node.files.push(new_file);
+            let data_dirs: Vec<PathBuf> =
+                vfs.source_dirs.iter().map(|(dir, _)| dir.clone()).collect();
+            let (opened, repeated) = archives::from_set(&map, list, &data_dirs);
+            let contributing: archives::ArchiveList = opened
+                .iter()
+                .filter(|archive| !(options.ignore_invalidation && archive.is_invalidation()))
+                .cloned()
+                .collect();
+
+            for (path, file) in archives::entries(&contributing) {
+                vfs.insert_normalized(path, file);
+            }
+
+            vfs.archives = opened;
+            vfs.repeated_archives = repeated;
         }
 
-        tree.get_mut(&root_path)
-            .expect("Root path should be guaranteed to always exist!")
-            .sort();
+        check_cancelled(cancel)?;
 
-        tree
+        for (path, file) in map {
+            if let Some(mut earlier) = loose_shadowed.remove(&path) {
+                vfs.insert_normalized(path.clone(), file);
+                vfs.shadowed.entry(path).or_default().append(&mut earlier);
+            } else {
+                vfs.insert_normalized(path, file);
+            }
+        }
+
+        Ok(vfs)
     }
 
-    /// Return a matching set of vfs entries from filter predicates for directories and files
-    /// Might be empty.
-    pub fn tree_filtered(
-        &self,
-        relative: bool,
-        file_filter: impl Fn(&VfsFile) -> bool,
-    ) -> DisplayTree {
-        let mut tree = self.tree(relative);
+    /// Builds a VFS from the contents of a single archive, without any data directories, so an
+    /// archive can be inspected on its own. Fails if `path` isn't a readable archive.
+    #[cfg(feature = "bsa")]
+    pub fn from_archive(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let archive = archives::open(path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a readable BSA or BA2 archive", path.display()),
+            )
+        })?;
 
-        tree.iter_mut().for_each(|(_root_dir, files)| {
-            files.filter(&file_filter);
-        });
+        let opened = vec![archive];
+        let mut vfs = Self::new();
 
-        tree
-    }
+        for (path, file) in archives::entries(&opened) {
+            vfs.insert_normalized(path, file);
+        }
 
-    /// String formatter for the file tree
-    /// Includes a newline, so caller is responsible for using the appropriate writer
-    fn file_str<S: AsRef<str> + std::fmt::Display>(file: S) -> String {
-        format!("{}{}\n", Self::FILE_PREFIX, file,)
+        vfs.archives = opened;
+        Ok(vfs)
     }
 
-    /// String formatter for the file tree
-    /// Includes a newline, so caller is responsible for using the appropriate writer
-    fn dir_str<S: AsRef<str> + std::fmt::Display>(dir: S) -> String {
-        format!("{}{}/\n", Self::DIR_PREFIX, dir,)
-    }
+    /// Like `from_directories_with`, but returns as soon as the loose files are scanned. The
+    /// listed archives are only located, and are left for the returned `ArchiveLoader` to open,
+    /// eg on another thread. Pass what it loads to `merge_archives` to end up with the same VFS
+    /// `from_directories_with` builds.
+    ///
+    /// ```no_run
+    /// use vfstool_lib::{ScanOptions, VFS};
+    ///
+    /// let (mut vfs, loader) =
+    ///     VFS::from_directories_lazy(["Data Files"], vec!["Morrowind.bsa"], &ScanOptions::default());
+    /// let loading = std::thread::spawn(move || loader.load());
+    ///
+    /// // Loose files can be looked up right away
+    /// let _ = vfs.get_file("meshes/xbase_anim.nif");
+    ///
+    /// vfs.merge_archives(loading.join().unwrap());
+    /// ```
+    #[cfg(feature = "bsa")]
+    pub fn from_directories_lazy(
+        search_dirs: impl IntoParallelIterator<Item = impl AsRef<Path> + Sync>,
+        archive_list: Vec<&str>,
+        options: &ScanOptions,
+    ) -> (Self, ArchiveLoader) {
+        let mut vfs = Self::from_directories_with(search_dirs, None, options);
 
-    /// Return whether any relative path in the vfs corresponds to the absolute path given
+        let data_dirs: Vec<PathBuf> = vfs.source_dirs.iter().map(|(dir, _)| dir.clone()).collect();
+        let (located, repeated) = archives::locate_set(&vfs.file_map, archive_list, &data_dirs);
+        vfs.repeated_archives = repeated;
+        let loader = ArchiveLoader {
+            located,
+            ignore_invalidation: options.ignore_invalidation,
+        };
+
+        (vfs, loader)
+    }
+
+    /// Adds archives loaded by an `ArchiveLoader` below every loose file, as `from_directories`
+    /// would have. Loose files keep winning, and the archived copies they hide are kept as
+    /// shadowed.
+    #[cfg(feature = "bsa")]
+    pub fn merge_archives(&mut self, loaded: LoadedArchives) {
+        let mut archive_layer = Self::new();
+
+        let contributing: archives::ArchiveList = loaded
+            .archives
+            .iter()
+            .filter(|archive| !(loaded.ignore_invalidation && archive.is_invalidation()))
+            .cloned()
+            .collect();
+
+        for (path, file) in archives::entries(&contributing) {
+            archive_layer.insert_normalized(path, file);
+        }
+
+        archive_layer.archives = loaded.archives;
+        let unreadable = std::mem::take(&mut self.unreadable);
+        let repeated_archives = std::mem::take(&mut self.repeated_archives);
+
+        *self = archive_layer.overlay(self);
+        self.unreadable = unreadable;
+        self.repeated_archives = repeated_archives;
+    }
+
+    /// Layers `higher` over this VFS without touching the filesystem, as if its sources were
+    /// loaded after these. `higher` wins every path both contain, and the copies it replaces are
+    /// kept as shadowed. Files and archives are shared with both inputs rather than reopened.
+    ///
+    /// Sources are listed as this VFS's followed by `higher`'s, so archives from `higher` still
+    /// rank below every data directory in `priority_of`.
+    pub fn overlay(&self, higher: &VFS) -> VFS {
+        let mut vfs = Self::new();
+
+        vfs.source_dirs = self
+            .source_dirs
+            .iter()
+            .chain(&higher.source_dirs)
+            .cloned()
+            .collect();
+
+        #[cfg(feature = "bsa")]
+        {
+            vfs.archives = self
+                .archives
+                .iter()
+                .chain(&higher.archives)
+                .cloned()
+                .collect();
+        }
+
+        for layer in [self, higher] {
+            for (path, file) in &layer.file_map {
+                for hidden in layer.shadowed.get(path).into_iter().flatten() {
+                    vfs.insert_normalized(path.clone(), hidden.clone());
+                }
+
+                vfs.insert_normalized(path.clone(), file.clone());
+            }
+        }
+
+        vfs
+    }
+
+    /// A copy of this VFS with every file inside of the directory `from` moved under `to`, eg to
+    /// preview what reorganizing `textures` into `textures/tx` would look like. Files are shared
+    /// with this VFS rather than reopened, and its sources are kept as they are.
+    ///
+    /// A moved file wins over any file already at its new path, like a moved folder overwriting
+    /// the destination, and the file it replaces is kept as shadowed.
+    pub fn with_prefix_remapped(&self, from: &Path, to: &Path) -> VFS {
+        let from = normalize_path(from);
+        let to = normalize_path(to);
+
+        let mut vfs = Self::new();
+        vfs.source_dirs = self.source_dirs.clone();
+        #[cfg(feature = "bsa")]
+        {
+            vfs.archives = self.archives.clone();
+        }
+
+        let (moved, kept): (Vec<_>, Vec<_>) = self
+            .file_map
+            .iter()
+            .partition(|(path, _file)| path.starts_with(&from));
+        let moved = moved.into_iter().map(|(path, file)| {
+            let relative = path
+                .strip_prefix(&from)
+                .expect("Moved paths should start with the remapped prefix!");
+            (path, to.join(relative), file)
+        });
+        let kept = kept
+            .into_iter()
+            .map(|(path, file)| (path, path.clone(), file));
+
+        // Moved files go in last, so they win at any path they land on
+        for (old_path, new_path, file) in kept.chain(moved) {
+            for hidden in self.shadowed.get(old_path).into_iter().flatten() {
+                vfs.insert_normalized(new_path.clone(), hidden.clone());
+            }
+
+            vfs.insert_normalized(new_path, file.clone());
+        }
+
+        vfs
+    }
+
+    /// Drops every file `predicate` rejects, in place, so a VFS can be narrowed step by step
+    /// without rebuilding it. The predicate is given each file's normalized VFS path. Copies
+    /// shadowed by a dropped file are dropped along with it.
+    pub fn retain(&mut self, predicate: impl Fn(&Path, &VfsFile) -> bool) {
+        self.prefix_index.take();
+        self.file_map.retain(|path, file| predicate(path, file));
+        self.shadowed
+            .retain(|path, _| self.file_map.contains_key(path));
+    }
+
+    /// Drops the loose `.bsa` and `.ba2` files which were loaded as archives of this VFS, so
+    /// listings show each archive's contents without the container next to them. Archives which
+    /// sit in a data directory without being loaded are kept, as nothing stands in for them.
+    #[cfg(feature = "bsa")]
+    pub fn hide_archive_files(&mut self) {
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let loaded: std::collections::HashSet<PathBuf> = self
+            .archives
+            .iter()
+            .map(|archive| canonical(archive.path()))
+            .collect();
+
+        self.retain(|_path, file| {
+            let is_container = !file.is_archive()
+                && file.extension().is_some_and(|ext| {
+                    ext.eq_ignore_ascii_case("bsa") || ext.eq_ignore_ascii_case("ba2")
+                });
+
+            !(is_container && loaded.contains(&canonical(file.path())))
+        });
+    }
+
+    /// Keeps up to `capacity_bytes` of decompressed TES4 and FO4 entries in memory, so opening
+    /// the same archived file again skips decompression. One cache is shared by every archive.
+    #[cfg(feature = "bsa")]
+    pub fn with_cache(self, capacity_bytes: usize) -> Self {
+        let cache = Arc::new(archives::EntryCache::new(capacity_bytes));
+
+        for archive in &self.archives {
+            archive.set_cache(Arc::clone(&cache));
+        }
+
+        self
+    }
+
+    /// Every archive which was opened while building this VFS, in load order
+    #[cfg(feature = "bsa")]
+    pub fn archives(&self) -> &[Arc<archives::StoredArchive>] {
+        &self.archives
+    }
+
+    /// Files `ScanOptions::skip_unreadable` left out while building this VFS, with why each one
+    /// couldn't be read, sorted by path. Always empty unless that option was set.
+    pub fn unreadable(&self) -> &[UnreadableFile] {
+        &self.unreadable
+    }
+
+    /// Archives which were listed more than once when building this VFS, sorted. Each one is
+    /// only loaded at its last entry in the list.
+    #[cfg(feature = "bsa")]
+    pub fn repeated_archives(&self) -> &[PathBuf] {
+        &self.repeated_archives
+    }
+
+    /// Lists every data directory and archive this VFS was built from, in load order.
+    pub fn sources(&self) -> Sources {
+        Sources {
+            dirs: self.source_dirs.clone(),
+            #[cfg(feature = "bsa")]
+            archives: self
+                .archives
+                .iter()
+                .map(|archive| {
+                    (
+                        archive.path().to_path_buf(),
+                        archive.format(),
+                        archive.len(),
+                    )
+                })
+                .collect(),
+            #[cfg(not(feature = "bsa"))]
+            archives: Vec::new(),
+        }
+    }
+
+    /// Sums the size of every file in the VFS, as `VfsFile::size` reports it, along with how many
+    /// files couldn't be sized and were counted as zero. Files are stat'd in parallel.
+    pub fn total_bytes(&self) -> (u64, usize) {
+        self.file_map
+            .par_iter()
+            .map(|(_path, file)| match file.size() {
+                Ok(size) => (size, 0),
+                Err(_) => (0, 1),
+            })
+            .reduce(
+                || (0, 0),
+                |(bytes, unknown), (other_bytes, other_unknown)| {
+                    (bytes + other_bytes, unknown + other_unknown)
+                },
+            )
+    }
+
+    /// Summarizes every archive which was opened while building this VFS, in load order, with
+    /// how many of its files win over every other copy
+    pub fn archive_report(&self) -> Vec<ArchiveStats> {
+        #[cfg(feature = "bsa")]
+        {
+            let mut winning: HashMap<*const archives::StoredArchive, usize> = HashMap::new();
+
+            for file in self.file_map.values() {
+                if let Ok(archive) = file.parent_archive_handle() {
+                    *winning.entry(Arc::as_ptr(&archive)).or_default() += 1;
+                }
+            }
+
+            self.archives
+                .iter()
+                .enumerate()
+                .map(|(priority, archive)| ArchiveStats {
+                    path: archive.path().to_path_buf(),
+                    priority,
+                    format: archive.format(),
+                    entries: archive.len(),
+                    winning: winning.get(&Arc::as_ptr(archive)).copied().unwrap_or(0),
+                    size: std::fs::metadata(crate::long_path(archive.path()))
+                        .map(|metadata| metadata.len())
+                        .ok(),
+                })
+                .collect()
+        }
+
+        #[cfg(not(feature = "bsa"))]
+        Vec::new()
+    }
+
+    /// Every path with copies hidden by a higher-priority source, along with those hidden copies
+    /// (lowest priority first) and the copy which won
+    pub fn shadowed(&self) -> impl Iterator<Item = (&PathBuf, &[VfsFile], &VfsFile)> {
+        self.shadowed.iter().map(|(path, hidden)| {
+            let winner = self
+                .file_map
+                .get(path)
+                .expect("Shadowed paths should always have a winning file!");
+
+            (path, hidden.as_slice(), winner)
+        })
+    }
+
+    /// Sorted summary of `shadowed`, naming the winning and hidden sources of each path
+    pub fn shadowed_report(&self) -> BTreeMap<PathBuf, ShadowedFile> {
+        self.shadowed()
+            .map(|(path, hidden, winner)| {
+                (
+                    path.clone(),
+                    ShadowedFile {
+                        winner: source_of(winner),
+                        shadowed: hidden.iter().map(source_of).collect(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Every path where an archived copy wins over a loose file, naming the winning archive and
+    /// the loose copies it hides. OpenMW always ranks loose files above archives, and so does
+    /// construction, so this is only non-empty for VFSes layered by hand, eg with `overlay`,
+    /// where it points at a layer applied in the wrong order.
+    pub fn archives_over_loose(&self) -> BTreeMap<PathBuf, ShadowedFile> {
+        self.shadowed()
+            .filter(|(_path, _hidden, winner)| winner.is_archive())
+            .filter_map(|(path, hidden, winner)| {
+                let loose: Vec<PathBuf> = hidden
+                    .iter()
+                    .filter(|file| !file.is_archive())
+                    .map(source_of)
+                    .collect();
+
+                (!loose.is_empty()).then(|| {
+                    (
+                        path.clone(),
+                        ShadowedFile {
+                            winner: source_of(winner),
+                            shadowed: loose,
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Every path whose copies across sources are spelled with differing case, along with each
+    /// distinct spelling, lowest priority first. These load fine in OpenMW, but clash when
+    /// the VFS is collapsed onto a case-sensitive filesystem.
+    ///
+    /// TES4 archive entries are skipped, since their original spelling isn't kept.
+    pub fn case_conflicts(&self) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+        self.shadowed()
+            .filter_map(|(path, hidden, winner)| {
+                let mut spellings: Vec<PathBuf> = Vec::new();
+
+                for spelled in hidden
+                    .iter()
+                    .chain([winner])
+                    .filter_map(VfsFile::spelled_path)
+                {
+                    if !spellings.contains(&spelled) {
+                        spellings.push(spelled);
+                    }
+                }
+
+                (spellings.len() > 1).then(|| (path.clone(), spellings))
+            })
+            .collect()
+    }
+
+    /// Compares this VFS against another one.
+    /// A file's source is its parent archive for archived files, or its full path for loose ones.
+    /// All lists are sorted by VFS path.
+    pub fn diff(&self, other: &VFS) -> VfsDiff {
+        let mut diff = VfsDiff::default();
+
+        for (path, file) in &self.file_map {
+            match other.file_map.get(path) {
+                None => diff.only_in_self.push(path.clone()),
+                Some(other_file) => {
+                    let (ours, theirs) = (source_of(file), source_of(other_file));
+                    if ours != theirs {
+                        diff.changed.push((path.clone(), ours, theirs));
+                    }
+                }
+            }
+        }
+
+        diff.only_in_other = other
+            .file_map
+            .keys()
+            .filter(|path| !self.file_map.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff.only_in_self.sort();
+        diff.only_in_other.sort();
+        diff.changed.sort();
+
+        diff
+    }
+
+    /// Every source path in OpenMW resolution order: archives first, then data directories.
+    /// A source's position here is its priority, and later sources win.
+    fn source_order(&self) -> impl Iterator<Item = &Path> {
+        #[cfg(feature = "bsa")]
+        let archives = self.archives.iter().map(|archive| archive.path());
+        #[cfg(not(feature = "bsa"))]
+        let archives = std::iter::empty();
+
+        archives.chain(self.source_dirs.iter().map(|(dir, _count)| dir.as_path()))
+    }
+
+    /// Zero-based load-order index of the data directory or archive which provided `file`.
+    /// Archives come first, followed by data directories, so a higher index always wins.
+    ///
+    /// Returns `None` for files which weren't loaded from one of this VFS's sources.
+    pub fn priority_of(&self, file: &VfsFile) -> Option<usize> {
+        let root = file.source();
+        self.source_order().position(|source| source == root)
+    }
+
+    /// Returns a sorted version of the VFS contents as a binary tree
+    /// Easier to display.
+    ///
+    /// `display` picks the layout, see `PathDisplay`. A `bool` may be passed instead, where
+    /// `true` means `PathDisplay::Relative`.
+    pub fn tree(&self, display: impl Into<PathDisplay>) -> DisplayTree {
+        Self::tree_from(
+            self.file_map
+                .iter()
+                .map(|(key, file)| (key.as_path(), file)),
+            display.into(),
+        )
+    }
+
+    /// How many directories deep the deepest file in the VFS is, where files at the root of the
+    /// VFS are at depth 0. Matches `depth` of the root node `tree` builds, without building it.
+    pub fn max_depth(&self) -> usize {
+        self.file_map
+            .keys()
+            .map(|path| path.components().count().saturating_sub(1))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Like `tree`, but only containing files inside of the VFS directory `prefix`, eg
+    /// `music/explore`. Matches the same files as `paths_with`.
+    pub fn tree_under<P: AsRef<Path>>(
+        &self,
+        display: impl Into<PathDisplay>,
+        prefix: P,
+    ) -> DisplayTree {
+        Self::tree_from(self.paths_with(prefix), display.into())
+    }
+
+    /// Like `tree`, but builds each top-level directory on its own thread. Gives the same result
+    /// as `tree`, which is still faster for small VFSes.
+    pub fn par_tree(&self, display: impl Into<PathDisplay>) -> DisplayTree {
+        let display = display.into();
+        let root_path = display.root();
+
+        let entries: Vec<_> = self
+            .file_map
+            .par_iter()
+            .map(|(key, entry)| (display_path(key, entry, display), entry))
+            .collect();
+
+        // Relative directories are merged ignoring case, so every spelling of a top-level
+        // directory must end up in the same group
+        let mut groups: HashMap<Option<PathBuf>, Vec<(PathBuf, &VfsFile)>> = HashMap::new();
+        for (path, entry) in entries {
+            let top_level = top_level_dir(&path, &root_path).map(normalize_path);
+            groups.entry(top_level).or_default().push((path, entry));
+        }
+
+        let nodes: Vec<DirectoryNode> = groups
+            .into_par_iter()
+            .map(|(_top_level, mut entries)| {
+                entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+                Self::node_from(entries, &root_path, display)
+            })
+            .collect();
+
+        let mut root = DirectoryNode::new();
+        root.show_sources = display == PathDisplay::Both;
+
+        for node in nodes {
+            root.files.extend(node.files);
+            root.subdirs.extend(node.subdirs);
+        }
+
+        BTreeMap::from([(root_path, root)])
+    }
+
+    fn tree_from<'a>(
+        entries: impl Iterator<Item = VFSTuple<'a>>,
+        display: PathDisplay,
+    ) -> DisplayTree {
+        let root_path = display.root();
+
+        // Sorted so the spelling `DirSpellings` keeps doesn't depend on hash order
+        let mut entries: Vec<_> = entries
+            .map(|(key, entry)| (display_path(key, entry, display), entry))
+            .collect();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let root = Self::node_from(entries, &root_path, display);
+        BTreeMap::from([(root_path, root)])
+    }
+
+    /// Builds the sorted node at `root_path` holding `entries`, which must be sorted by path
+    fn node_from(
+        entries: Vec<(PathBuf, &VfsFile)>,
+        root_path: &Path,
+        display: PathDisplay,
+    ) -> DirectoryNode {
+        let show_sources = display == PathDisplay::Both;
+        let mut root = DirectoryNode::new();
+        root.show_sources = show_sources;
+
+        let mut spellings = (display == PathDisplay::Relative).then(DirSpellings::default);
+
+        for (path, entry) in entries {
+            let parent = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(root_path);
+            let parent = match &mut spellings {
+                Some(spellings) => spellings.unify(parent),
+                None => parent.to_path_buf(),
+            };
+
+            let mut current_path = PathBuf::new();
+            let mut current_node = &mut root;
+
+            for component in parent.components() {
+                current_path.push(component);
+
+                if current_path == root_path {
+                    continue;
+                }
+
+                current_node = current_node
+                    .subdirs
+                    .entry(current_path.clone())
+                    .or_insert_with(|| DirectoryNode {
+                        show_sources,
+                        ..DirectoryNode::new()
+                    });
+            }
+
+            let new_file = match entry.is_archive() {
+                false => entry.clone(),
+                #[cfg(feature = "bsa")]
+                true => VfsFile::from_archive(
+                    path.to_string_lossy(),
+                    entry.parent_archive_handle().unwrap(),
+                ),
+                #[cfg(not(feature = "bsa"))]
+                true => unimplemented!(
+                    "BSA archives are not supported in this build. Enable the 'bsa' feature of vfstool_lib to use them."
+                ),
+            };
+
+            current_node.files.push(new_file);
+        }
+
+        root.sort();
+        root
+    }
+
+    /// Visits the same layout `tree` builds, in the order it is displayed, without building it.
+    /// Each directory's files come first, sorted by name, followed by its subdirectories.
+    ///
+    /// Files are passed as they're stored in the VFS, so archived files keep their archive path
+    /// rather than the display path `tree` gives them.
+    pub fn walk(&self, display: impl Into<PathDisplay>, visitor: &mut dyn FnMut(WalkEvent<'_>)) {
+        fn parent_and_name(path: &Path) -> (&Path, &OsStr) {
+            (
+                path.parent().unwrap_or(Path::new("")),
+                path.file_name().unwrap_or_default(),
+            )
+        }
+
+        let display = display.into();
+        let root_path = display.root();
+
+        let mut paths: Vec<(PathBuf, &VfsFile)> = self
+            .file_map
+            .iter()
+            .map(|(key, entry)| (display_path(key, entry, display), entry))
+            .collect();
+
+        // Sorting by parent first gives a depth-first order with files ahead of subdirs, and puts
+        // every file of a directory next to each other, so each directory is only stored once
+        paths.sort_by(|(a, _), (b, _)| parent_and_name(a).cmp(&parent_and_name(b)));
+
+        let mut spellings = (display == PathDisplay::Relative).then(DirSpellings::default);
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut entries: Vec<(usize, &OsStr, &VfsFile)> = Vec::with_capacity(paths.len());
+        let mut last_parent = None;
+
+        for (path, entry) in &paths {
+            let (parent, name) = parent_and_name(path);
+
+            if last_parent != Some(parent) {
+                dirs.push(match &mut spellings {
+                    Some(spellings) => spellings.unify(parent),
+                    None => parent.to_path_buf(),
+                });
+                last_parent = Some(parent);
+            }
+
+            entries.push((dirs.len() - 1, name, entry));
+        }
+
+        // Relative directories are respelled like `tree` does, which means sorting again after
+        if spellings.is_some() {
+            entries.sort_by(|a, b| (&dirs[a.0], a.1).cmp(&(&dirs[b.0], b.1)));
+        }
+
+        visitor(WalkEvent::EnterDir(&root_path));
+        let mut open_dirs: Vec<PathBuf> = Vec::new();
+        let mut current_dir: Option<&Path> = None;
+
+        for &(dir, _name, entry) in &entries {
+            let parent = dirs[dir].as_path();
+
+            if current_dir != Some(parent) {
+                while open_dirs.last().is_some_and(|dir| !parent.starts_with(dir)) {
+                    let dir = open_dirs
+                        .pop()
+                        .expect("Directory stack was checked to be non-empty");
+                    visitor(WalkEvent::LeaveDir(&dir));
+                }
+
+                let mut current_path = open_dirs.last().cloned().unwrap_or_default();
+                let remaining = parent
+                    .strip_prefix(&current_path)
+                    .expect("Open directories are always ancestors of the current file");
+
+                for component in remaining.components() {
+                    current_path.push(component);
+
+                    if current_path == root_path {
+                        continue;
+                    }
+
+                    visitor(WalkEvent::EnterDir(&current_path));
+                    open_dirs.push(current_path.clone());
+                }
+
+                current_dir = Some(parent);
+            }
+
+            visitor(WalkEvent::File(entry));
+        }
+
+        while let Some(dir) = open_dirs.pop() {
+            visitor(WalkEvent::LeaveDir(&dir));
+        }
+
+        visitor(WalkEvent::LeaveDir(&root_path));
+    }
+
+    /// Return a matching set of vfs entries from filter predicates for directories and files
+    /// Might be empty.
+    pub fn tree_filtered(
+        &self,
+        display: impl Into<PathDisplay>,
+        file_filter: impl Fn(&VfsFile) -> bool,
+    ) -> DisplayTree {
+        let mut tree = self.tree(display);
+
+        tree.iter_mut().for_each(|(_root_dir, files)| {
+            files.filter(&file_filter);
+        });
+
+        tree
+    }
+
+    /// Like `tree_filtered`, but with a root per source rather than one for the whole VFS. Each
+    /// data directory and archive which provides a file `file_filter` keeps is keyed by its path,
+    /// and holds those files at their VFS paths. Files are only listed under the source they're
+    /// actually loaded from, not ones they shadow.
+    pub fn tree_by_source(&self, file_filter: impl Fn(&VfsFile) -> bool) -> DisplayTree {
+        // Subdirectories are keyed below their source, so flat outputs like JSON Lines and text
+        // trees still show it. The root already names the source, so files don't repeat it.
+        fn place_under(node: &mut DirectoryNode, source: &Path) {
+            node.show_sources = false;
+            node.subdirs = std::mem::take(&mut node.subdirs)
+                .into_iter()
+                .map(|(path, mut subdir)| {
+                    place_under(&mut subdir, source);
+                    (source.join(path), subdir)
+                })
+                .collect();
+        }
+
+        let mut by_source: BTreeMap<PathBuf, Vec<VFSTuple<'_>>> = BTreeMap::new();
+
+        for (key, file) in &self.file_map {
+            by_source
+                .entry(file.source())
+                .or_default()
+                .push((key.as_path(), file));
+        }
+
+        let display = PathDisplay::Both;
+
+        by_source
+            .into_iter()
+            .filter_map(|(source, entries)| {
+                let mut root = Self::tree_from(entries.into_iter(), display)
+                    .remove(&display.root())
+                    .expect("Root path should be guaranteed to always exist!");
+
+                root.filter(&file_filter);
+                place_under(&mut root, &source);
+
+                (!root.files.is_empty() || !root.subdirs.is_empty()).then_some((source, root))
+            })
+            .collect()
+    }
+
+    /// Return whether any relative path in the vfs corresponds to the absolute path given
     /// Note that the path is normalized by this function, so it's not necessary to do so
     /// beforehand
     pub fn has_normalized_file(&self, target: &Path) -> bool {
@@ -299,266 +1600,2711 @@ impl VFS {
     /// Returns the formatted file tree for a filtered subset
     pub fn display_filtered<'a>(
         &self,
-        relative: bool,
+        display: impl Into<PathDisplay>,
         file_filter: impl Fn(&VfsFile) -> bool,
     ) -> String {
-        let tree = self.tree_filtered(relative, file_filter);
+        let tree = self.tree_filtered(display, file_filter);
+        Self::display_tree(&tree, &TreeStyle::default())
+    }
+
+    /// Formats the result of `tree` or `tree_filtered` as text, drawn with the given glyphs
+    pub fn display_tree(tree: &DisplayTree, style: &TreeStyle) -> String {
         let mut output = String::new();
 
-        if let Err(error) = write_tree_io(&tree, &mut output) {
-            panic!("Failed to format DisplayTree: {}", error)
+        if let Err(error) = write_tree_io(tree, &mut output, style) {
+            panic!("Failed to format DisplayTree: {}", error)
+        };
+
+        output
+    }
+
+    /// Serializes the result of `tree` or `display_filtered` functions to JSON, YAML, TOML, or
+    /// JSON Lines, laid out as `SerializeLayout::Standard`
+    ///
+    /// JSON Lines writes one `{"path": ...}` object per file, with its `source` in
+    /// `PathDisplay::Both` trees, and `{"path": "dir/...", "truncated_files": N}` for directories
+    /// cut off by `DirectoryNode::truncate`. Since every line stands alone,
+    /// subtrees are written in parallel and concatenated. The nested formats stay serial.
+    ///
+    /// This collects the output of `serialize_to_writer` into a `String`.
+    #[cfg(feature = "serialize")]
+    pub fn serialize_from_tree(tree: &DisplayTree, write_type: SerializeType) -> Result<String> {
+        Self::serialize_from_tree_with(tree, write_type, SerializeLayout::Standard)
+    }
+
+    /// Like `serialize_from_tree`, with JSON and TOML laid out as `layout` asks
+    #[cfg(feature = "serialize")]
+    pub fn serialize_from_tree_with(
+        tree: &DisplayTree,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+    ) -> Result<String> {
+        let mut buffer = Vec::new();
+        Self::serialize_to_writer(tree, write_type, layout, &mut buffer)?;
+
+        into_utf8(buffer)
+    }
+
+    /// Like `serialize_from_tree`, but writes straight into `writer` instead of building a
+    /// `String` first
+    ///
+    /// JSON and YAML stream through their serializers. TOML has no streaming serializer, so the
+    /// document is still rendered in memory before being written. JSON Lines renders each subtree's
+    /// lines in parallel, then writes them out in order.
+    ///
+    /// `writer` isn't buffered here, so wrap files and other unbuffered sinks in a `BufWriter`.
+    #[cfg(feature = "serialize")]
+    pub fn serialize_to_writer<W: std::io::Write>(
+        tree: &DisplayTree,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+        writer: W,
+    ) -> Result<()> {
+        match write_type {
+            SerializeType::JsonLines => write_tree_json_lines(tree, None, writer),
+            _ => serialize_into(tree, write_type, layout, writer),
+        }
+    }
+
+    /// Like `serialize_from_tree`, but writes each file as `{name, priority}`, where `priority`
+    /// comes from `priority_of` and is left out for files without a known source
+    #[cfg(feature = "serialize")]
+    pub fn serialize_with_priority(
+        &self,
+        tree: &DisplayTree,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+    ) -> Result<String> {
+        let mut buffer = Vec::new();
+        self.serialize_with_priority_to_writer(tree, write_type, layout, &mut buffer)?;
+
+        into_utf8(buffer)
+    }
+
+    /// Like `serialize_with_priority`, but writes straight into `writer`, the same way as
+    /// `serialize_to_writer`
+    #[cfg(feature = "serialize")]
+    pub fn serialize_with_priority_to_writer<W: std::io::Write>(
+        &self,
+        tree: &DisplayTree,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+        writer: W,
+    ) -> Result<()> {
+        let priorities: HashMap<&Path, usize> = self
+            .source_order()
+            .enumerate()
+            .map(|(index, source)| (source, index))
+            .collect();
+
+        let priority = |file: &VfsFile| priorities.get(file.source().as_path()).copied();
+
+        if write_type == SerializeType::JsonLines {
+            return write_tree_json_lines(tree, Some(&priority), writer);
+        }
+
+        let prioritized: BTreeMap<&PathBuf, SerializableNode<'_>> = tree
+            .iter()
+            .map(|(path, node)| {
+                (
+                    path,
+                    SerializableNode {
+                        node,
+                        priority: Some(&priority),
+                    },
+                )
+            })
+            .collect();
+
+        serialize_into(&prioritized, write_type, layout, writer)
+    }
+
+    /// Serializes the result of `shadowed_report` to JSON, YAML, TOML, or JSON Lines, where each
+    /// line holds a single path
+    #[cfg(feature = "serialize")]
+    pub fn serialize_shadowed(
+        report: &BTreeMap<PathBuf, ShadowedFile>,
+        write_type: SerializeType,
+    ) -> Result<String> {
+        Self::serialize_shadowed_with(report, write_type, SerializeLayout::Standard)
+    }
+
+    /// Like `serialize_shadowed`, with JSON and TOML laid out as `layout` asks
+    #[cfg(feature = "serialize")]
+    pub fn serialize_shadowed_with(
+        report: &BTreeMap<PathBuf, ShadowedFile>,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+    ) -> Result<String> {
+        serialize_as(report, write_type, layout)
+    }
+
+    /// Serializes the result of `case_conflicts` to JSON, YAML, TOML, or JSON Lines, where each
+    /// line holds a single path
+    #[cfg(feature = "serialize")]
+    pub fn serialize_case_conflicts(
+        report: &BTreeMap<PathBuf, Vec<PathBuf>>,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+    ) -> Result<String> {
+        serialize_as(report, write_type, layout)
+    }
+
+    /// Serializes the result of `archive_report` to JSON, YAML, TOML, or JSON Lines, keyed by each
+    /// archive's path
+    #[cfg(feature = "serialize")]
+    pub fn serialize_archive_report(
+        report: &[ArchiveStats],
+        write_type: SerializeType,
+        layout: SerializeLayout,
+    ) -> Result<String> {
+        let keyed: BTreeMap<&Path, &ArchiveStats> = report
+            .iter()
+            .map(|stats| (stats.path.as_path(), stats))
+            .collect();
+
+        serialize_as(&keyed, write_type, layout)
+    }
+
+    /// Writes several named trees into `writer` as a single document, keyed by name, eg the
+    /// results of a batch of searches keyed by their query. JSON Lines writes a line per section.
+    #[cfg(feature = "serialize")]
+    pub fn serialize_sections_to_writer<W: std::io::Write>(
+        sections: &BTreeMap<String, DisplayTree>,
+        write_type: SerializeType,
+        layout: SerializeLayout,
+        writer: W,
+    ) -> Result<()> {
+        serialize_into(sections, write_type, layout, writer)
+    }
+
+    /// Loads a tree written by `serialize_from_tree` back into a `DisplayTree`
+    ///
+    /// Files in the result are path-only: they're named after their position in the tree and
+    /// aren't backed by a data directory or archive. JSON Lines output can't be read back.
+    #[cfg(feature = "serialize")]
+    pub fn deserialize_tree(data: &str, read_type: SerializeType) -> Result<DisplayTree> {
+        fn to_io_error<E: std::fmt::Display>(err: E) -> Error {
+            Error::new(ErrorKind::InvalidData, err.to_string())
+        }
+
+        let mut tree: DisplayTree = match read_type {
+            SerializeType::Json => serde_json::from_str(data).map_err(to_io_error)?,
+            SerializeType::Yaml => serde_yaml::from_str(data).map_err(to_io_error)?,
+            SerializeType::Toml => toml::from_str(data).map_err(to_io_error)?,
+            SerializeType::JsonLines => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "JSON Lines output doesn't keep the tree's structure",
+                ));
+            }
+        };
+
+        for (root, node) in tree.iter_mut() {
+            // `tree` only includes the root in subdirectory keys when it's a real filesystem
+            // root, as opposed to the `Data Files` placeholder used for relative trees
+            let parent = if root.has_root() {
+                root.as_path()
+            } else {
+                Path::new("")
+            };
+
+            node.rebase(parent);
+        }
+
+        Ok(tree)
+    }
+}
+
+#[cfg(feature = "serialize")]
+fn serialize_as<T: serde::Serialize>(
+    value: &T,
+    write_type: SerializeType,
+    layout: SerializeLayout,
+) -> Result<String> {
+    let mut buffer = Vec::new();
+    serialize_into(value, write_type, layout, &mut buffer)?;
+
+    into_utf8(buffer)
+}
+
+#[cfg(feature = "serialize")]
+fn serialize_into<T: serde::Serialize, W: std::io::Write>(
+    value: &T,
+    write_type: SerializeType,
+    layout: SerializeLayout,
+    mut writer: W,
+) -> Result<()> {
+    fn to_io_error<E: std::fmt::Display>(err: E) -> Error {
+        Error::new(ErrorKind::InvalidData, err.to_string())
+    }
+
+    match (write_type, layout) {
+        (SerializeType::JsonLines, _) => {
+            // Maps become a line per entry, anything else a single line
+            match serde_json::to_value(value)? {
+                serde_json::Value::Object(entries) => {
+                    for entry in entries {
+                        serde_json::to_writer(&mut writer, &serde_json::Map::from_iter([entry]))?;
+                        writer.write_all(b"\n")?;
+                    }
+                }
+                other => {
+                    serde_json::to_writer(&mut writer, &other)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+        }
+        (SerializeType::Json, SerializeLayout::Pretty) => {
+            serde_json::to_writer_pretty(writer, value)?
+        }
+        (SerializeType::Json, _) => serde_json::to_writer(writer, value)?,
+        (SerializeType::Yaml, _) => serde_yaml::to_writer(writer, value).map_err(to_io_error)?,
+        (SerializeType::Toml, SerializeLayout::Compact) => {
+            writer.write_all(toml::to_string(value).map_err(to_io_error)?.as_bytes())?
+        }
+        (SerializeType::Toml, _) => writer.write_all(
+            toml::to_string_pretty(value)
+                .map_err(to_io_error)?
+                .as_bytes(),
+        )?,
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "serialize")]
+fn into_utf8(buffer: Vec<u8>) -> Result<String> {
+    String::from_utf8(buffer).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+/// A single line of a tree written as JSON Lines
+#[cfg(feature = "serialize")]
+#[derive(serde::Serialize)]
+struct JsonLine {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated_files: Option<usize>,
+}
+
+/// Writes every file of a tree as JSON Lines, rendering each subtree in parallel. Lines keep
+/// the same order as the nested formats, and each path is the file's full position in the tree.
+#[cfg(feature = "serialize")]
+fn write_tree_json_lines<W: std::io::Write>(
+    tree: &DisplayTree,
+    priority: Option<PriorityFn<'_>>,
+    mut writer: W,
+) -> Result<()> {
+    fn node_lines(
+        dir: &Path,
+        node: &DirectoryNode,
+        priority: Option<PriorityFn<'_>>,
+    ) -> Result<Vec<u8>> {
+        let mut lines = Vec::new();
+        let mut push_line = |line: JsonLine| -> Result<()> {
+            serde_json::to_writer(&mut lines, &line)?;
+            lines.push(b'\n');
+            Ok(())
+        };
+
+        for file in &node.files {
+            let name = file.file_name().unwrap_or_default();
+
+            push_line(JsonLine {
+                path: dir.join(name).to_string_lossy().into_owned(),
+                priority: priority.and_then(|priority| priority(file)),
+                source: node.show_sources.then(|| file.canonical_path()),
+                truncated_files: None,
+            })?;
+        }
+
+        if node.truncated_files > 0 {
+            push_line(JsonLine {
+                path: dir.join("...").to_string_lossy().into_owned(),
+                priority: None,
+                source: None,
+                truncated_files: Some(node.truncated_files),
+            })?;
+        }
+
+        let subtrees = node
+            .subdirs
+            .par_iter()
+            .map(|(subdir, subnode)| node_lines(subdir, subnode, priority))
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+
+        lines.extend(subtrees.concat());
+        Ok(lines)
+    }
+
+    // Same as `deserialize_tree`, the `Data Files` placeholder isn't part of relative paths
+    let fragments = tree
+        .par_iter()
+        .map(|(root, node)| {
+            let dir = if root.has_root() {
+                root.as_path()
+            } else {
+                Path::new("")
+            };
+
+            node_lines(dir, node, priority)
+        })
+        .collect::<Result<Vec<Vec<u8>>>>()?;
+
+    for fragment in fragments {
+        writer.write_all(&fragment)?;
+    }
+
+    Ok(())
+}
+
+/// Fails with `ErrorKind::Interrupted` once `cancel` has been set
+fn check_cancelled(cancel: &AtomicBool) -> Result<()> {
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Error::new(ErrorKind::Interrupted, "cancelled"));
+    }
+
+    Ok(())
+}
+
+/// Turns backslashes into forward slashes without touching case, so archive entries and Windows
+/// style paths split into the same components as the rest
+fn unify_separators(path: &Path) -> PathBuf {
+    path.to_string_lossy().replace('\\', "/").into()
+}
+
+fn to_eof_err<E: std::fmt::Display>(error: E) -> std::io::Error {
+    Error::new(ErrorKind::UnexpectedEof, error.to_string())
+}
+
+/// A file's line in the text form of a tree: its name, followed by its source if the node
+/// shows them. Files without a name, eg malformed archive entries, are left out, and can be
+/// found with `DirectoryNode::nameless_files`.
+fn file_label(node: &DirectoryNode, file: &VfsFile) -> Option<String> {
+    let name = file.path().file_name()?.to_string_lossy();
+
+    Some(if node.show_sources {
+        format!("{name} -> {}", file.canonical_path())
+    } else {
+        name.into_owned()
+    })
+}
+
+fn write_files_io<W: Write>(
+    w: &mut W,
+    node: &DirectoryNode,
+    dir: &PathBuf,
+    style: &TreeStyle,
+) -> std::io::Result<()> {
+    if !node.files.is_empty() || node.truncated_files > 0 {
+        write!(w, "{}", style.dir_str(dir.to_string_lossy())).map_err(to_eof_err)?;
+
+        for label in node.files.iter().filter_map(|file| file_label(node, file)) {
+            write!(w, "{}", style.file_str(label)).map_err(to_eof_err)?;
+        }
+
+        if node.truncated_files > 0 {
+            let summary = format!("... ({} files)", node.truncated_files);
+            write!(w, "{}", style.file_str(summary)).map_err(to_eof_err)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn print_files_fmt(
+    f: &mut std::fmt::Formatter<'_>,
+    node: &DirectoryNode,
+    dir: &PathBuf,
+    style: &TreeStyle,
+) -> std::fmt::Result {
+    if !node.files.is_empty() || node.truncated_files > 0 {
+        write!(f, "{}", style.dir_str(dir.to_string_lossy()))?;
+
+        for label in node.files.iter().filter_map(|file| file_label(node, file)) {
+            write!(f, "{}", style.file_str(label))?;
+        }
+
+        if node.truncated_files > 0 {
+            let summary = format!("... ({} files)", node.truncated_files);
+            write!(f, "{}", style.file_str(summary))?;
+        }
+    };
+
+    Ok(())
+}
+
+fn write_node_io<W: Write>(
+    w: &mut W,
+    node: &DirectoryNode,
+    parent_dir: &PathBuf,
+    style: &TreeStyle,
+) -> std::io::Result<()> {
+    write_files_io(w, &node, parent_dir, style)?;
+
+    for (subdir_name, subdir_node) in &node.subdirs {
+        write_node_io(w, subdir_node, &subdir_name, style)?;
+    }
+
+    Ok(())
+}
+
+fn print_node_fmt(
+    f: &mut std::fmt::Formatter<'_>,
+    node: &DirectoryNode,
+    parent_dir: &PathBuf,
+    style: &TreeStyle,
+) -> std::fmt::Result {
+    print_files_fmt(f, &node, parent_dir, style)?;
+
+    for (subdir_name, subdir_node) in &node.subdirs {
+        print_node_fmt(f, subdir_node, &subdir_name, style)?;
+    }
+
+    Ok(())
+}
+
+fn write_tree_io<W: Write>(
+    tree: &DisplayTree,
+    f: &mut W,
+    style: &TreeStyle,
+) -> std::io::Result<()> {
+    for (root_subdir, files) in tree {
+        write_files_io(f, files, root_subdir, style)?;
+
+        for (subdir_name, sub_node) in &files.subdirs {
+            write_node_io(f, &sub_node, &subdir_name, style)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_tree_fmt(
+    tree: &DisplayTree,
+    f: &mut std::fmt::Formatter<'_>,
+    style: &TreeStyle,
+) -> std::fmt::Result {
+    for (root_subdir, files) in tree {
+        print_files_fmt(f, files, root_subdir, style)?;
+
+        for (subdir_name, sub_node) in &files.subdirs {
+            print_node_fmt(f, &sub_node, &subdir_name, style)?;
+        }
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for VFS {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        print_tree_fmt(&self.tree(true), f, &TreeStyle::default())
+    }
+}
+
+/// Serializes the same structure as `serialize_from_tree` does for `tree(PathDisplay::Relative)`,
+/// so a VFS can be handed straight to any serde serializer
+#[cfg(feature = "serialize")]
+impl serde::Serialize for VFS {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde::Serialize::serialize(&self.tree(PathDisplay::Relative), serializer)
+    }
+}
+
+impl Index<&str> for VFS {
+    type Output = VfsFile;
+
+    fn index(&self, index: &str) -> &Self::Output {
+        let normalized_path = normalize_path(index);
+
+        // If the path exists in the file_map, return the file, otherwise return a default value
+        self.file_map.get(&normalized_path).unwrap_or_else(|| {
+            static DEFAULT_FILE: std::sync::OnceLock<VfsFile> = std::sync::OnceLock::new();
+            DEFAULT_FILE.get_or_init(|| VfsFile::default())
+        })
+    }
+}
+
+impl FromIterator<(PathBuf, VfsFile)> for VFS {
+    /// Builds a VFS from already-resolved entries without touching the filesystem.
+    ///
+    /// Keys are normalized on insert, and later entries win over earlier ones at the same path,
+    /// the same as later data directories do.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use vfstool_lib::{VFS, VfsFile};
+    ///
+    /// let vfs: VFS = [
+    ///     (PathBuf::from("Meshes\\A.nif"), VfsFile::from("/base/Meshes/A.nif")),
+    ///     (PathBuf::from("meshes/a.nif"), VfsFile::from("/patch/meshes/a.nif")),
+    /// ]
+    /// .into_iter()
+    /// .collect();
+    ///
+    /// assert_eq!(vfs.get_file("MESHES/A.NIF").unwrap().path().to_str(), Some("/patch/meshes/a.nif"));
+    /// assert_eq!(vfs.shadowed().count(), 1);
+    /// ```
+    fn from_iter<I: IntoIterator<Item = (PathBuf, VfsFile)>>(iter: I) -> Self {
+        let mut vfs = VFS::new();
+
+        for (path, file) in iter {
+            vfs.insert(path, file);
+        }
+
+        vfs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "bsa")]
+    use ba2::CompressableFrom;
+    #[cfg(feature = "bsa")]
+    use ba2::tes3::{Archive, ArchiveKey, File};
+    #[cfg(feature = "bsa")]
+    use ba2::tes4::{
+        Archive as Tes4Archive, ArchiveFlags as Tes4Flags, ArchiveKey as Tes4ArchiveKey,
+        ArchiveOptions as Tes4ArchiveOptions, Directory as Tes4Directory,
+        DirectoryKey as Tes4DirKey, File as Tes4File,
+        FileCompressionOptions as Tes4CompressionOptions, Version as Tes4Version,
+    };
+    use std::fs;
+    #[cfg(feature = "bsa")]
+    use std::io::{Read, Seek};
+    use std::path::PathBuf;
+
+    const TEST_DATA: &[&str] = &[
+        "file1.txt",
+        "file2.txt",
+        "file3.txt",
+        "file4.txt",
+        "file5.txt",
+        "file6.txt",
+    ];
+
+    const TEST_STRING: &str = "Act IV, Scene III, continued
+
+Lifts-Her-Tail
+Certainly not, kind sir! I am here but to clean your chambers.
+
+Crantius Colto
+Is that all you have come here for, little one? My chambers?
+
+Lifts-Her-Tail
+I have no idea what it is you imply, master. I am but a poor Argonian maid.
+
+Crantius Colto
+So you are, my dumpling. And a good one at that. Such strong legs and shapely tail.
+
+Lifts-Her-Tail
+You embarrass me, sir!
+
+Crantius Colto
+Fear not. You are safe here with me.
+
+Lifts-Her-Tail
+I must finish my cleaning, sir. The mistress will have my head if I do not!
+
+Crantius Colto
+Cleaning, eh? I have something for you. Here, polish my spear.
+
+Lifts-Her-Tail
+But it is huge! It could take me all night!
+
+Crantius Colto
+Plenty of time, my sweet. Plenty of time.
+
+END OF ACT IV, SCENE III";
+
+    fn create_files(dir: &PathBuf, files: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for file in files {
+            let file_path = dir.join(file);
+            fs::write(file_path, TEST_STRING).unwrap();
+        }
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_vfs_from_directories() {
+        let temp_path = std::env::current_dir().unwrap();
+        let archive_dir = temp_path.join("archives");
+
+        fs::create_dir_all(&archive_dir).unwrap();
+
+        // Create directories and files
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+
+        // Create BSA archives
+        let bsa1 = create_bsa_archive(&archive_dir, "archive1.bsa", &TEST_DATA[0..6]);
+        let bsa2 = create_bsa_archive(&archive_dir, "archive2.bsa", &TEST_DATA[0..5]);
+        let bsa3 = create_bsa_archive(&archive_dir, "archive3.bsa", &TEST_DATA[0..4]);
+
+        // Construct VFS
+        let search_dirs = vec![
+            archive_dir.clone(),
+            dir1.clone(),
+            dir2.clone(),
+            dir3.clone(),
+        ];
+        let archive_list = vec!["archive1.bsa", "archive2.bsa", "archive3.bsa"];
+
+        let vfs = VFS::from_directories(search_dirs.clone(), Some(archive_list));
+
+        // Verify file locations
+        verify_file_locations(&vfs, &bsa1, &bsa2, &bsa3, &dir1, &dir2, &dir3);
+
+        // Clean up test files and directories
+        clean_up_test_files(&search_dirs);
+    }
+
+    /// Builds the same layout as `test_vfs_from_directories` under `temp_path`, returning the VFS,
+    /// its archives and its search directories in load order
+    #[cfg(feature = "bsa")]
+    fn create_layered_vfs(temp_path: &Path) -> (VFS, [PathBuf; 3], [PathBuf; 4]) {
+        let archive_dir = temp_path.join("archives");
+        fs::create_dir_all(&archive_dir).unwrap();
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(temp_path);
+
+        let bsas = [
+            create_bsa_archive(&archive_dir, "archive1.bsa", &TEST_DATA[0..6]),
+            create_bsa_archive(&archive_dir, "archive2.bsa", &TEST_DATA[0..5]),
+            create_bsa_archive(&archive_dir, "archive3.bsa", &TEST_DATA[0..4]),
+        ];
+        let search_dirs = [archive_dir, dir1, dir2, dir3];
+        let vfs = VFS::from_directories(
+            &search_dirs,
+            Some(vec!["archive1.bsa", "archive2.bsa", "archive3.bsa"]),
+        );
+
+        (vfs, bsas, search_dirs)
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_sources() {
+        let temp_path = std::env::current_dir().unwrap().join("sources");
+        let (vfs, [bsa1, bsa2, bsa3], [archive_dir, dir1, dir2, dir3]) =
+            create_layered_vfs(&temp_path);
+
+        let sources = vfs.sources();
+        assert_eq!(
+            sources.dirs,
+            vec![(archive_dir, 3), (dir1, 3), (dir2, 2), (dir3, 1)]
+        );
+        assert_eq!(
+            sources.archives,
+            vec![
+                (bsa1, ArchiveFormat::Tes3, 6),
+                (bsa2, ArchiveFormat::Tes3, 5),
+                (bsa3, ArchiveFormat::Tes3, 4),
+            ]
+        );
+
+        let mut bsa3_entries: Vec<PathBuf> = vfs.archives()[2].entries().collect();
+        bsa3_entries.sort();
+        assert_eq!(
+            bsa3_entries,
+            TEST_DATA[0..4]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>(),
+            "Shadowed entries should still be listed by their archive"
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_priority_of() {
+        let temp_path = std::env::current_dir().unwrap().join("priority_of");
+        let (vfs, ..) = create_layered_vfs(&temp_path);
+
+        let priority = |name: &str| vfs.priority_of(vfs.get_file(name).unwrap());
+        assert_eq!(priority("file1.txt"), Some(6));
+        assert_eq!(priority("file3.txt"), Some(4));
+        assert_eq!(priority("file4.txt"), Some(2));
+        assert_eq!(
+            vfs.priority_of(&VfsFile::from("/elsewhere/file1.txt")),
+            None
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(all(feature = "bsa", feature = "serialize"))]
+    #[test]
+    fn test_serialize_with_priority() {
+        let temp_path = std::env::current_dir()
+            .unwrap()
+            .join("serialize_with_priority");
+        let (vfs, ..) = create_layered_vfs(&temp_path);
+
+        let tree = vfs.tree(true);
+        for format in [
+            SerializeType::Json,
+            SerializeType::Yaml,
+            SerializeType::Toml,
+        ] {
+            let serialized = vfs
+                .serialize_with_priority(&tree, format, SerializeLayout::Compact)
+                .unwrap();
+            assert!(serialized.contains("priority"), "{serialized}");
+        }
+
+        let json = vfs
+            .serialize_with_priority(&tree, SerializeType::Json, SerializeLayout::Compact)
+            .unwrap();
+        assert!(
+            json.contains(r#"{"name":"file1.txt","priority":6}"#),
+            "{json}"
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_shadowed_report() {
+        let temp_path = std::env::current_dir().unwrap().join("shadowed_report");
+        let (vfs, [bsa1, bsa2, bsa3], [_, dir1, dir2, dir3]) = create_layered_vfs(&temp_path);
+
+        let report = vfs.shadowed_report();
+        let sources_of = |name: &str| {
+            let entry = &report[&PathBuf::from(name)];
+            let mut sources = entry.shadowed.clone();
+            sources.push(entry.winner.clone());
+            sources
+        };
+
+        assert_eq!(
+            sources_of("file1.txt"),
+            vec![
+                bsa1.clone(),
+                bsa2.clone(),
+                bsa3.clone(),
+                dir1.join("file1.txt"),
+                dir2.join("file1.txt"),
+                dir3.join("file1.txt"),
+            ]
+        );
+        assert_eq!(
+            sources_of("file4.txt"),
+            vec![bsa1.clone(), bsa2.clone(), bsa3]
+        );
+        assert_eq!(sources_of("file5.txt"), vec![bsa1, bsa2]);
+        assert!(!report.contains_key(&PathBuf::from("file6.txt")));
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[test]
+    fn test_iter_sorted_is_the_same_every_run() {
+        let temp_path = std::env::current_dir().unwrap().join("iter_sorted");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let dirs = [dir1, dir2, dir3];
+
+        // Each VFS hashes its paths differently, so only a sorted walk can match between them
+        let walk = || -> Vec<PathBuf> {
+            VFS::from_directories(&dirs, None)
+                .iter_sorted()
+                .map(|(path, _file)| path.to_path_buf())
+                .collect()
+        };
+        let first = walk();
+
+        assert!(first.is_sorted());
+        assert_eq!(first.len(), 3);
+        assert_eq!(walk(), first);
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    fn create_test_dirs_and_files(temp_path: &Path) -> (PathBuf, PathBuf, PathBuf) {
+        let dir1 = temp_path.join("dir1");
+        let dir2 = temp_path.join("dir2");
+        let dir3 = temp_path.join("dir3");
+
+        create_files(&dir1, &TEST_DATA[0..3]); // file1.txt, file2.txt, file3.txt
+        create_files(&dir2, &TEST_DATA[0..2]); // file1.txt, file2.txt
+        create_files(&dir3, &TEST_DATA[0..1]); // file1.txt
+        create_files(&temp_path.to_path_buf(), &TEST_DATA[..]);
+
+        (dir1, dir2, dir3)
+    }
+
+    #[cfg(feature = "bsa")]
+    fn create_bsa_archive(archive_dir: &Path, archive_name: &str, data: &[&str]) -> PathBuf {
+        let archive_path = archive_dir.join(archive_name);
+        let archive: Archive = data
+            .iter()
+            .map(|s| {
+                let key: ArchiveKey = s.to_string().into();
+                let file: File = File::from(s.as_bytes());
+                (key, file)
+            })
+            .collect();
+        let mut dst = fs::File::create(&archive_path).unwrap();
+        archive.write(&mut dst).unwrap();
+        archive_path
+    }
+
+    /// Writes a single compressed entry at `textures/compressed.txt` using the codec implied by
+    /// `version`
+    #[cfg(feature = "bsa")]
+    fn create_tes4_archive(archive_dir: &Path, archive_name: &str, version: Tes4Version) {
+        let options = Tes4CompressionOptions::builder().version(version).build();
+        let file: Tes4File = Tes4File::from_decompressed(TEST_STRING.as_bytes())
+            .compress(&options)
+            .unwrap();
+
+        let directory: Tes4Directory = [(Tes4DirKey::from(b"compressed.txt"), file)]
+            .into_iter()
+            .collect();
+        let archive: Tes4Archive = [(Tes4ArchiveKey::from(b"textures"), directory)]
+            .into_iter()
+            .collect();
+
+        let archive_options = Tes4ArchiveOptions::builder()
+            .version(version)
+            .flags(Tes4Flags::DIRECTORY_STRINGS | Tes4Flags::FILE_STRINGS | Tes4Flags::COMPRESSED)
+            .build();
+
+        let mut dst = fs::File::create(archive_dir.join(archive_name)).unwrap();
+        archive.write(&mut dst, &archive_options).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_archives_referenced_by_path() {
+        let temp_path = std::env::current_dir().unwrap().join("archives_by_path");
+        let data_dir = temp_path.join("data");
+        let outside = temp_path.join("outside");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+
+        let absolute = create_bsa_archive(&outside, "absolute.bsa", &TEST_DATA[0..1]);
+        create_bsa_archive(&outside, "relative.bsa", &TEST_DATA[1..2]);
+
+        let absolute = absolute.to_string_lossy().to_string();
+        let vfs = VFS::from_directories(
+            [&data_dir],
+            Some(vec![
+                absolute.as_str(),
+                "../outside/relative.bsa",
+                "missing.bsa",
+            ]),
+        );
+
+        assert_eq!(vfs.archives().len(), 2);
+        assert!(vfs.get_file(TEST_DATA[0]).unwrap().is_archive());
+        assert_eq!(
+            vfs.get_file(TEST_DATA[1])
+                .unwrap()
+                .parent_archive_name()
+                .as_deref(),
+            Some("relative.bsa")
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_repeated_archives_open_once() {
+        let temp_path = std::env::current_dir().unwrap().join("repeated_archives");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+
+        let first = create_bsa_archive(&data_dir, "first.bsa", &TEST_DATA[0..1]);
+        let second = create_bsa_archive(&data_dir, "second.bsa", &TEST_DATA[0..1]);
+
+        let vfs = VFS::from_directories(
+            [&data_dir],
+            Some(vec!["first.bsa", "second.bsa", "First.bsa"]),
+        );
+
+        let opened: Vec<&Path> = vfs
+            .archives()
+            .iter()
+            .map(|archive| archive.path())
+            .collect();
+        assert_eq!(opened, [second.as_path(), first.as_path()]);
+        assert_eq!(vfs.repeated_archives(), std::slice::from_ref(&first));
+        assert_eq!(
+            vfs.get_file(TEST_DATA[0])
+                .unwrap()
+                .parent_archive_name()
+                .as_deref(),
+            Some("first.bsa")
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_relative_paths_match_keys() {
+        let temp_path = std::env::current_dir().unwrap().join("relative_paths");
+        let data_dir = temp_path.join("Data Files");
+        fs::create_dir_all(data_dir.join("Meshes")).unwrap();
+        fs::write(data_dir.join("Meshes/XBase_Anim.nif"), "").unwrap();
+        create_bsa_archive(&data_dir, "a.bsa", &["Textures\\Tx_Bone.dds"]);
+
+        let vfs = VFS::from_directories([&data_dir], Some(vec!["a.bsa"]));
+
+        assert_eq!(vfs.iter().count(), 3);
+        for (key, file) in vfs.iter() {
+            assert_eq!(&file.relative_path(), key);
+        }
+        assert_ne!(
+            vfs.get_file("meshes/xbase_anim.nif").unwrap().path(),
+            Path::new("meshes/xbase_anim.nif")
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_from_archive() {
+        let temp_path = std::env::current_dir().unwrap().join("single_archive");
+        fs::create_dir_all(&temp_path).unwrap();
+        let archive = create_bsa_archive(&temp_path, "Single.bsa", &TEST_DATA[..2]);
+
+        let vfs = VFS::from_archive(&archive).unwrap();
+        assert_eq!(vfs.iter().count(), 2);
+        assert_eq!(vfs.archives().len(), 1);
+        assert_eq!(
+            vfs.get_file(TEST_DATA[0])
+                .unwrap()
+                .parent_archive_name()
+                .as_deref(),
+            Some("Single.bsa")
+        );
+
+        let not_archive = temp_path.join("plain.txt");
+        fs::write(&not_archive, "not an archive").unwrap();
+        assert_eq!(
+            VFS::from_archive(&not_archive).err().map(|err| err.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_lazy_archives_match_eager_build() {
+        let temp_path = std::env::current_dir().unwrap().join("lazy_archives");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(data_dir.join("meshes")).unwrap();
+        fs::write(data_dir.join(TEST_DATA[0]), "loose").unwrap();
+        create_bsa_archive(&data_dir, "first.bsa", &TEST_DATA[..2]);
+        create_bsa_archive(&data_dir, "second.bsa", &TEST_DATA[1..3]);
+        let archive_list = vec!["first.bsa", "missing.bsa", "second.bsa"];
+
+        let eager = VFS::from_directories([&data_dir], Some(archive_list.clone()));
+        let (mut lazy, loader) =
+            VFS::from_directories_lazy([&data_dir], archive_list, &ScanOptions::default());
+
+        assert_eq!(
+            loader.archives(),
+            [data_dir.join("first.bsa"), data_dir.join("second.bsa")]
+        );
+        assert!(lazy.archives().is_empty());
+        assert!(lazy.get_file(TEST_DATA[1]).is_none());
+
+        let loaded = std::thread::spawn(move || loader.load()).join().unwrap();
+        lazy.merge_archives(loaded);
+
+        let sources = |vfs: &VFS| -> BTreeMap<PathBuf, PathBuf> {
+            vfs.iter()
+                .map(|(path, file)| (path.clone(), source_of(file)))
+                .collect()
+        };
+        assert_eq!(sources(&lazy), sources(&eager));
+        assert_eq!(lazy.shadowed_report(), eager.shadowed_report());
+        assert_eq!(lazy.archive_report(), eager.archive_report());
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_archives_over_loose() {
+        let temp_path = std::env::current_dir().unwrap().join("archives_over_loose");
+        let data_dir = temp_path.join("data");
+        let archive_dir = temp_path.join("archives");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::create_dir_all(&archive_dir).unwrap();
+        fs::write(data_dir.join(TEST_DATA[0]), "loose").unwrap();
+        let archive = create_bsa_archive(&archive_dir, "late.bsa", &TEST_DATA[..2]);
+
+        // Built normally, the loose file wins and nothing is flagged
+        let built = VFS::from_directories([&data_dir, &archive_dir], Some(vec!["late.bsa"]));
+        assert!(built.archives_over_loose().is_empty());
+
+        let loose = VFS::from_directories([&data_dir], None);
+        let layered = loose.overlay(&VFS::from_archive(&archive).unwrap());
+        let flagged = layered.archives_over_loose();
+
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(
+            flagged[Path::new(TEST_DATA[0])],
+            ShadowedFile {
+                winner: archive.clone(),
+                shadowed: vec![data_dir.join(TEST_DATA[0])],
+            }
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_hide_archive_files() {
+        let temp_path = std::env::current_dir().unwrap().join("hide_archive_files");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        create_bsa_archive(&data_dir, "Loaded.bsa", &TEST_DATA[..1]);
+        create_bsa_archive(&data_dir, "unlisted.bsa", &TEST_DATA[1..2]);
+
+        let mut vfs = VFS::from_directories([&data_dir], Some(vec!["Loaded.bsa"]));
+        assert!(vfs.get_file("loaded.bsa").is_some());
+
+        vfs.hide_archive_files();
+
+        assert!(vfs.get_file("loaded.bsa").is_none());
+        assert!(vfs.get_file(TEST_DATA[0]).unwrap().is_archive());
+        assert!(vfs.get_file("unlisted.bsa").is_some());
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_invalidation_archive_shadows_nothing() {
+        let temp_path = std::env::current_dir()
+            .unwrap()
+            .join("invalidation_archive");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+
+        let loose = data_dir.join(TEST_DATA[0]);
+        fs::write(&loose, "real").unwrap();
+        create_bsa_archive(&data_dir, "real.bsa", &TEST_DATA[1..2]);
+        create_bsa_archive(
+            &data_dir,
+            "ArchiveInvalidationInvalidated!.bsa",
+            &TEST_DATA[0..2],
+        );
+        let archive_list = vec!["real.bsa", "ArchiveInvalidationInvalidated!.bsa"];
+
+        let vfs = VFS::from_directories([&data_dir], Some(archive_list.clone()));
+        assert_eq!(vfs.archives().len(), 2);
+        assert!(vfs.archives()[1].is_invalidation());
+        assert_eq!(vfs.get_file(TEST_DATA[0]).unwrap().path(), loose);
+        assert_eq!(
+            vfs.get_file(TEST_DATA[1])
+                .unwrap()
+                .parent_archive_name()
+                .as_deref(),
+            Some("real.bsa")
+        );
+        assert_eq!(vfs.shadowed().count(), 0);
+
+        let options = ScanOptions {
+            ignore_invalidation: false,
+            ..Default::default()
+        };
+        let kept = VFS::from_directories_with([&data_dir], Some(archive_list), &options);
+        assert_eq!(kept.get_file(TEST_DATA[0]).unwrap().path(), loose);
+        assert!(
+            kept.get_file(TEST_DATA[1])
+                .unwrap()
+                .parent_archive_name()
+                .is_some_and(crate::is_invalidation_archive)
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_archive_report() {
+        let temp_path = std::env::current_dir().unwrap().join("archive_report");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+
+        fs::write(data_dir.join(TEST_DATA[0]), "loose").unwrap();
+        let lower = create_bsa_archive(&data_dir, "lower.bsa", &TEST_DATA[0..3]);
+        let higher = create_bsa_archive(&data_dir, "higher.bsa", &TEST_DATA[1..2]);
+
+        let vfs = VFS::from_directories([&data_dir], Some(vec!["lower.bsa", "higher.bsa"]));
+        let report = vfs.archive_report();
+
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].path, lower);
+        assert_eq!(
+            (report[0].priority, report[0].entries, report[0].winning),
+            (0, 3, 1)
+        );
+        assert_eq!(report[1].path, higher);
+        assert_eq!(
+            (report[1].priority, report[1].entries, report[1].winning),
+            (1, 1, 1)
+        );
+        assert_eq!(report[1].format, ArchiveFormat::Tes3);
+        assert_eq!(report[1].size, Some(fs::metadata(&higher).unwrap().len()));
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(all(feature = "bsa", feature = "serialize"))]
+    #[test]
+    fn test_path_display_modes() {
+        let temp_path = std::env::current_dir().unwrap().join("path_display");
+        let data_dir = temp_path.join("data");
+        fs::create_dir_all(data_dir.join("meshes")).unwrap();
+        fs::write(data_dir.join("meshes/a.nif"), "").unwrap();
+        create_bsa_archive(&data_dir, "a.bsa", &TEST_DATA[0..1]);
+
+        let vfs = VFS::from_directories([&data_dir], Some(vec!["a.bsa"]));
+
+        let relative = vfs.tree(PathDisplay::Relative);
+        let root = &relative[Path::new("Data Files")];
+        assert_eq!(root.get_dir(Path::new("a.bsa")).unwrap().files.len(), 1);
+        assert_eq!(root.get_dir(Path::new("meshes")).unwrap().files.len(), 1);
+        assert_eq!(vfs.tree(true).len(), relative.len());
+
+        // The absolute layout nests each directory below `/`, which is only how Unix spells the
+        // data directory's path
+        #[cfg(unix)]
+        {
+            let data_from_root: PathBuf = data_dir
+                .components()
+                .filter(|component| matches!(component, std::path::Component::Normal(_)))
+                .collect();
+
+            let absolute = vfs.tree(PathDisplay::Absolute);
+            let root = &absolute[Path::new("/")];
+            assert!(root.get_dir(&data_from_root.join("meshes")).is_some());
+            assert_eq!(
+                root.get_dir(&data_from_root.join("a.bsa"))
+                    .unwrap()
+                    .find(TEST_DATA[0]),
+                vec![data_dir.join("a.bsa").join(TEST_DATA[0])]
+            );
+        }
+
+        let both = vfs.tree(PathDisplay::Both);
+        let root = &both[Path::new("Data Files")];
+        assert!(root.get_dir(Path::new("a.bsa")).is_none());
+        assert_eq!(root.find(TEST_DATA[0]).len(), 1);
+
+        let text = VFS::display_tree(&both, &TreeStyle::default());
+        let loose_source = vfs.get_file("meshes/a.nif").unwrap().canonical_path();
+        assert!(text.contains(&format!("a.nif -> {loose_source}")));
+        let archived_source = vfs.get_file(TEST_DATA[0]).unwrap().canonical_path();
+        assert!(archived_source.ends_with(&format!("a.bsa::{}", TEST_DATA[0])));
+        assert!(text.contains(&format!("{} -> {archived_source}", TEST_DATA[0])));
+
+        let json = VFS::serialize_from_tree(&both, SerializeType::Json).unwrap();
+        assert!(json.contains(r#""source":"#));
+        let loaded = VFS::deserialize_tree(&json, SerializeType::Json).unwrap();
+        assert_eq!(
+            loaded[Path::new("Data Files")].file_count(),
+            root.file_count()
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_tes4_archive_versions_decompress() {
+        let archive_dir = std::env::current_dir().unwrap().join("tes4_archives");
+        fs::create_dir_all(&archive_dir).unwrap();
+
+        for (archive_name, version) in [
+            ("oblivion.bsa", Tes4Version::TES4),
+            ("skyrim.bsa", Tes4Version::TES5),
+            ("skyrim_se.bsa", Tes4Version::SSE),
+        ] {
+            create_tes4_archive(&archive_dir, archive_name, version);
+
+            let vfs = VFS::from_directories([&archive_dir], Some(vec![archive_name]));
+            let file = vfs
+                .get_file("textures/compressed.txt")
+                .expect("Compressed entry should be present in the VFS");
+
+            assert_eq!(
+                file.parent_archive_handle().unwrap().tes4_version(),
+                Some(version)
+            );
+
+            let mut reader = file.open().unwrap();
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).unwrap();
+            assert_eq!(
+                contents, TEST_STRING,
+                "{archive_name} decompressed incorrectly"
+            );
+
+            reader.seek(std::io::SeekFrom::Start(4)).unwrap();
+            contents.clear();
+            reader.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, TEST_STRING[4..]);
+            assert_eq!(file.size().unwrap(), TEST_STRING.len() as u64);
+
+            let mut owned = file.open_owned().unwrap();
+            let owned_contents = std::thread::spawn(move || {
+                let mut contents = String::new();
+                owned.read_to_string(&mut contents).unwrap();
+                contents
+            })
+            .join()
+            .unwrap();
+            assert_eq!(owned_contents, TEST_STRING);
+        }
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_tes4_backslash_directories_open_by_vfs_key() {
+        let archive_dir = std::env::current_dir().unwrap().join("tes4_backslashes");
+        fs::create_dir_all(&archive_dir).unwrap();
+
+        let directory: Tes4Directory = [(
+            Tes4DirKey::from(b"nested.txt"),
+            Tes4File::from_decompressed(TEST_STRING.as_bytes()),
+        )]
+        .into_iter()
+        .collect();
+        let archive: Tes4Archive = [(Tes4ArchiveKey::from(b"textures\\sub\\dir"), directory)]
+            .into_iter()
+            .collect();
+        let options = Tes4ArchiveOptions::builder()
+            .version(Tes4Version::TES4)
+            .flags(Tes4Flags::DIRECTORY_STRINGS | Tes4Flags::FILE_STRINGS)
+            .build();
+        let mut dst = fs::File::create(archive_dir.join("nested.bsa")).unwrap();
+        archive.write(&mut dst, &options).unwrap();
+        drop(dst);
+
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["nested.bsa"]));
+        let file = vfs.get_file("textures/sub/dir/nested.txt").unwrap();
+
+        // VFS paths use forward slashes, which ba2 turns into the backslashes the archive stores
+        let key = PathBuf::from("textures/sub/dir/nested.txt");
+        let (dir_key, _file_key) = crate::vfs_file::ArchiveReference::tes4_keys(&key).unwrap();
+        assert_eq!(dir_key.name(), "textures\\sub\\dir");
+
+        let mut contents = String::new();
+        file.open().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, TEST_STRING);
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_root_level_archive_entries() {
+        let archive_dir = std::env::current_dir().unwrap().join("root_level_entries");
+        fs::create_dir_all(&archive_dir).unwrap();
+
+        let directory: Tes4Directory = [(
+            Tes4DirKey::from(b"root.txt"),
+            Tes4File::from_decompressed(TEST_STRING.as_bytes()),
+        )]
+        .into_iter()
+        .collect();
+        let archive: Tes4Archive = [(Tes4ArchiveKey::from(b""), directory)]
+            .into_iter()
+            .collect();
+        let options = Tes4ArchiveOptions::builder()
+            .version(Tes4Version::TES4)
+            .flags(Tes4Flags::DIRECTORY_STRINGS | Tes4Flags::FILE_STRINGS)
+            .build();
+        let mut dst = fs::File::create(archive_dir.join("root.bsa")).unwrap();
+        archive.write(&mut dst, &options).unwrap();
+        drop(dst);
+
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["root.bsa"]));
+        let file = vfs.get_file("root.txt").unwrap();
+        assert!(file.is_archive());
+
+        let mut contents = String::new();
+        file.open().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, TEST_STRING);
+
+        let tree = vfs.tree(PathDisplay::Both);
+        let root = &tree[&PathBuf::from("Data Files")];
+        assert!(root.subdirs.is_empty());
+        assert!(
+            root.files
+                .iter()
+                .any(|file| file.file_name() == Some("root.txt".as_ref()))
+        );
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+
+        // A file without a name is left out of the text form instead of panicking
+        let mut nameless = VFS::new();
+        nameless.insert(PathBuf::from("broken"), VfsFile::from("/"));
+        nameless.insert_loose(PathBuf::from("meshes/a.nif"));
+        let text = VFS::display_tree(&nameless.tree(PathDisplay::Relative), &TreeStyle::ascii());
+        assert!(text.contains("a.nif"));
+        assert_eq!(text.matches("|   +-- ").count(), 1);
+
+        // It's handed back to the caller to report instead
+        let tree = nameless.tree(PathDisplay::Relative);
+        let skipped: Vec<&Path> = tree
+            .values()
+            .flat_map(DirectoryNode::nameless_files)
+            .map(VfsFile::path)
+            .collect();
+        assert_eq!(skipped, [Path::new("/")]);
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_cached_archive_reads() {
+        let archive_dir = std::env::current_dir().unwrap().join("cached_archives");
+        fs::create_dir_all(&archive_dir).unwrap();
+        create_tes4_archive(&archive_dir, "cached.bsa", Tes4Version::SSE);
+
+        let vfs =
+            VFS::from_directories([&archive_dir], Some(vec!["cached.bsa"])).with_cache(1 << 20);
+        let file = vfs.get_file("textures/compressed.txt").unwrap();
+        let cache = Arc::clone(file.parent_archive_handle().unwrap().cache().unwrap());
+
+        for _ in 0..2 {
+            let mut contents = String::new();
+            file.open().unwrap().read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, TEST_STRING);
+        }
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.total_bytes(), TEST_STRING.len());
+
+        // A hit is served straight from the cache, without decompressing the entry again
+        let archive_path = file.parent_archive_handle().unwrap().path().to_path_buf();
+        let cached: Arc<[u8]> = b"from the cache".as_slice().into();
+        cache.insert(&archive_path, file.path(), Arc::clone(&cached));
+
+        let mut contents = Vec::new();
+        file.open().unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, *cached);
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_open_cached() {
+        let archive_dir = std::env::current_dir().unwrap().join("open_cached");
+        fs::create_dir_all(&archive_dir).unwrap();
+        create_tes4_archive(&archive_dir, "cached.bsa", Tes4Version::TES5);
+
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["cached.bsa"]));
+
+        for path in ["textures/compressed.txt", "cached.bsa"] {
+            let file = vfs.get_file(path).unwrap();
+            let expected = fs::read(archive_dir.join(path))
+                .unwrap_or_else(|_| TEST_STRING.as_bytes().to_vec());
+
+            for _ in 0..2 {
+                let mut reader = file.open_cached().unwrap();
+                let mut contents = Vec::new();
+                reader.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, expected);
+
+                reader.seek(std::io::SeekFrom::Start(4)).unwrap();
+                contents.clear();
+                reader.read_to_end(&mut contents).unwrap();
+                assert_eq!(contents, expected[4..]);
+            }
+        }
+
+        // The contents were kept by the file itself, which only counts them when debugged,
+        // rather than in a cache the rest of the archive would start using
+        let file = vfs.get_file("textures/compressed.txt").unwrap();
+        assert!(file.parent_archive_handle().unwrap().cache().is_none());
+        let debugged = format!("{file:?}");
+        assert!(debugged.contains(&format!("cached_bytes: Some({})", TEST_STRING.len())));
+        assert!(!debugged.contains("Lifts-Her-Tail"));
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_open_into_reuses_buffer() {
+        use crate::archives::{PackCompression, PackFormat};
+
+        let archive_dir = std::env::current_dir().unwrap().join("open_into");
+        fs::create_dir_all(&archive_dir).unwrap();
+        create_tes4_archive(&archive_dir, "tes5.bsa", Tes4Version::TES5);
+        create_tes4_archive(&archive_dir, "sse.bsa", Tes4Version::SSE);
+        create_bsa_archive(&archive_dir, "tes3.bsa", &TEST_DATA[..1]);
+        let mut dst = fs::File::create(archive_dir.join("fo4.ba2")).unwrap();
+        archives::pack(
+            vec![(
+                PathBuf::from("meshes/packed.nif"),
+                TEST_STRING.as_bytes().to_vec(),
+                PackCompression::Best,
+            )],
+            PackFormat::Fo4,
+            &mut dst,
+        )
+        .unwrap();
+        drop(dst);
+
+        let mut buf = b"left over from an earlier file".to_vec();
+        for archive in ["tes5.bsa", "sse.bsa", "tes3.bsa", "fo4.ba2"] {
+            let vfs = VFS::from_directories([&archive_dir], Some(vec![archive]));
+
+            for (path, file) in vfs.iter() {
+                let mut expected = Vec::new();
+                file.open().unwrap().read_to_end(&mut expected).unwrap();
+
+                file.open_into(&mut buf).unwrap();
+                assert_eq!(buf, expected, "{} read differently", path.display());
+            }
+        }
+
+        // Reads through `open_into` fill an attached cache the same as `open` does
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["sse.bsa"])).with_cache(1 << 20);
+        let file = vfs.get_file("textures/compressed.txt").unwrap();
+        let cache = Arc::clone(file.parent_archive_handle().unwrap().cache().unwrap());
+
+        file.open_into(&mut buf).unwrap();
+        assert_eq!(buf, TEST_STRING.as_bytes());
+        assert_eq!(cache.len(), 1);
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_files_outlive_vfs() {
+        let archive_dir = std::env::current_dir().unwrap().join("files_outlive_vfs");
+        fs::create_dir_all(&archive_dir).unwrap();
+        create_tes4_archive(&archive_dir, "owned.bsa", Tes4Version::TES5);
+
+        let (archived, loose) = {
+            let vfs = VFS::from_directories([&archive_dir], Some(vec!["owned.bsa"]));
+            (
+                vfs.get_file("textures/compressed.txt").unwrap().clone(),
+                vfs.get_file("owned.bsa").unwrap().clone(),
+            )
+        };
+
+        let mut contents = String::new();
+        archived
+            .open()
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, TEST_STRING);
+        assert_eq!(archived.size().unwrap(), TEST_STRING.len() as u64);
+        assert_eq!(loose.path(), archive_dir.join("owned.bsa"));
+        assert!(loose.open_owned().is_ok());
+
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_tree_serialization_round_trip() {
+        let mut vfs = VFS::new();
+
+        for path in [
+            "/mods/base/meshes/xbase_anim.nif",
+            "/mods/base/textures/tx_bone_01.dds",
+            "/mods/patch/Textures/Terrain/tx_ash_01.dds",
+            "/mods/patch/readme.txt",
+        ] {
+            let relative = normalize_path(path.splitn(4, '/').last().unwrap());
+            vfs.file_map.insert(relative, VfsFile::from(path));
+        }
+
+        for relative in [false, true] {
+            let tree = vfs.tree(relative);
+
+            for format in [
+                SerializeType::Json,
+                SerializeType::Yaml,
+                SerializeType::Toml,
+            ] {
+                let serialized =
+                    VFS::serialize_from_tree_with(&tree, format, SerializeLayout::Pretty).unwrap();
+                let loaded = VFS::deserialize_tree(&serialized, format).unwrap();
+
+                assert_eq!(
+                    serialized,
+                    VFS::serialize_from_tree_with(&loaded, format, SerializeLayout::Pretty)
+                        .unwrap()
+                );
+
+                let compact =
+                    VFS::serialize_from_tree_with(&tree, format, SerializeLayout::Compact).unwrap();
+                assert!(compact.len() <= serialized.len());
+                assert_eq!(
+                    serialized,
+                    VFS::serialize_from_tree_with(
+                        &VFS::deserialize_tree(&compact, format).unwrap(),
+                        format,
+                        SerializeLayout::Pretty
+                    )
+                    .unwrap()
+                );
+
+                // The standard layout keeps JSON compact and TOML pretty, as it always has
+                let expected = match format {
+                    SerializeType::Json => &compact,
+                    _ => &serialized,
+                };
+                assert_eq!(&VFS::serialize_from_tree(&tree, format).unwrap(), expected);
+
+                let (mut original_display, mut loaded_display) = (String::new(), String::new());
+                write_tree_io(&tree, &mut original_display, &TreeStyle::default()).unwrap();
+                write_tree_io(&loaded, &mut loaded_display, &TreeStyle::default()).unwrap();
+
+                assert_eq!(original_display, loaded_display);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_only_matches_loose_files() {
+        let temp_path = std::env::current_dir().unwrap().join("scan_only");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let search_dirs = vec![dir1.clone(), dir2.clone(), dir3.clone()];
+
+        let scanned = VFS::scan_only(&search_dirs);
+        let built = VFS::from_directories(&search_dirs, None);
+
+        assert_eq!(scanned.sources().dirs, built.sources().dirs);
+        assert!(scanned.diff(&built).is_empty());
+        assert_eq!(
+            scanned.get_file("file1.txt").unwrap().path(),
+            dir3.join("file1.txt")
+        );
+
+        fs::remove_dir_all(&temp_path).unwrap();
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_json_lines_follow_tree_order() {
+        let vfs: VFS = (0..200)
+            .map(|index| {
+                let relative = format!("dir{}/sub{}/file{index}.dds", index % 7, index % 3);
+                (PathBuf::from(&relative), VfsFile::from(relative))
+            })
+            .collect();
+        let mut tree = vfs.tree(true);
+
+        let lines = VFS::serialize_from_tree(&tree, SerializeType::JsonLines).unwrap();
+        let mut expected = Vec::new();
+        vfs.walk(true, &mut |event| {
+            if let WalkEvent::File(file) = event {
+                expected.push(file.path().to_string_lossy().to_string());
+            }
+        });
+        let written: Vec<String> = lines
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                value["path"].as_str().unwrap().to_string()
+            })
+            .collect();
+
+        assert_eq!(written, expected);
+        assert!(VFS::deserialize_tree(&lines, SerializeType::JsonLines).is_err());
+
+        tree.values_mut().for_each(|node| node.truncate(1));
+        let truncated = VFS::serialize_from_tree(&tree, SerializeType::JsonLines).unwrap();
+        assert_eq!(truncated.lines().count(), 7);
+        assert!(truncated.contains(r#"{"path":"dir0/...","truncated_files":29}"#));
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_serialize_sections() {
+        let vfs: VFS = ["meshes/a.nif", "textures/a.dds"]
+            .into_iter()
+            .map(|path| (PathBuf::from(path), VfsFile::from(path)))
+            .collect();
+        let sections = BTreeMap::from([
+            (
+                "nif$".to_string(),
+                vfs.tree_filtered(true, |file| file.path().starts_with("meshes")),
+            ),
+            (
+                "^textures".to_string(),
+                vfs.tree_filtered(true, |file| file.path().starts_with("textures")),
+            ),
+        ]);
+
+        let mut written = Vec::new();
+        VFS::serialize_sections_to_writer(
+            &sections,
+            SerializeType::Json,
+            SerializeLayout::Compact,
+            &mut written,
+        )
+        .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&written).unwrap();
+        assert_eq!(value["nif$"]["Data Files"]["meshes"]["."][0], "a.nif");
+        assert_eq!(
+            value["^textures"]["Data Files"]["textures"]["."][0],
+            "a.dds"
+        );
+
+        let mut lines = Vec::new();
+        VFS::serialize_sections_to_writer(
+            &sections,
+            SerializeType::JsonLines,
+            SerializeLayout::Compact,
+            &mut lines,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(lines).unwrap().lines().count(), 2);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_serialize_to_writer_matches_string() {
+        let vfs: VFS = (0..50)
+            .map(|index| {
+                let relative = format!("dir{}/sub{}/file{index}.nif", index % 5, index % 2);
+                (PathBuf::from(&relative), VfsFile::from(relative))
+            })
+            .collect();
+        let tree = vfs.tree(true);
+
+        for format in [
+            SerializeType::Json,
+            SerializeType::Yaml,
+            SerializeType::Toml,
+            SerializeType::JsonLines,
+        ] {
+            for layout in [
+                SerializeLayout::Standard,
+                SerializeLayout::Pretty,
+                SerializeLayout::Compact,
+            ] {
+                let mut written = Vec::new();
+                VFS::serialize_to_writer(&tree, format, layout, &mut written).unwrap();
+                assert_eq!(
+                    String::from_utf8(written).unwrap(),
+                    VFS::serialize_from_tree_with(&tree, format, layout).unwrap()
+                );
+
+                let mut prioritized = Vec::new();
+                vfs.serialize_with_priority_to_writer(&tree, format, layout, &mut prioritized)
+                    .unwrap();
+                assert_eq!(
+                    String::from_utf8(prioritized).unwrap(),
+                    vfs.serialize_with_priority(&tree, format, layout).unwrap()
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_serialize_vfs_directly() {
+        let vfs: VFS = [
+            ("meshes/a.nif", "/mods/base/Meshes/A.nif"),
+            ("textures/b.dds", "/mods/base/textures/b.dds"),
+        ]
+        .into_iter()
+        .map(|(key, path)| (PathBuf::from(key), VfsFile::from(path)))
+        .collect();
+        let tree = vfs.tree(PathDisplay::Relative);
+
+        assert_eq!(
+            serde_json::to_string(&vfs).unwrap(),
+            VFS::serialize_from_tree(&tree, SerializeType::Json).unwrap()
+        );
+        assert_eq!(
+            serde_yaml::to_string(&vfs).unwrap(),
+            VFS::serialize_from_tree(&tree, SerializeType::Yaml).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_vfs_diff() {
+        let (mut base, mut patched) = (VFS::new(), VFS::new());
+
+        for (vfs, paths) in [
+            (
+                &mut base,
+                &[
+                    ("meshes/a.nif", "/base/meshes/a.nif"),
+                    ("meshes/b.nif", "/base/meshes/b.nif"),
+                    ("textures/old.dds", "/base/textures/old.dds"),
+                ][..],
+            ),
+            (
+                &mut patched,
+                &[
+                    ("meshes/a.nif", "/patch/Meshes/A.nif"),
+                    ("meshes/b.nif", "/base/meshes/b.nif"),
+                    ("textures/new.dds", "/patch/textures/new.dds"),
+                ][..],
+            ),
+        ] {
+            for (relative, full) in paths {
+                vfs.file_map
+                    .insert(normalize_path(relative), VfsFile::from(full));
+            }
+        }
+
+        let diff = base.diff(&patched);
+        assert_eq!(diff.only_in_self, vec![PathBuf::from("textures/old.dds")]);
+        assert_eq!(diff.only_in_other, vec![PathBuf::from("textures/new.dds")]);
+        assert_eq!(
+            diff.changed,
+            vec![(
+                PathBuf::from("meshes/a.nif"),
+                PathBuf::from("/base/meshes/a.nif"),
+                PathBuf::from("/patch/Meshes/A.nif"),
+            )]
+        );
+
+        assert!(base.diff(&base).is_empty());
+    }
+
+    #[test]
+    fn test_overlay() {
+        let entries = |paths: &[(&str, &str)]| -> VFS {
+            paths
+                .iter()
+                .map(|(relative, full)| (PathBuf::from(relative), VfsFile::from(full)))
+                .collect()
+        };
+
+        let base = entries(&[
+            ("meshes/a.nif", "/base/meshes/a.nif"),
+            ("meshes/a.nif", "/base2/meshes/a.nif"),
+            ("meshes/b.nif", "/base/meshes/b.nif"),
+        ]);
+        let higher = entries(&[
+            ("Meshes/A.nif", "/mod/Meshes/A.nif"),
+            ("textures/new.dds", "/mod/textures/new.dds"),
+        ]);
+
+        let merged = base.overlay(&higher);
+
+        assert_eq!(merged.iter().count(), 3);
+        assert_eq!(
+            merged.get_file("meshes/a.nif").unwrap().path(),
+            Path::new("/mod/Meshes/A.nif")
+        );
+        assert_eq!(
+            merged.shadowed_report()[Path::new("meshes/a.nif")].shadowed,
+            vec![
+                PathBuf::from("/base/meshes/a.nif"),
+                PathBuf::from("/base2/meshes/a.nif")
+            ]
+        );
+        assert_eq!(
+            merged.diff(&base).only_in_self,
+            vec![PathBuf::from("textures/new.dds")]
+        );
+        assert!(base.overlay(&VFS::new()).diff(&base).is_empty());
+    }
+
+    #[test]
+    fn test_with_prefix_remapped() {
+        let vfs: VFS = [
+            ("Textures/a.dds", "/base/Textures/a.dds"),
+            ("textures/a.dds", "/patch/textures/a.dds"),
+            ("textures/b.dds", "/base/textures/b.dds"),
+            ("textures/tx/b.dds", "/old/textures/tx/b.dds"),
+            ("texturesx/c.dds", "/base/texturesx/c.dds"),
+        ]
+        .into_iter()
+        .map(|(relative, full)| (PathBuf::from(relative), VfsFile::from(full)))
+        .collect();
+
+        let remapped = vfs.with_prefix_remapped(Path::new("Textures"), Path::new("textures/tx"));
+
+        let mut paths: Vec<&PathBuf> = remapped.iter().map(|(path, _file)| path).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                &PathBuf::from("textures/tx/a.dds"),
+                &PathBuf::from("textures/tx/b.dds"),
+                &PathBuf::from("textures/tx/tx/b.dds"),
+                &PathBuf::from("texturesx/c.dds"),
+            ]
+        );
+
+        // Shadowed copies move along with the file hiding them
+        assert_eq!(
+            remapped.shadowed_report()[Path::new("textures/tx/a.dds")].shadowed,
+            vec![PathBuf::from("/base/Textures/a.dds")]
+        );
+        assert_eq!(
+            remapped.get_file("textures/tx/b.dds").unwrap().path(),
+            Path::new("/base/textures/b.dds")
+        );
+        assert!(vfs.get_file("textures/a.dds").is_some());
+
+        // A moved file wins over the one already at its new path
+        let flattened = vfs.with_prefix_remapped(Path::new("textures/tx"), Path::new("textures"));
+        assert_eq!(
+            flattened.get_file("textures/b.dds").unwrap().path(),
+            Path::new("/old/textures/tx/b.dds")
+        );
+        assert_eq!(
+            flattened.shadowed_report()[Path::new("textures/b.dds")].shadowed,
+            vec![PathBuf::from("/base/textures/b.dds")]
+        );
+    }
+
+    #[test]
+    fn test_retain() {
+        let temp_path = std::env::current_dir().unwrap().join("retain");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let mut vfs = VFS::from_directories([&dir1, &dir2, &dir3], None);
+        assert_eq!(vfs.shadowed().count(), 2);
+
+        // file1.txt is won by dir3, so its shadowed copies in dir1 and dir2 go with it
+        vfs.retain(|_, file| !file.path().starts_with(&dir3));
+        assert!(vfs.get_file("file1.txt").is_none());
+        assert_eq!(vfs.iter().count(), 2);
+        assert_eq!(vfs.shadowed().count(), 1);
+
+        vfs.retain(|path, _| path != Path::new("file2.txt"));
+        assert_eq!(
+            vfs.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+            ["file3.txt"]
+        );
+        assert_eq!(vfs.shadowed().count(), 0);
+
+        vfs.retain(|_, _| false);
+        assert!(vfs.iter().next().is_none());
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_total_bytes() {
+        let temp_path = std::env::current_dir().unwrap().join("total_bytes");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let archive = create_bsa_archive(&dir1, "sized.bsa", &["meshes\\sized.nif"]);
+
+        let mut vfs = VFS::from_directories([&dir1, &dir2, &dir3], Some(vec!["sized.bsa"]));
+
+        // Three loose files, the archive itself, and the archived entry, whose contents are its name
+        let expected = 3 * TEST_STRING.len() as u64
+            + fs::metadata(&archive).unwrap().len()
+            + "meshes\\sized.nif".len() as u64;
+        assert_eq!(vfs.total_bytes(), (expected, 0));
+
+        vfs.file_map.insert(
+            "missing.txt".into(),
+            VfsFile::from(temp_path.join("missing.txt")),
+        );
+        assert_eq!(vfs.total_bytes(), (expected, 1));
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_tree_by_source() {
+        let temp_path = std::env::current_dir().unwrap().join("tree_by_source");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let archive = create_bsa_archive(&dir1, "by_source.bsa", &["meshes\\a.nif"]);
+
+        let vfs = VFS::from_directories([&dir1, &dir2, &dir3], Some(vec!["by_source.bsa"]));
+        let tree = vfs.tree_by_source(|_| true);
+
+        // Each file is listed under the source it wins from
+        assert_eq!(
+            tree.keys().collect::<Vec<_>>(),
+            [&dir1, &archive, &dir2, &dir3]
+        );
+        assert_eq!(tree[&dir3].files.len(), 1);
+        assert_eq!(tree[&dir2].files.len(), 1);
+        assert!(tree[&dir1].files.iter().all(VfsFile::is_loose));
+
+        let meshes = tree[&archive].get_dir(Path::new("meshes")).unwrap();
+        assert_eq!(meshes.files[0].path(), Path::new("meshes/a.nif"));
+        assert!(!meshes.show_sources);
+        assert!(tree[&archive].subdirs.contains_key(&archive.join("meshes")));
+
+        let only_archived = vfs.tree_by_source(VfsFile::is_archive);
+        assert_eq!(only_archived.keys().collect::<Vec<_>>(), [&archive]);
+
+        fs::remove_dir_all(temp_path).unwrap();
+    }
+
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_tree_merges_differently_spelled_dirs() {
+        let temp_path = std::env::current_dir().unwrap().join("merged_dirs");
+        let data_dir = temp_path.join("data");
+        create_files(&data_dir.join("Meshes"), &["A.nif"]);
+        create_bsa_archive(&data_dir, "merged.bsa", &["meshes\\b.nif"]);
+
+        let vfs = VFS::from_directories([&data_dir], Some(vec!["merged.bsa"]));
+        let tree = vfs.tree(PathDisplay::Both);
+        let root = &tree[&PathBuf::from("Data Files")];
+
+        assert_eq!(root.subdirs.len(), 1);
+        let meshes = root.subdirs.values().next().unwrap();
+        let mut archived: Vec<_> = meshes.files.iter().map(VfsFile::is_archive).collect();
+        archived.sort();
+        assert_eq!(archived, [false, true]);
+
+        fs::remove_dir_all(temp_path).unwrap();
+
+        // Loose files spelling a directory differently share one VFS directory, but absolute
+        // paths are real directories on disk, so they're kept apart
+        let vfs: VFS = ["/mods/base/meshes/a.nif", "/mods/base/Meshes/b.nif"]
+            .into_iter()
+            .map(|path| {
+                let relative = PathBuf::from(path.splitn(4, '/').last().unwrap());
+                (relative, VfsFile::from(path))
+            })
+            .collect();
+
+        let tree = vfs.tree(PathDisplay::Relative);
+        let root = &tree[&PathBuf::from("Data Files")];
+        assert_eq!(root.subdirs.len(), 1);
+        assert_eq!(root.subdirs.values().next().unwrap().files.len(), 2);
+
+        for tree in [
+            vfs.tree(PathDisplay::Absolute),
+            vfs.par_tree(PathDisplay::Absolute),
+        ] {
+            let base = tree[&PathBuf::from("/")]
+                .get_dir(Path::new("mods/base"))
+                .unwrap();
+
+            assert_eq!(
+                base.subdirs.keys().collect::<Vec<_>>(),
+                [
+                    Path::new("/mods/base/Meshes"),
+                    Path::new("/mods/base/meshes")
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_par_tree_matches_tree() {
+        let vfs: VFS = [
+            "/mods/base/meshes/a.nif",
+            "/mods/base/Meshes/b.nif",
+            "/mods/base/textures/tx/c.dds",
+            "/mods/patch/Textures/TX/d.dds",
+            "/mods/patch/e.esp",
+            "/other/sound/f.wav",
+        ]
+        .into_iter()
+        .map(|path| {
+            let relative = PathBuf::from(path.splitn(4, '/').last().unwrap());
+            (relative, VfsFile::from(path))
+        })
+        .collect();
+
+        for display in [
+            PathDisplay::Relative,
+            PathDisplay::Absolute,
+            PathDisplay::Both,
+        ] {
+            assert_eq!(
+                format!("{:?}", vfs.par_tree(display)),
+                format!("{:?}", vfs.tree(display))
+            );
+        }
+    }
+
+    #[test]
+    fn test_walk_matches_tree() {
+        fn flatten(path: &Path, node: &DirectoryNode, out: &mut Vec<(PathBuf, Vec<String>)>) {
+            let files = node
+                .files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            out.push((path.to_path_buf(), files));
+
+            for (subdir, subnode) in &node.subdirs {
+                flatten(subdir, subnode, out);
+            }
+        }
+
+        let mut vfs = VFS::new();
+
+        for path in [
+            "/mods/base/meshes/xbase_anim.nif",
+            "/mods/base/meshes/a/b/c.nif",
+            "/mods/base/textures/tx_bone_01.dds",
+            "/mods/patch/Textures/Terrain/tx_ash_01.dds",
+            "/mods/patch/Textures/Terrain/tx_ash_00.dds",
+            "/mods/patch/readme.txt",
+            "/mods/base/Meshes/xbase_skin.nif",
+        ] {
+            let relative = normalize_path(path.splitn(4, '/').last().unwrap());
+            vfs.file_map.insert(relative, VfsFile::from(path));
+        }
+
+        for relative in [false, true] {
+            let mut expected = Vec::new();
+            for (root, node) in vfs.tree(relative) {
+                flatten(&root, &node, &mut expected);
+            }
+
+            let (mut walked, mut open) = (Vec::new(), Vec::new());
+            let mut depth = 0i32;
+            vfs.walk(relative, &mut |event| match event {
+                WalkEvent::EnterDir(dir) => {
+                    depth += 1;
+                    open.push(walked.len());
+                    walked.push((dir.to_path_buf(), Vec::new()));
+                }
+                WalkEvent::File(file) => {
+                    let name = file.file_name().unwrap().to_string_lossy().to_string();
+                    walked[*open.last().unwrap()].1.push(name);
+                }
+                WalkEvent::LeaveDir(dir) => {
+                    depth -= 1;
+                    assert_eq!(walked[open.pop().unwrap()].0, dir);
+                }
+            });
+
+            assert_eq!(depth, 0);
+            assert_eq!(walked, expected);
+        }
+    }
+
+    #[test]
+    fn test_tree_under_prefix() {
+        let mut vfs = VFS::new();
+
+        for path in [
+            "/mods/base/Music/MS/Explore/mx_explore_1.mp3",
+            "/mods/base/Music/MS/Battle/mx_battle_1.mp3",
+            "/mods/base/Music/msx/other.mp3",
+            "/mods/base/sound/fx/foot.wav",
+        ] {
+            let relative = normalize_path(path.splitn(4, '/').last().unwrap());
+            vfs.file_map.insert(relative, VfsFile::from(path));
+        }
+
+        let tree = vfs.tree_under(true, "music/ms/");
+        let root = &tree[&PathBuf::from("Data Files")];
+        let ms = &root.subdirs[&PathBuf::from("music")].subdirs[&PathBuf::from("music/ms")];
+
+        assert_eq!(root.subdirs[&PathBuf::from("music")].subdirs.len(), 1);
+        assert_eq!(ms.subdirs.len(), 2);
+    }
+
+    #[test]
+    fn test_max_depth_matches_tree() {
+        let vfs: VFS = [
+            "morrowind.esm",
+            "music/ms/explore/mx_explore_1.mp3",
+            "sound/fx/foot.wav",
+        ]
+        .into_iter()
+        .map(|path| (PathBuf::from(path), VfsFile::from(path)))
+        .collect();
+
+        let tree = vfs.tree(PathDisplay::Relative);
+        let root = tree.values().next().unwrap();
+
+        assert_eq!(vfs.max_depth(), 3);
+        assert_eq!(root.depth(), vfs.max_depth());
+        assert_eq!(VFS::new().max_depth(), 0);
+    }
+
+    #[test]
+    fn test_paths_with_indexed_matches_paths_with() {
+        let mut vfs: VFS = [
+            "music/ms/explore/mx_explore_1.mp3",
+            "music/ms/battle/mx_battle_1.mp3",
+            "music/msx/other.mp3",
+            "music/ms",
+            "sound/fx/foot.wav",
+        ]
+        .into_iter()
+        .map(|path| (PathBuf::from(path), VfsFile::from(path)))
+        .collect();
+
+        fn linear<'a>(vfs: &'a VFS, prefix: &str) -> Vec<&'a Path> {
+            let mut paths: Vec<&Path> = vfs.paths_with(prefix).map(|(path, _)| path).collect();
+            paths.sort();
+            paths
+        }
+        fn indexed<'a>(vfs: &'a VFS, prefix: &str) -> Vec<&'a Path> {
+            vfs.paths_with_indexed(prefix)
+                .map(|(path, _)| path)
+                .collect()
+        }
+
+        vfs.build_prefix_index();
+        for prefix in ["", "Music/MS/", "music/ms", "music/m", "sound", "textures"] {
+            assert_eq!(indexed(&vfs, prefix), linear(&vfs, prefix), "{prefix}");
+        }
+
+        // Changing the VFS has to drop the stale index
+        vfs.insert_loose(PathBuf::from("music/ms/new.mp3"));
+        assert_eq!(indexed(&vfs, "music/ms").len(), 4);
+        vfs.retain(|path, _| !path.starts_with("music/ms/battle"));
+        assert_eq!(indexed(&vfs, "music/ms"), linear(&vfs, "music/ms"));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let dir = std::env::current_dir().unwrap().join("max_depth");
+        create_files(&dir, &["top.esp"]);
+        create_files(&dir.join("meshes"), &["a.nif"]);
+        create_files(&dir.join("meshes/deep"), &["b.nif"]);
+
+        let scan = |max_depth| {
+            let options = ScanOptions {
+                max_depth,
+                ..Default::default()
+            };
+            let mut paths: Vec<PathBuf> = VFS::from_directories_with([&dir], None, &options)
+                .iter()
+                .map(|(path, _file)| path.clone())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(scan(Some(1)), [PathBuf::from("top.esp")]);
+        assert_eq!(
+            scan(Some(2)),
+            [PathBuf::from("meshes/a.nif"), PathBuf::from("top.esp")]
+        );
+        assert_eq!(scan(None).len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_priority_overrides_pin_directories() {
+        let dir = std::env::current_dir().unwrap().join("priority_overrides");
+        let dirs: Vec<PathBuf> = ["low", "middle", "high"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for data_dir in &dirs {
+            create_files(&data_dir.join("meshes"), &["a.nif"]);
+        }
+        create_files(&dirs[0], &["only_low.esp"]);
+
+        let winner = |overrides: &[&PathBuf]| {
+            let options = ScanOptions {
+                priority_overrides: overrides.iter().map(|dir| dir.to_path_buf()).collect(),
+                ..Default::default()
+            };
+            let vfs = VFS::from_directories_with(&dirs, None, &options);
+            let file = vfs.get_file("meshes/a.nif").unwrap();
+            dirs.iter()
+                .position(|data_dir| file.path().starts_with(data_dir))
         };
 
-        output
+        assert_eq!(winner(&[]), Some(2));
+        assert_eq!(winner(&[&dirs[0]]), Some(0));
+        assert_eq!(winner(&[&dirs[0], &dirs[1]]), Some(1));
+        assert_eq!(winner(&[&dir.join("unlisted")]), Some(2));
+
+        let options = ScanOptions {
+            priority_overrides: vec![dirs[0].clone()],
+            ..Default::default()
+        };
+        let vfs = VFS::from_directories_with(&dirs, None, &options);
+        let load_order: Vec<PathBuf> = vfs.sources().dirs.into_iter().map(|(dir, _)| dir).collect();
+        assert_eq!(
+            load_order,
+            [dirs[1].clone(), dirs[2].clone(), dirs[0].clone()]
+        );
+        assert!(vfs.get_file("only_low.esp").is_some());
+
+        fs::remove_dir_all(dir).unwrap();
     }
 
-    /// Serializes the result of `tree` or `display_filtered` functions to JSON, YAML, or TOML
-    #[cfg(feature = "serialize")]
-    pub fn serialize_from_tree(tree: &DisplayTree, write_type: SerializeType) -> Result<String> {
-        fn to_io_error<E: std::fmt::Display>(err: E) -> Error {
-            Error::new(ErrorKind::InvalidData, err.to_string())
-        }
+    #[test]
+    fn test_ignored_files_stay_out() {
+        let dir = std::env::current_dir().unwrap().join("ignore_rules");
+        create_files(&dir, &["top.esp", "notes.bak"]);
+        create_files(&dir.join(".git"), &["HEAD"]);
+        create_files(&dir.join("Meshes"), &["a.nif", "keep.bak"]);
 
-        let serialized_content = match write_type {
-            SerializeType::Json => serde_json::to_string(&tree).map_err(to_io_error)?,
-            SerializeType::Yaml => serde_yaml::to_string(&tree).map_err(to_io_error)?,
-            SerializeType::Toml => toml::to_string_pretty(&tree).map_err(to_io_error)?,
+        let options = ScanOptions {
+            ignore: IgnoreRules::parse("*.bak\n.git/\n!meshes/keep.bak").unwrap(),
+            ..Default::default()
         };
+        let vfs = VFS::from_directories_with([&dir], None, &options);
+
+        let mut paths: Vec<&PathBuf> = vfs.iter().map(|(path, _file)| path).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                &PathBuf::from("meshes/a.nif"),
+                &PathBuf::from("meshes/keep.bak"),
+                &PathBuf::from("top.esp")
+            ]
+        );
 
-        Ok(serialized_content)
+        fs::remove_dir_all(dir).unwrap();
     }
-}
 
-fn to_eof_err<E: std::fmt::Display>(error: E) -> std::io::Error {
-    Error::new(ErrorKind::UnexpectedEof, error.to_string())
-}
+    #[test]
+    #[cfg(unix)]
+    fn test_skip_unreadable_files() {
+        use std::os::unix::fs::PermissionsExt;
 
-fn write_files_io<W: Write>(w: &mut W, node: &DirectoryNode, dir: &PathBuf) -> std::io::Result<()> {
-    if !node.files.is_empty() {
-        write!(w, "{}", VFS::dir_str(dir.to_string_lossy())).map_err(to_eof_err)?;
+        let dir = std::env::current_dir().unwrap().join("unreadable");
+        fs::create_dir_all(&dir).unwrap();
+        let locked = dir.join("locked.txt");
+        fs::write(dir.join("open.txt"), "").unwrap();
+        fs::write(&locked, "").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
 
-        for file in &node.files {
-            write!(
-                w,
-                "{}",
-                VFS::file_str(file.path().file_name().unwrap().to_string_lossy())
-            )
-            .map_err(to_eof_err)?;
+        // Privileged users can open the file regardless, so only expect it gone when it is not
+        let readable = fs::File::open(&locked).is_ok();
+        let options = ScanOptions {
+            skip_unreadable: true,
+            ..Default::default()
+        };
+        let permissive = VFS::from_directories([&dir], None);
+        let checked = VFS::from_directories_with([&dir], None, &options);
+
+        assert!(permissive.get_file("locked.txt").is_some());
+        assert!(permissive.unreadable().is_empty());
+        assert!(checked.get_file("open.txt").is_some());
+        assert_eq!(checked.get_file("locked.txt").is_some(), readable);
+
+        // The file left out is handed back rather than logged
+        let unreadable: Vec<&Path> = checked
+            .unreadable()
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect();
+        if readable {
+            assert!(unreadable.is_empty());
+        } else {
+            assert_eq!(unreadable, [locked.as_path()]);
+            assert_eq!(
+                checked.unreadable()[0].error.kind(),
+                ErrorKind::PermissionDenied
+            );
         }
-    };
 
-    Ok(())
-}
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
 
-fn print_files_fmt(
-    f: &mut std::fmt::Formatter<'_>,
-    node: &DirectoryNode,
-    dir: &PathBuf,
-) -> std::fmt::Result {
-    if !node.files.is_empty() {
-        write!(f, "{}", VFS::dir_str(dir.to_string_lossy()))?;
+    #[test]
+    #[cfg(unix)]
+    fn test_broken_links() {
+        let temp_path = std::env::current_dir().unwrap().join("broken_links");
+        let meshes = temp_path.join("meshes");
+        fs::create_dir_all(&meshes).unwrap();
+        fs::write(meshes.join("real.nif"), "").unwrap();
 
-        for file in &node.files {
-            write!(
-                f,
-                "{}",
-                VFS::file_str(file.path().file_name().unwrap().to_string_lossy())
-            )?;
+        for (name, target) in [("good.nif", "real.nif"), ("moved.nif", "../../gone/a.nif")] {
+            let _ = fs::remove_file(meshes.join(name));
+            std::os::unix::fs::symlink(target, meshes.join(name)).unwrap();
         }
-    };
 
-    Ok(())
-}
+        assert_eq!(
+            VFS::broken_links(&temp_path, &ScanOptions::default()),
+            [BrokenLink {
+                link: meshes.join("moved.nif"),
+                target: PathBuf::from("../../gone/a.nif"),
+            }]
+        );
 
-fn write_node_io<W: Write>(
-    w: &mut W,
-    node: &DirectoryNode,
-    parent_dir: &PathBuf,
-) -> std::io::Result<()> {
-    write_files_io(w, &node, parent_dir)?;
+        let vfs = VFS::from_directories([&temp_path], None);
+        assert!(vfs.get_file("meshes/good.nif").is_some());
+        assert!(vfs.get_file("meshes/moved.nif").is_none());
 
-    for (subdir_name, subdir_node) in &node.subdirs {
-        write_node_io(w, subdir_node, &subdir_name)?;
+        let shallow = ScanOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert!(VFS::broken_links(&temp_path, &shallow).is_empty());
+
+        fs::remove_dir_all(temp_path).unwrap();
     }
 
-    Ok(())
-}
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve_symlinked_sources() {
+        let temp_path = std::env::current_dir().unwrap().join("symlinked_sources");
+        let real = temp_path.join("real");
+        let linked = temp_path.join("linked");
+        fs::create_dir_all(real.join("meshes")).unwrap();
+        fs::write(real.join("meshes/a.nif"), "").unwrap();
+        let _ = fs::remove_file(&linked);
+        std::os::unix::fs::symlink(&real, &linked).unwrap();
 
-fn print_node_fmt(
-    f: &mut std::fmt::Formatter<'_>,
-    node: &DirectoryNode,
-    parent_dir: &PathBuf,
-) -> std::fmt::Result {
-    print_files_fmt(f, &node, parent_dir)?;
+        let dirs = [real.clone(), linked.join("..").join("linked")];
+        let unresolved = VFS::from_directories(&dirs, None);
+        assert_eq!(unresolved.shadowed().count(), 1);
 
-    for (subdir_name, subdir_node) in &node.subdirs {
-        print_node_fmt(f, subdir_node, &subdir_name)?;
+        let options = ScanOptions {
+            resolve_symlinks: true,
+            ..Default::default()
+        };
+        let resolved = VFS::from_directories_with(&dirs, None, &options);
+        let canonical = fs::canonicalize(&real).unwrap();
+
+        assert_eq!(resolved.shadowed().count(), 0);
+        assert_eq!(
+            resolved.get_file("meshes/a.nif").unwrap().path(),
+            canonical.join("meshes/a.nif")
+        );
+        assert!(
+            resolved
+                .sources()
+                .dirs
+                .iter()
+                .all(|(dir, _)| *dir == canonical)
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_cancelled_construction() {
+        let temp_path = std::env::current_dir()
+            .unwrap()
+            .join("cancelled_construction");
+        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        let dirs = [dir1, dir2, dir3];
 
-fn write_tree_io<W: Write>(tree: &DisplayTree, f: &mut W) -> std::io::Result<()> {
-    for (root_subdir, files) in tree {
-        write_files_io(f, files, root_subdir)?;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let vfs = VFS::from_directories_cancellable(&dirs, None, &ScanOptions::default(), &cancel)
+            .unwrap();
+        assert_eq!(vfs.iter().count(), 3);
 
-        for (subdir_name, sub_node) in &files.subdirs {
-            write_node_io(f, &sub_node, &subdir_name)?;
-        }
+        cancel.store(true, Ordering::Relaxed);
+        let error =
+            VFS::from_directories_cancellable(&dirs, None, &ScanOptions::default(), &cancel)
+                .err()
+                .unwrap();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+
+        fs::remove_dir_all(temp_path).unwrap();
     }
-    Ok(())
-}
 
-fn print_tree_fmt(tree: &DisplayTree, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    for (root_subdir, files) in tree {
-        print_files_fmt(f, files, root_subdir)?;
+    #[test]
+    fn test_cancelled_partway_through_walk() {
+        const FILE_COUNT: usize = 1_000;
 
-        for (subdir_name, sub_node) in &files.subdirs {
-            print_node_fmt(f, &sub_node, &subdir_name)?;
+        let temp_path = std::env::current_dir().unwrap().join("cancelled_walk");
+        fs::create_dir_all(&temp_path).unwrap();
+        for index in 0..FILE_COUNT {
+            fs::write(temp_path.join(format!("file{index}.txt")), "").unwrap();
         }
-    }
-    Ok(())
-}
 
-impl std::fmt::Display for VFS {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        print_tree_fmt(&self.tree(true), f)
-    }
-}
+        // Cancelling as soon as the first file comes out of the walk, the way a cancel button
+        // pressed mid-scan would, stops it well short of the whole directory
+        let cancel = AtomicBool::new(false);
+        let found =
+            VFS::directory_contents_to_file_map(&temp_path, &ScanOptions::default(), &cancel)
+                .map(|entry| {
+                    cancel.store(true, Ordering::Relaxed);
+                    entry
+                })
+                .count();
 
-impl Index<&str> for VFS {
-    type Output = VfsFile;
+        assert!(found > 0 && found < FILE_COUNT, "walk found {found} files");
 
-    fn index(&self, index: &str) -> &Self::Output {
-        let normalized_path = normalize_path(index);
+        // The partial walk is then reported as a cancellation rather than used
+        let error = check_cancelled(&cancel).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
 
-        // If the path exists in the file_map, return the file, otherwise return a default value
-        self.file_map.get(&normalized_path).unwrap_or_else(|| {
-            static DEFAULT_FILE: std::sync::OnceLock<VfsFile> = std::sync::OnceLock::new();
-            DEFAULT_FILE.get_or_init(|| VfsFile::default())
-        })
+        fs::remove_dir_all(temp_path).unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use ba2::tes3::{Archive, ArchiveKey, File};
-    use std::fs;
-    use std::path::PathBuf;
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_canonical_paths_spell_entries_alike() {
+        let archive_dir = std::env::current_dir().unwrap().join("canonical_entries");
+        fs::create_dir_all(&archive_dir).unwrap();
+        let tes3 = create_bsa_archive(&archive_dir, "tes3.bsa", &["Meshes\\a.nif"]);
+        create_tes4_archive(&archive_dir, "tes4.bsa", Tes4Version::TES5);
 
-    const TEST_DATA: &[&str] = &[
-        "file1.txt",
-        "file2.txt",
-        "file3.txt",
-        "file4.txt",
-        "file5.txt",
-        "file6.txt",
-    ];
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["tes3.bsa", "tes4.bsa"]));
+        let canonical = |path: &str| vfs.get_file(path).unwrap().canonical_path();
 
-    const TEST_STRING: &str = "Act IV, Scene III, continued
+        assert_eq!(
+            canonical("meshes/a.nif"),
+            format!("{}::meshes/a.nif", tes3.display())
+        );
+        assert!(
+            canonical("textures/compressed.txt").ends_with("tes4.bsa::textures/compressed.txt")
+        );
 
-Lifts-Her-Tail
-Certainly not, kind sir! I am here but to clean your chambers.
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
 
-Crantius Colto
-Is that all you have come here for, little one? My chambers?
+    #[cfg(feature = "bsa")]
+    #[test]
+    fn test_tes4_non_ascii_uppercase_names_open() {
+        let archive_dir = std::env::current_dir().unwrap().join("tes4_non_ascii");
+        fs::create_dir_all(&archive_dir).unwrap();
 
-Lifts-Her-Tail
-I have no idea what it is you imply, master. I am but a poor Argonian maid.
+        let directory: Tes4Directory = [(
+            Tes4DirKey::from("Évé.dds".as_bytes()),
+            Tes4File::from_decompressed(TEST_STRING.as_bytes()),
+        )]
+        .into_iter()
+        .collect();
+        let archive: Tes4Archive = [(Tes4ArchiveKey::from(b"textures"), directory)]
+            .into_iter()
+            .collect();
+        let options = Tes4ArchiveOptions::builder()
+            .version(Tes4Version::TES4)
+            .flags(Tes4Flags::DIRECTORY_STRINGS | Tes4Flags::FILE_STRINGS)
+            .build();
+        let mut dst = fs::File::create(archive_dir.join("accents.bsa")).unwrap();
+        archive.write(&mut dst, &options).unwrap();
+        drop(dst);
 
-Crantius Colto
-So you are, my dumpling. And a good one at that. Such strong legs and shapely tail.
+        let vfs = VFS::from_directories([&archive_dir], Some(vec!["accents.bsa"]));
+        let file = vfs.get_file("textures/évé.dds").unwrap();
 
-Lifts-Her-Tail
-You embarrass me, sir!
+        let mut contents = String::new();
+        file.open().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, TEST_STRING);
 
-Crantius Colto
-Fear not. You are safe here with me.
+        fs::remove_dir_all(&archive_dir).unwrap();
+    }
 
-Lifts-Her-Tail
-I must finish my cleaning, sir. The mistress will have my head if I do not!
+    #[test]
+    fn test_unicode_names_shadow_across_case() {
+        let temp_path = std::env::current_dir().unwrap().join("unicode_names");
+        let (base, patch) = (temp_path.join("base"), temp_path.join("patch"));
 
-Crantius Colto
-Cleaning, eh? I have something for you. Here, polish my spear.
+        for (dir, names) in [
+            (&base, ["Élévation.dds", "Straße.nif", "Дерево.nif"]),
+            (&patch, ["élévation.DDS", "STRAßE.nif", "дерево.NIF"]),
+        ] {
+            fs::create_dir_all(dir.join("Textures")).unwrap();
+            for name in names {
+                fs::write(dir.join("Textures").join(name), "").unwrap();
+            }
+        }
 
-Lifts-Her-Tail
-But it is huge! It could take me all night!
+        let vfs = VFS::from_directories([&base, &patch], None);
+        assert_eq!(vfs.iter().count(), 3);
+        assert_eq!(vfs.shadowed().count(), 3);
 
-Crantius Colto
-Plenty of time, my sweet. Plenty of time.
+        for (query, winner) in [
+            ("TEXTURES\\ÉLÉVATION.dds", "élévation.DDS"),
+            ("textures/straße.nif", "STRAßE.nif"),
+            ("Textures/ДЕРЕВО.nif", "дерево.NIF"),
+        ] {
+            assert_eq!(
+                vfs.get_file(query).unwrap().path(),
+                patch.join("Textures").join(winner),
+                "{query} didn't resolve to the patch's copy"
+            );
+        }
 
-END OF ACT IV, SCENE III";
+        fs::remove_dir_all(temp_path).unwrap();
+    }
 
-    fn create_files(dir: &PathBuf, files: &[&str]) {
-        fs::create_dir_all(dir).unwrap();
-        for file in files {
-            let file_path = dir.join(file);
-            fs::write(file_path, TEST_STRING).unwrap();
+    #[test]
+    fn test_case_conflicts() {
+        let temp_path = std::env::current_dir().unwrap().join("case_conflicts");
+        let (base, mid, patch) = (
+            temp_path.join("base"),
+            temp_path.join("mid"),
+            temp_path.join("patch"),
+        );
+
+        for (dir, files) in [
+            (&base, ["Meshes/XBase.nif", "textures/a.dds"]),
+            (&mid, ["meshes/xbase.nif", "textures/a.dds"]),
+            (&patch, ["Meshes/XBase.nif", "textures/b.dds"]),
+        ] {
+            for file in files {
+                let path = dir.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
         }
+
+        let vfs = VFS::from_directories([&base, &mid, &patch], None);
+        let conflicts = vfs.case_conflicts();
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[Path::new("meshes/xbase.nif")],
+            [
+                PathBuf::from("Meshes/XBase.nif"),
+                PathBuf::from("meshes/xbase.nif")
+            ]
+        );
+
+        fs::remove_dir_all(temp_path).unwrap();
     }
 
     #[test]
-    fn test_vfs_from_directories() {
-        let temp_path = std::env::current_dir().unwrap();
-        let archive_dir = temp_path.join("archives");
-
-        fs::create_dir_all(&archive_dir).unwrap();
+    fn test_insert_in_memory() {
+        let mut vfs = VFS::new();
+        vfs.insert_loose(PathBuf::from("Meshes/A.nif"));
+        vfs.insert_loose(PathBuf::from("textures/b.dds"));
+        vfs.insert(
+            PathBuf::from("meshes\\a.nif"),
+            VfsFile::from("/generated/meshes/a.nif"),
+        );
 
-        // Create directories and files
-        let (dir1, dir2, dir3) = create_test_dirs_and_files(&temp_path);
+        assert_eq!(
+            vfs.get_file("MESHES/A.NIF").unwrap().path(),
+            Path::new("/generated/meshes/a.nif")
+        );
+        assert_eq!(
+            vfs.get_file("textures/b.dds").unwrap().path(),
+            Path::new("textures/b.dds")
+        );
+        assert_eq!(vfs.shadowed().count(), 1);
 
-        // Create BSA archives
-        let bsa1 = create_bsa_archive(&archive_dir, "archive1.bsa", &TEST_DATA[0..6]);
-        let bsa2 = create_bsa_archive(&archive_dir, "archive2.bsa", &TEST_DATA[0..5]);
-        let bsa3 = create_bsa_archive(&archive_dir, "archive3.bsa", &TEST_DATA[0..4]);
+        let tree = vfs.tree(PathDisplay::Relative);
+        let root = &tree[&PathBuf::from("Data Files")];
+        assert_eq!(
+            root.subdirs.keys().collect::<Vec<_>>(),
+            [Path::new("meshes"), Path::new("textures")]
+        );
+    }
 
-        // Construct VFS
-        let search_dirs = vec![
-            archive_dir.clone(),
-            dir1.clone(),
-            dir2.clone(),
-            dir3.clone(),
-        ];
-        let archive_list = vec!["archive1.bsa", "archive2.bsa", "archive3.bsa"];
+    #[test]
+    fn test_get_file_exact() {
+        let vfs: VFS = [
+            ("Meshes/XBase_Anim.nif", "/mods/base/Meshes/XBase_Anim.nif"),
+            ("textures/tx_bone.dds", "textures\\tx_bone.dds"),
+        ]
+        .into_iter()
+        .map(|(relative, path)| (PathBuf::from(relative), VfsFile::from(path)))
+        .collect();
 
-        let vfs = VFS::from_directories(search_dirs.clone(), Some(archive_list));
+        assert!(vfs.get_file_exact("Meshes/XBase_Anim.nif").is_some());
+        assert!(vfs.get_file_exact("Meshes\\XBase_Anim.nif").is_some());
+        assert!(vfs.get_file("meshes/xbase_anim.nif").is_some());
+        assert!(vfs.get_file_exact("meshes/xbase_anim.nif").is_none());
+        assert!(vfs.get_file_exact("textures/tx_bone.dds").is_some());
+        assert!(vfs.get_file_exact("Textures/tx_bone.dds").is_none());
+        assert!(vfs.get_file_exact("meshes/missing.nif").is_none());
+    }
 
-        // Verify file locations
-        verify_file_locations(&vfs, &bsa1, &bsa2, &bsa3, &dir1, &dir2, &dir3);
+    #[test]
+    fn test_get_texture_falls_back_by_extension() {
+        let vfs: VFS = [
+            ("textures/foo.dds", "/mods/base/textures/foo.dds"),
+            ("textures/bar.bmp", "/mods/base/textures/bar.bmp"),
+            ("textures/both.tga", "/mods/base/textures/both.tga"),
+            ("textures/both.dds", "/mods/base/textures/both.dds"),
+        ]
+        .into_iter()
+        .map(|(relative, path)| (PathBuf::from(relative), VfsFile::from(path)))
+        .collect();
 
-        // Clean up test files and directories
-        clean_up_test_files(&search_dirs);
+        assert!(vfs.get_file("textures/foo.tga").is_none());
+        assert_eq!(
+            vfs.get_texture("Textures\\Foo.tga").unwrap().path(),
+            Path::new("/mods/base/textures/foo.dds")
+        );
+        assert_eq!(
+            vfs.get_texture("textures/bar.tga").unwrap().path(),
+            Path::new("/mods/base/textures/bar.bmp")
+        );
+        assert_eq!(
+            vfs.get_texture("textures/both.tga").unwrap().path(),
+            Path::new("/mods/base/textures/both.tga")
+        );
+        assert!(vfs.get_texture("textures/missing.tga").is_none());
     }
 
-    fn create_test_dirs_and_files(temp_path: &Path) -> (PathBuf, PathBuf, PathBuf) {
-        let dir1 = temp_path.join("dir1");
-        let dir2 = temp_path.join("dir2");
-        let dir3 = temp_path.join("dir3");
+    #[test]
+    fn test_collect_from_entries() {
+        let vfs: VFS = [
+            "/mods/base/Meshes/XBase_Anim.nif",
+            "/mods/base/Textures/tx_bone_01.dds",
+            "/mods/patch/meshes/xbase_anim.nif",
+        ]
+        .into_iter()
+        .map(|path| {
+            let relative = PathBuf::from(path.splitn(4, '/').last().unwrap());
+            (relative, VfsFile::from(path))
+        })
+        .collect();
 
-        create_files(&dir1, &TEST_DATA[0..3]); // file1.txt, file2.txt, file3.txt
-        create_files(&dir2, &TEST_DATA[0..2]); // file1.txt, file2.txt
-        create_files(&dir3, &TEST_DATA[0..1]); // file1.txt
-        create_files(&temp_path.to_path_buf(), &TEST_DATA[..]);
+        assert_eq!(vfs.iter().count(), 2);
+        assert_eq!(
+            vfs.get_file("meshes/xbase_anim.nif").unwrap().path(),
+            Path::new("/mods/patch/meshes/xbase_anim.nif")
+        );
+        assert!(vfs.iter().all(|(key, _)| *key == normalize_path(key)));
 
-        (dir1, dir2, dir3)
+        let tree = vfs.tree_filtered(true, |file| file.path().starts_with("/mods/patch"));
+        let root = &tree[&PathBuf::from("Data Files")];
+        assert_eq!(root.file_count(), 1);
+        assert!(root.subdirs.contains_key(&PathBuf::from("meshes")));
     }
 
-    fn create_bsa_archive(archive_dir: &Path, archive_name: &str, data: &[&str]) -> PathBuf {
-        let archive_path = archive_dir.join(archive_name);
-        let archive: Archive = data
-            .iter()
-            .map(|s| {
-                let key: ArchiveKey = s.to_string().into();
-                let file: File = File::from(s.as_bytes());
-                (key, file)
-            })
-            .collect();
-        let mut dst = fs::File::create(&archive_path).unwrap();
-        archive.write(&mut dst).unwrap();
-        archive_path
+    #[test]
+    fn test_display_tree_styles() {
+        let mut vfs = VFS::new();
+        vfs.file_map.insert(
+            PathBuf::from("meshes/xbase_anim.nif"),
+            VfsFile::from("/mods/base/meshes/xbase_anim.nif"),
+        );
+
+        let tree = vfs.tree(true);
+
+        assert_eq!(
+            VFS::display_tree(&tree, &TreeStyle::ascii()),
+            "+-- meshes/\n|   +-- xbase_anim.nif\n"
+        );
+
+        let custom = TreeStyle {
+            dir_prefix: "> ".into(),
+            file_prefix: "  - ".into(),
+        };
+        assert_eq!(
+            VFS::display_tree(&tree, &custom),
+            "> meshes/\n  - xbase_anim.nif\n"
+        );
     }
 
+    #[cfg(feature = "bsa")]
     fn verify_file_locations(
         vfs: &VFS,
         bsa1: &PathBuf,
@@ -618,8 +4364,25 @@ END OF ACT IV, SCENE III";
                 .path(),
             dir3.join("file1.txt")
         );
+
+        assert_eq!(
+            vfs.file_map
+                .get(&PathBuf::from("file2.txt"))
+                .unwrap()
+                .source_dir(),
+            Some(dir2.as_path())
+        );
+
+        assert_eq!(
+            vfs.file_map
+                .get(&PathBuf::from("file6.txt"))
+                .unwrap()
+                .source_dir(),
+            None
+        );
     }
 
+    #[cfg(feature = "bsa")]
     fn clean_up_test_files(search_dirs: &[PathBuf]) {
         search_dirs
             .iter()
@@ -4,49 +4,81 @@ use ba2::{
     tes3::ArchiveKey as Tes3Key,
     tes4::{
         ArchiveKey as Tes4ArchiveKey, DirectoryKey as Tes4DirKey, File as Tes4File,
-        FileCompressionOptions as Tes4CompressionOptions,
+        FileCompressionOptions as Tes4CompressionOptions, Version as Tes4Version,
     },
 };
 
 #[cfg(feature = "bsa")]
 use std::{
-    io::{Cursor, Error, ErrorKind},
-    sync::Arc,
+    borrow::Cow,
+    io::{Cursor, Error, ErrorKind, SeekFrom},
+    sync::OnceLock,
 };
 
 use std::{
     fs::File as StdFile,
-    io::{self, Read},
+    io::{self, BufReader, Read, Seek},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 #[cfg(feature = "bsa")]
-use crate::archives::{StoredArchive, TypedArchive};
+use crate::archives::{EntryCache, StoredArchive, TypedArchive};
+
+/// Lowercase extensions OpenMW loads as content files, including its own `omw*` types
+pub const PLUGIN_EXTENSIONS: &[&str] = &["esm", "esp", "omwaddon", "omwgame", "omwscripts"];
+
+/// A reader which can also seek, as returned by `VfsFile::open` for every kind of file
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
 
 #[cfg(feature = "bsa")]
 pub struct Fo4FileReader<'a> {
-    chunks: std::vec::IntoIter<&'a [u8]>,
-    current_chunk: Option<&'a [u8]>,
-    position: usize,
+    chunks: Vec<Cow<'a, [u8]>>,
+    /// Where each chunk starts within the whole file
+    offsets: Vec<u64>,
+    len: u64,
+    position: u64,
 }
 
 #[cfg(feature = "bsa")]
 /// Since FO4 Archives are stored in chunks, implement a custom reader for them
 /// This allows to seamlessly call read on them as we do for other all other file types
 impl<'a> Fo4FileReader<'a> {
-    pub fn new(file: &'a Fo4File) -> Self {
-        let mut chunks = file
+    /// Compressed chunks are decompressed up front, while the rest are read in place.
+    pub fn new(file: &'a Fo4File) -> io::Result<Self> {
+        let chunks = file
             .iter()
-            .map(|chunk| chunk.as_bytes())
-            .collect::<Vec<_>>()
-            .into_iter();
-        let current_chunk = chunks.next();
+            .map(|chunk| {
+                if !chunk.is_compressed() {
+                    return Ok(Cow::Borrowed(chunk.as_bytes()));
+                }
 
-        Self {
+                let mut data = Vec::new();
+                chunk
+                    .decompress_into(&mut data, &Default::default())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(Cow::Owned(data))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut len = 0;
+        let offsets = chunks
+            .iter()
+            .map(|chunk| {
+                let offset = len;
+                len += chunk.len() as u64;
+                offset
+            })
+            .collect();
+
+        Ok(Self {
             chunks,
-            current_chunk,
+            offsets,
+            len,
             position: 0,
-        }
+        })
     }
 }
 
@@ -55,27 +87,21 @@ impl Read for Fo4FileReader<'_> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut total_read = 0;
 
-        while total_read < buf.len() {
-            let chunk = match self.current_chunk {
-                Some(chunk) if self.position < chunk.len() => chunk,
-                _ => {
-                    // Move to the next chunk
-                    self.current_chunk = self.chunks.next();
-                    self.position = 0;
-                    match self.current_chunk {
-                        Some(chunk) => chunk,
-                        None => return Ok(total_read), // No more data
-                    }
-                }
-            };
+        while total_read < buf.len() && self.position < self.len {
+            // Empty chunks share their offset with the next one, so this always lands on the
+            // chunk actually holding `position`
+            let index = self
+                .offsets
+                .partition_point(|&offset| offset <= self.position)
+                - 1;
+            let chunk = &self.chunks[index];
+            let start = (self.position - self.offsets[index]) as usize;
 
-            let remaining = chunk.len() - self.position;
-            let to_read = (buf.len() - total_read).min(remaining);
+            let to_read = (buf.len() - total_read).min(chunk.len() - start);
 
-            buf[total_read..total_read + to_read]
-                .copy_from_slice(&chunk[self.position..self.position + to_read]);
+            buf[total_read..total_read + to_read].copy_from_slice(&chunk[start..start + to_read]);
 
-            self.position += to_read;
+            self.position += to_read as u64;
             total_read += to_read;
         }
 
@@ -83,6 +109,30 @@ impl Read for Fo4FileReader<'_> {
     }
 }
 
+#[cfg(feature = "bsa")]
+impl Seek for Fo4FileReader<'_> {
+    /// Seeks the same way as a `Cursor`: seeking past the end is allowed and reads nothing, while
+    /// seeking before the start is an error
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+
+        match target {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
 #[cfg(feature = "bsa")]
 pub struct TES4FileReader {
     data: Cursor<Vec<u8>>, // Cursor over the file's data (decompressed or raw)
@@ -93,9 +143,13 @@ impl TES4FileReader {
     /// Creates a new `TES4FileReader` for a TES4 file.
     ///
     /// If the file is compressed, it will be decompressed before being wrapped in the reader.
-    pub fn new(file: &Tes4File) -> io::Result<Self> {
+    /// The archive `version` selects the codec: zlib for v103/v104 (Oblivion through Skyrim LE)
+    /// and LZ4 for v105 (Skyrim SE).
+    pub fn new(file: &Tes4File, version: Tes4Version) -> io::Result<Self> {
         let data = if file.is_compressed() {
-            file.decompress(&Tes4CompressionOptions::default())
+            let options = Tes4CompressionOptions::builder().version(version).build();
+
+            file.decompress(&options)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
                 .as_bytes()
                 .to_vec()
@@ -117,14 +171,39 @@ impl Read for TES4FileReader {
 }
 
 #[cfg(feature = "bsa")]
-#[derive(Debug)]
+impl Seek for TES4FileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+#[cfg(feature = "bsa")]
+#[derive(Clone)]
 pub struct ArchiveReference {
     path: PathBuf,
     parent_archive: Arc<StoredArchive>,
+    /// Decompressed contents, kept by `VfsFile::open_cached`
+    contents: OnceLock<Arc<[u8]>>,
+}
+
+#[cfg(feature = "bsa")]
+/// Shows how many bytes `open_cached` kept instead of printing them
+impl std::fmt::Debug for ArchiveReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArchiveReference")
+            .field("path", &self.path)
+            .field("parent_archive", &self.parent_archive)
+            .field(
+                "cached_bytes",
+                &self.contents.get().map(|contents| contents.len()),
+            )
+            .finish()
+    }
 }
 
 #[cfg(feature = "bsa")]
 impl ArchiveReference {
+    /// Directory and file keys of this entry inside of a TES4 archive
     pub fn tes4_keys(path: &PathBuf) -> io::Result<(Tes4ArchiveKey<'_>, Tes4DirKey<'_>)> {
         let dir_key: Tes4ArchiveKey = path
             .parent()
@@ -145,13 +224,95 @@ impl ArchiveReference {
 
         Ok((dir_key, file_key))
     }
+
+    /// Error for an entry which its archive doesn't hold
+    fn missing(&self) -> Error {
+        Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "{} is missing from {}",
+                self.path.display(),
+                self.parent_archive.path().display()
+            ),
+        )
+    }
+
+    /// Finds this entry in its archive and appends its contents to `buf`, decompressing them
+    /// if they're stored compressed
+    fn read_into(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let path_string = self.path.to_string_lossy().to_string();
+
+        match self.parent_archive.handle() {
+            TypedArchive::Tes3(archive) => {
+                let key: Tes3Key = path_string.into();
+                let file = archive.get(&key).ok_or_else(|| self.missing())?;
+                buf.extend_from_slice(file.as_bytes());
+            }
+
+            TypedArchive::Tes4(archive) => {
+                let (dir_key, file_key) = Self::tes4_keys(&self.path)?;
+                let file: &Tes4File = archive
+                    .get(&dir_key)
+                    .and_then(|dir| dir.get(&file_key))
+                    .ok_or_else(|| self.missing())?;
+
+                if file.is_compressed() {
+                    let options = Tes4CompressionOptions::builder()
+                        .version(self.parent_archive.tes4_version().unwrap_or_default())
+                        .build();
+                    file.decompress_into(buf, &options)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                } else {
+                    buf.extend_from_slice(file.as_bytes());
+                }
+            }
+
+            TypedArchive::Fo4(archive) => {
+                let key: Fo4ArchiveKey = path_string.into();
+                let file: &Fo4File = archive.get(&key).ok_or_else(|| self.missing())?;
+
+                for chunk in file.iter() {
+                    if chunk.is_compressed() {
+                        chunk
+                            .decompress_into(buf, &Default::default())
+                            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                    } else {
+                        buf.extend_from_slice(chunk.as_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Contents of this entry from `cache`, reading and storing them on a miss
+    fn cached(&self, cache: &EntryCache) -> io::Result<Arc<[u8]>> {
+        let archive_path = self.parent_archive.path();
+
+        if let Some(data) = cache.get(archive_path, &self.path) {
+            return Ok(data);
+        }
+
+        let mut data = Vec::new();
+        self.read_into(&mut data)?;
+
+        let data: Arc<[u8]> = data.into();
+        cache.insert(archive_path, &self.path, Arc::clone(&data));
+
+        Ok(data)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum FileType {
     #[cfg(feature = "bsa")]
     Archive(ArchiveReference),
-    Loose(PathBuf),
+    /// A file on disk, along with the data directory it was found in (if known)
+    Loose {
+        path: PathBuf,
+        root: Option<Arc<Path>>,
+    },
 }
 
 /// Represents a file within the Virtual File System (VFS).
@@ -161,9 +322,17 @@ pub enum FileType {
 /// path to ensure correct file operations. Paths should only be normalized when **retrieved**,
 /// not when constructing the file, as normalization may affect OS file resolution.
 ///
+/// A file has two paths: `path` is where its source keeps it, ie its location on disk or its
+/// entry name inside of an archive, while `relative_path` is its normalized path inside of the
+/// VFS.
+///
 /// Files in the VFS should be **unique** and stored in a HashMap inside the `VFS` struct.
 /// They are typically wrapped in `Arc<VfsFile>` for safe concurrent access.
-#[derive(Debug)]
+///
+/// A `VfsFile` doesn't borrow from the `VFS` it came from. Loose files own their path, and
+/// archived ones share their parent archive through an `Arc`, so a clone is cheap and can still
+/// be opened after the `VFS` is dropped, eg to hand a file across an API boundary.
+#[derive(Clone, Debug)]
 pub struct VfsFile {
     file: FileType,
 }
@@ -171,7 +340,10 @@ pub struct VfsFile {
 impl Default for VfsFile {
     fn default() -> Self {
         Self {
-            file: FileType::Loose(PathBuf::default()),
+            file: FileType::Loose {
+                path: PathBuf::default(),
+                root: None,
+            },
         }
     }
 }
@@ -193,7 +365,7 @@ impl VfsFile {
     ///
     /// ```
     /// use std::path::PathBuf;
-    /// use dw_vfs_lib::VfsFile;
+    /// use vfstool_lib::VfsFile;
     ///
     /// let path = "C:\\Morrowind\\Data Files\\Morrowind.esm";
     ///
@@ -202,7 +374,23 @@ impl VfsFile {
     /// ```
     pub fn from<P: AsRef<Path>>(path: P) -> Self {
         VfsFile {
-            file: FileType::Loose(path.as_ref().to_path_buf()),
+            file: FileType::Loose {
+                path: path.as_ref().to_path_buf(),
+                root: None,
+            },
+        }
+    }
+
+    /// Creates a loose `VfsFile` which remembers the data directory it was found in.
+    ///
+    /// `root` is shared between every file from the same data directory, and is what
+    /// `source_dir` returns.
+    pub fn from_source_dir<P: AsRef<Path>>(path: P, root: Arc<Path>) -> Self {
+        VfsFile {
+            file: FileType::Loose {
+                path: path.as_ref().to_path_buf(),
+                root: Some(root),
+            },
         }
     }
 
@@ -213,13 +401,14 @@ impl VfsFile {
             file: FileType::Archive(ArchiveReference {
                 path,
                 parent_archive,
+                contents: OnceLock::new(),
             }),
         }
     }
 
     pub fn is_loose(&self) -> bool {
         match self.file {
-            FileType::Loose(_) => true,
+            FileType::Loose { .. } => true,
             #[cfg(feature = "bsa")]
             FileType::Archive(_) => false,
         }
@@ -227,7 +416,7 @@ impl VfsFile {
 
     pub fn is_archive(&self) -> bool {
         match self.file {
-            FileType::Loose(_) => false,
+            FileType::Loose { .. } => false,
             #[cfg(feature = "bsa")]
             FileType::Archive(_) => true,
         }
@@ -235,7 +424,7 @@ impl VfsFile {
 
     pub fn parent_archive_path(&self) -> Option<String> {
         match &self.file {
-            FileType::Loose(_) => None,
+            FileType::Loose { .. } => None,
             #[cfg(feature = "bsa")]
             FileType::Archive(archive_ref) => {
                 let path_str = archive_ref
@@ -254,7 +443,7 @@ impl VfsFile {
 
     pub fn parent_archive_name(&self) -> Option<String> {
         match &self.file {
-            FileType::Loose(_) => None,
+            FileType::Loose { .. } => None,
 
             #[cfg(feature = "bsa")]
             FileType::Archive(archive_ref) => {
@@ -270,10 +459,22 @@ impl VfsFile {
         }
     }
 
+    /// Returns the data directory a loose file was loaded from, eg
+    /// `C:\\Games\\Morrowind\\Data Files` for `C:\\Games\\Morrowind\\Data Files\\Meshes\\XBase_Anim.nif`.
+    ///
+    /// Archived files, and loose files which weren't created from a data directory, return `None`.
+    pub fn source_dir(&self) -> Option<&Path> {
+        match &self.file {
+            FileType::Loose { root, .. } => root.as_deref(),
+            #[cfg(feature = "bsa")]
+            FileType::Archive(_) => None,
+        }
+    }
+
     #[cfg(feature = "bsa")]
     pub fn parent_archive_handle(&self) -> Result<Arc<StoredArchive>, Error> {
         match &self.file {
-            FileType::Loose(_) => Err(Error::new(
+            FileType::Loose { .. } => Err(Error::new(
                 ErrorKind::InvalidData,
                 "Loose files may not return an archive reference!",
             )),
@@ -281,18 +482,20 @@ impl VfsFile {
         }
     }
 
-    /// Opens the file and returns a standard `File` handle.
+    /// Opens the file for reading. Every reader can also seek, whether the file is loose or
+    /// archived. Loose files are buffered, so many small reads, eg parsing a header, don't each
+    /// cost a syscall.
     ///
     /// # Returns
     ///
-    /// * `Ok(StdFile)` - If the file exists and can be opened.
+    /// * `Ok(Box<dyn ReadSeek>)` - If the file exists and can be opened.
     /// * `Err(io::Error)` - If the file does not exist or cannot be opened.
     ///
     /// # Examples
     ///
     /// ```
     /// use std::path::PathBuf;
-    /// use dw_vfs_lib::VfsFile;
+    /// use vfstool_lib::VfsFile;
     ///
     /// let path = "C:\\Some\\Very\\Long\\Path";
     ///
@@ -301,49 +504,221 @@ impl VfsFile {
     ///
     /// assert!(result.is_err());
     /// ```
-    pub fn open(&self) -> io::Result<Box<dyn Read + '_>> {
+    pub fn open(&self) -> io::Result<Box<dyn ReadSeek + '_>> {
         match &self.file {
-            FileType::Loose(path) => {
-                let file = StdFile::open(&path)?;
-                Ok(Box::new(file))
+            FileType::Loose { path, .. } => {
+                let file = StdFile::open(crate::long_path(path))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => {
+                let parent = &archive_ref.parent_archive;
+
+                // TES3 archives are never compressed, so their entries are read in place
+                if let TypedArchive::Tes3(archive) = parent.handle() {
+                    let key: Tes3Key = archive_ref.path.to_string_lossy().to_string().into();
+                    let file = archive.get(&key).ok_or_else(|| archive_ref.missing())?;
+                    return Ok(Box::new(Cursor::new(file.as_bytes())));
+                }
+
+                match parent.cache() {
+                    Some(cache) => Ok(Box::new(Cursor::new(archive_ref.cached(cache)?))),
+                    None => {
+                        let mut data = Vec::new();
+                        archive_ref.read_into(&mut data)?;
+                        Ok(Box::new(Cursor::new(data)))
+                    }
+                }
             }
+        }
+    }
+
+    /// Returns the size of the file's contents in bytes, as `open` would read them.
+    ///
+    /// Loose files are stat'd. Archived files report their decompressed size from the archive's
+    /// index, so nothing is decompressed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let file = VfsFile::from("C:\\Some\\Very\\Long\\Path");
+    /// assert!(file.size().is_err());
+    /// ```
+    pub fn size(&self) -> io::Result<u64> {
+        match &self.file {
+            FileType::Loose { path, .. } => Ok(std::fs::metadata(crate::long_path(path))?.len()),
 
             #[cfg(feature = "bsa")]
             FileType::Archive(archive_ref) => {
-                let parent = archive_ref.parent_archive.handle();
+                let not_found = || archive_ref.missing();
                 let path_string = archive_ref.path.to_string_lossy().to_string();
 
-                let data = match parent {
+                let size = match archive_ref.parent_archive.handle() {
                     TypedArchive::Tes3(archive) => {
                         let key: Tes3Key = path_string.into();
-                        archive.get(&key).and_then(|data| Some(data.as_bytes()))
+                        archive.get(&key).ok_or_else(not_found)?.len()
                     }
 
                     TypedArchive::Tes4(archive) => {
                         let (dir_key, file_key) = ArchiveReference::tes4_keys(&archive_ref.path)?;
-
-                        let file: &Tes4File = archive
+                        let file = archive
                             .get(&dir_key)
                             .and_then(|dir| dir.get(&file_key))
-                            .unwrap();
+                            .ok_or_else(not_found)?;
 
-                        return Ok(Box::new(TES4FileReader::new(file)?));
+                        file.decompressed_len().unwrap_or(file.len())
                     }
 
                     TypedArchive::Fo4(archive) => {
                         let key: Fo4ArchiveKey = path_string.into();
-                        let file: &Fo4File = archive.get(&key).unwrap();
-                        return Ok(Box::new(Fo4FileReader::new(file)));
+                        archive
+                            .get(&key)
+                            .ok_or_else(not_found)?
+                            .iter()
+                            .map(|chunk| chunk.decompressed_len().unwrap_or(chunk.len()))
+                            .sum()
                     }
                 };
 
-                let cursor = Cursor::new(data.unwrap());
+                Ok(size as u64)
+            }
+        }
+    }
+
+    /// Whether the file is stored compressed inside its archive. Loose files and TES3 entries
+    /// never are, and FO4 entries count as compressed if any of their chunks is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let file = VfsFile::from("C:\\Some\\Very\\Long\\Path");
+    /// assert!(!file.is_compressed());
+    /// ```
+    pub fn is_compressed(&self) -> bool {
+        match &self.file {
+            FileType::Loose { .. } => false,
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => {
+                let path_string = archive_ref.path.to_string_lossy().to_string();
+
+                match archive_ref.parent_archive.handle() {
+                    TypedArchive::Tes3(_) => false,
+
+                    TypedArchive::Tes4(archive) => ArchiveReference::tes4_keys(&archive_ref.path)
+                        .ok()
+                        .and_then(|(dir_key, file_key)| {
+                            archive.get(&dir_key).and_then(|dir| dir.get(&file_key))
+                        })
+                        .is_some_and(|file| file.is_compressed()),
 
-                Ok(Box::new(cursor))
+                    TypedArchive::Fo4(archive) => {
+                        let key: Fo4ArchiveKey = path_string.into();
+                        archive
+                            .get(&key)
+                            .is_some_and(|file| file.iter().any(|chunk| chunk.is_compressed()))
+                    }
+                }
             }
         }
     }
 
+    /// Like `open`, but the reader owns everything it reads from, so it can be moved into another
+    /// thread or outlive this `VfsFile`.
+    ///
+    /// Loose files are read from disk through a buffer. Archived files are fully read, and
+    /// decompressed if necessary, into memory up front.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let file = VfsFile::from("C:\\Some\\Very\\Long\\Path");
+    /// assert!(file.open_owned().is_err());
+    /// ```
+    pub fn open_owned(&self) -> io::Result<Box<dyn ReadSeek + Send + 'static>> {
+        match &self.file {
+            FileType::Loose { path, .. } => Ok(Box::new(BufReader::new(StdFile::open(
+                crate::long_path(path),
+            )?))),
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(_) => {
+                let mut data = Vec::new();
+                self.open()?.read_to_end(&mut data)?;
+                Ok(Box::new(Cursor::new(data)))
+            }
+        }
+    }
+
+    /// Like `open`, but an archived file keeps its decompressed contents after the first call,
+    /// so opening the same `VfsFile` again only wraps them in a `Cursor`. Clones made after
+    /// that share the contents too. TES3 entries are already read in place and loose files are
+    /// streamed from disk, so both are opened as usual.
+    ///
+    /// This trades memory for speed, eg for a preview which reopens the same file, so `open`
+    /// doesn't do it. Nothing is attached to the archive, so other files keep opening the same
+    /// way they did before.
+    pub fn open_cached(&self) -> io::Result<Box<dyn ReadSeek + '_>> {
+        match &self.file {
+            FileType::Loose { .. } => self.open(),
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => {
+                if matches!(archive_ref.parent_archive.handle(), TypedArchive::Tes3(_)) {
+                    return self.open();
+                }
+
+                let contents = match archive_ref.contents.get() {
+                    Some(contents) => contents,
+                    None => {
+                        let mut data = Vec::new();
+                        archive_ref.read_into(&mut data)?;
+                        archive_ref.contents.get_or_init(|| data.into())
+                    }
+                };
+
+                Ok(Box::new(Cursor::new(Arc::clone(contents))))
+            }
+        }
+    }
+
+    /// Reads the whole file into `buf`, replacing whatever it held, so a batch of reads, eg for
+    /// hashing, can reuse one allocation instead of making a new one per file. Archived entries
+    /// are decompressed straight into `buf`.
+    ///
+    /// With an `EntryCache` attached, TES4 and FO4 entries are copied out of it, or stored in it
+    /// after being read, the same as `open` does. `open` stays the way to stream a file.
+    pub fn open_into(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.clear();
+
+        match &self.file {
+            FileType::Loose { path, .. } => {
+                StdFile::open(crate::long_path(path))?.read_to_end(buf)?;
+            }
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => {
+                let parent = &archive_ref.parent_archive;
+
+                match parent.cache() {
+                    Some(cache) if !matches!(parent.handle(), TypedArchive::Tes3(_)) => {
+                        buf.extend_from_slice(&archive_ref.cached(cache)?)
+                    }
+                    _ => archive_ref.read_into(buf)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Retrieves the file name (i.e., the last component of the path).
     ///
     /// # Returns
@@ -356,18 +731,20 @@ impl VfsFile {
     /// # Examples
     ///
     /// ```
-    /// use std::path::PathBuf;
-    /// use dw_vfs_lib::VfsFile;
+    /// use std::{ffi::OsStr, path::PathBuf};
+    /// use vfstool_lib::VfsFile;
     ///
-    /// let morrowind_esm = PathBuf::from("C:").join("Morrowind").join("Data
-    /// Files").join("Morrowind.esm");
+    /// let morrowind_esm = PathBuf::from("C:")
+    ///     .join("Morrowind")
+    ///     .join("Data Files")
+    ///     .join("Morrowind.esm");
     ///
     /// let file = VfsFile::from(morrowind_esm);
-    /// assert_eq!(file.file_name(), Some("Morrowind.esm"));
+    /// assert_eq!(file.file_name(), Some(OsStr::new("Morrowind.esm")));
     /// ```
     pub fn file_name(&self) -> Option<&std::ffi::OsStr> {
         match &self.file {
-            FileType::Loose(path) => path.file_name(),
+            FileType::Loose { path, .. } => path.file_name(),
             // This doesn't actually retrieve the filename, it just normalizes it
             // Now it does retrieve the filename, but wtf
             #[cfg(feature = "bsa")]
@@ -389,18 +766,20 @@ impl VfsFile {
     /// # Examples
     ///
     /// ```
-    /// use std::path::PathBuf;
-    /// use dw_vfs_lib::VfsFile;
+    /// use std::{ffi::OsStr, path::PathBuf};
+    /// use vfstool_lib::VfsFile;
     ///
-    /// let morrowind_esm = PathBuf::from("C:").join("Morrowind").join("Data
-    /// Files").join("Morrowind.esm");
+    /// let morrowind_esm = PathBuf::from("C:")
+    ///     .join("Morrowind")
+    ///     .join("Data Files")
+    ///     .join("Morrowind.esm");
     ///
     /// let file = VfsFile::from(morrowind_esm);
-    /// assert_eq!(file.file_stem(), Some("Morrowind"));
+    /// assert_eq!(file.file_stem(), Some(OsStr::new("Morrowind")));
     /// ```
     pub fn file_stem(&self) -> Option<&std::ffi::OsStr> {
         match &self.file {
-            FileType::Loose(path) => path.file_stem(),
+            FileType::Loose { path, .. } => path.file_stem(),
             // This doesn't actually retrieve the filename, it just normalizes it
             // Now it does retrieve the filename, but wtf
             #[cfg(feature = "bsa")]
@@ -408,7 +787,51 @@ impl VfsFile {
         }
     }
 
-    /// Returns the original (non-normalized) path of the file.
+    /// Returns the extension of the file, as its source spells it.
+    ///
+    /// Files without one, including names ending in a bare `.`, return `None`, so every feature
+    /// working by extension treats them alike.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::ffi::OsStr;
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// assert_eq!(VfsFile::from("Meshes/XBase_Anim.NIF").extension(), Some(OsStr::new("NIF")));
+    /// assert_eq!(VfsFile::from("Data Files/README").extension(), None);
+    /// assert_eq!(VfsFile::from("Data Files/notes.").extension(), None);
+    /// ```
+    pub fn extension(&self) -> Option<&std::ffi::OsStr> {
+        self.path()
+            .extension()
+            .filter(|extension| !extension.is_empty())
+    }
+
+    /// Whether this file is a content file, judged case-insensitively by its extension.
+    /// See `PLUGIN_EXTENSIONS` for the accepted types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// assert!(VfsFile::from("Data Files/Morrowind.ESM").is_plugin());
+    /// assert!(VfsFile::from("Data Files/ui.omwscripts").is_plugin());
+    /// assert!(!VfsFile::from("Data Files/Meshes/XBase_Anim.nif").is_plugin());
+    /// ```
+    pub fn is_plugin(&self) -> bool {
+        self.extension().is_some_and(|extension| {
+            let extension = extension.to_string_lossy().to_ascii_lowercase();
+            PLUGIN_EXTENSIONS.contains(&extension.as_str())
+        })
+    }
+
+    /// Returns the original (non-normalized) path of the file, as its source knows it.
+    ///
+    /// This is **not** the file's path inside of the VFS. Loose files return their path on disk,
+    /// data directory included, while archived files return their entry's path inside of the
+    /// archive. Use `relative_path` for the VFS path of any file.
     ///
     /// # Returns
     ///
@@ -417,7 +840,7 @@ impl VfsFile {
     /// # Examples
     ///
     /// ```
-    /// use dw_vfs_lib::VfsFile;
+    /// use vfstool_lib::VfsFile;
     /// use std::path::PathBuf;
     ///
     /// let path = "C:\\Morrowind\\Data Files\\Morrowind.esm";
@@ -427,12 +850,127 @@ impl VfsFile {
     /// ```
     pub fn path(&self) -> &Path {
         match &self.file {
-            FileType::Loose(path) => path.as_path(),
+            FileType::Loose { path, .. } => path.as_path(),
 
             #[cfg(feature = "bsa")]
             FileType::Archive(archive_ref) => &archive_ref.path,
         }
     }
+
+    /// Returns where the file was loaded from: its archive for archived files, or its data
+    /// directory for loose ones. Loose files without a `source_dir` are their own source.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{path::Path, sync::Arc};
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let root: Arc<Path> = Arc::from(Path::new("/games/Morrowind/Data Files"));
+    /// let file =
+    ///     VfsFile::from_source_dir("/games/Morrowind/Data Files/Meshes/XBase_Anim.nif", root);
+    ///
+    /// assert_eq!(file.source(), Path::new("/games/Morrowind/Data Files"));
+    /// assert_eq!(VfsFile::from("a.nif").source(), Path::new("a.nif"));
+    /// ```
+    pub fn source(&self) -> PathBuf {
+        match &self.file {
+            FileType::Loose { path, root } => root.as_deref().unwrap_or(path).to_path_buf(),
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => archive_ref.parent_archive.path().to_path_buf(),
+        }
+    }
+
+    /// Returns the file's normalized path inside of the VFS, eg `meshes/xbase_anim.nif`, whether
+    /// it's loose or archived. This is the key the VFS stores it under.
+    ///
+    /// Unlike `path`, this never includes a data directory or archive. Loose files without a
+    /// `source_dir` have no known data directory, so their whole path is normalized instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{path::Path, sync::Arc};
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let root: Arc<Path> = Arc::from(Path::new("/games/Morrowind/Data Files"));
+    /// let file =
+    ///     VfsFile::from_source_dir("/games/Morrowind/Data Files/Meshes/XBase_Anim.nif", root);
+    ///
+    /// assert_eq!(file.relative_path(), Path::new("meshes/xbase_anim.nif"));
+    /// assert_eq!(
+    ///     file.path(),
+    ///     Path::new("/games/Morrowind/Data Files/Meshes/XBase_Anim.nif")
+    /// );
+    /// ```
+    pub fn relative_path(&self) -> PathBuf {
+        match &self.file {
+            FileType::Loose { path, root } => {
+                let relative = root
+                    .as_deref()
+                    .and_then(|root| path.strip_prefix(root).ok())
+                    .unwrap_or(path);
+
+                crate::normalize_path(relative)
+            }
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => crate::normalize_path(&archive_ref.path),
+        }
+    }
+
+    /// Like `relative_path`, but spelled exactly as its source names it, only with `/` as the
+    /// separator. `None` for TES4 archive entries, which are only ever stored normalized.
+    pub fn spelled_path(&self) -> Option<PathBuf> {
+        let spelled = match &self.file {
+            FileType::Loose { path, root } => root
+                .as_deref()
+                .and_then(|root| path.strip_prefix(root).ok())
+                .unwrap_or(path),
+
+            #[cfg(feature = "bsa")]
+            FileType::Archive(archive_ref) => {
+                if archive_ref.parent_archive.format() == crate::ArchiveFormat::Tes4 {
+                    return None;
+                }
+                &archive_ref.path
+            }
+        };
+
+        Some(spelled.to_string_lossy().replace('\\', "/").into())
+    }
+
+    /// Returns a single display path identifying where this file really lives.
+    ///
+    /// Loose files resolve to their absolute on-disk path, without Windows' `\\?\` prefix, falling
+    /// back to the stored path if the file can no longer be found. Archived files are written as
+    /// `archive_path::entry`, where the entry always uses `/` as its separator, whatever the
+    /// archive's format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vfstool_lib::VfsFile;
+    ///
+    /// let path = "/definitely/not/a/real/dir/Morrowind.esm";
+    ///
+    /// let file = VfsFile::from(path);
+    /// assert_eq!(file.canonical_path(), path);
+    /// ```
+    pub fn canonical_path(&self) -> String {
+        match self.parent_archive_path() {
+            Some(archive_path) => format!(
+                "{archive_path}::{}",
+                self.path().to_string_lossy().replace('\\', "/")
+            ),
+            None => {
+                let path = std::fs::canonicalize(self.path())
+                    .unwrap_or_else(|_| self.path().to_path_buf());
+                crate::without_verbatim_prefix(&path.to_string_lossy()).into_owned()
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -535,6 +1073,41 @@ END OF ACT IV, SCENE III";
         let _ = remove_file(vfs_file.path());
     }
 
+    #[test]
+    fn owned_reader_moves_across_threads() {
+        let test_path = "owned_reader_test_file.txt";
+        std::fs::write(test_path, TEST_DATA).unwrap();
+
+        let file = VfsFile::from(test_path);
+        assert_eq!(file.size().unwrap(), TEST_DATA.len() as u64);
+
+        let reader = file.open_owned().unwrap();
+        let contents = thread::spawn(move || {
+            let mut reader = reader;
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).unwrap();
+            contents
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(contents, TEST_DATA);
+        let _ = remove_file(test_path);
+    }
+
+    #[test]
+    fn canonical_path_resolves_loose_files() {
+        let test_path = "canonical_test_file.txt";
+        let _ = File::create(test_path);
+
+        let vfs_file = VfsFile::from(test_path);
+        let canonical = PathBuf::from(vfs_file.canonical_path());
+
+        assert!(canonical.is_absolute());
+        assert!(canonical.ends_with(test_path));
+        let _ = remove_file(test_path);
+    }
+
     #[test]
     fn open_non_existing_file() {
         let bad_path = "non_existent_file";
@@ -684,4 +1257,45 @@ END OF ACT IV, SCENE III";
 
         let _ = remove_file(PathBuf::from(path_str));
     }
+
+    #[test]
+    #[cfg(feature = "bsa")]
+    fn fo4_reader_seeks_across_chunks() {
+        use super::Fo4FileReader;
+        use ba2::{
+            CompressableFrom,
+            fo4::{Chunk, File as Fo4File},
+        };
+        use std::io::{Seek, SeekFrom};
+
+        let data = TEST_DATA.as_bytes();
+        let file: Fo4File = [&data[..10], &[][..], &data[10..25], &data[25..]]
+            .into_iter()
+            .map(Chunk::from_decompressed)
+            .collect();
+        let mut reader = Fo4FileReader::new(&file).unwrap();
+
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, TEST_DATA);
+
+        let mut buf = [0; 8];
+        for (seek, expected) in [
+            (SeekFrom::Start(6), 6),
+            (SeekFrom::Current(-3), 11),
+            (SeekFrom::End(-8), data.len() - 8),
+        ] {
+            assert_eq!(reader.seek(seek).unwrap(), expected as u64);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, data[expected..expected + 8]);
+        }
+
+        assert!(
+            reader
+                .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+                .is_err()
+        );
+        reader.seek(SeekFrom::End(4)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
 }